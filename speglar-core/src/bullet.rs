@@ -0,0 +1,265 @@
+use crate::{
+    map::{bullet_is_passable, BaseMap},
+    n_from_bit, n_from_parts, Direction, Hitbox, Player, PlayerTag, RectExt, VectType, N,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, PartialOrd, Ord, Default)]
+pub enum BulletTag {
+    #[default]
+    NoPlayer,
+    Player1,
+    Player2,
+    Player3,
+    Player4,
+}
+
+impl BulletTag {
+    pub const fn matches_player(self, player: PlayerTag) -> bool {
+        matches!(
+            (self, player),
+            (BulletTag::Player1, PlayerTag::P1)
+                | (BulletTag::Player2, PlayerTag::P2)
+                | (BulletTag::Player3, PlayerTag::P3)
+                | (BulletTag::Player4, PlayerTag::P4)
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, PartialOrd, Ord, Default)]
+pub enum BulletType {
+    #[default]
+    Bullet,
+    Reflector,
+}
+
+impl BulletTag {
+    pub fn hits_player(self, ptag: PlayerTag) -> bool {
+        use BulletTag::*;
+        use PlayerTag::*;
+        !matches!(
+            (self, ptag),
+            (Player1, P1) | (Player2, P2) | (Player3, P3) | (Player4, P4)
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Bullet {
+    pub pos: VectType,
+    pub dir: Direction,
+    pub tag: BulletTag,
+    pub kind: BulletType,
+    pub charge: u8,
+    pub should_die: bool,
+}
+
+impl Hitbox for Bullet {
+    fn pos(&self) -> VectType {
+        self.pos
+    }
+    fn size(&self) -> VectType {
+        VectType::new(Self::SIZE, Self::SIZE)
+    }
+}
+
+impl Bullet {
+    pub const SIZE: N = n_from_parts(4, 0);
+    // Translates to 1.875 pixels per second, based on:
+    // * The 5th-from-last bit corresponds to 1/32 pixels per frame
+    // * The GBA is 60 FPS
+    // * 60 frame/s * 1/32 px/frame = 1.875 px/s
+    pub const BULLET_SPEED: N = n_from_bit(5);
+    // Translates to 0.9375 pixels per second, based on:
+    // * The 5th-from-last bit corresponds to 1/64 pixels per frame
+    // * The GBA is 60 FPS
+    // * 60 frame/s * 1/64 px/frame = 0.9375 px/s
+    pub const SHIELD_SPEED: N = n_from_bit(6);
+    pub fn new(pos: VectType, dir: Direction, tag: BulletTag, kind: BulletType) -> Self {
+        Self {
+            pos,
+            dir,
+            tag,
+            kind,
+            charge: 0,
+            should_die: false,
+        }
+    }
+    pub fn with_charge(mut self, charge: u8) -> Self {
+        self.charge = charge;
+        self
+    }
+    fn speed(&self) -> N {
+        match self.kind {
+            // Each level of charge adds another BULLET_SPEED.
+            BulletType::Bullet => Self::BULLET_SPEED * (self.charge as i32 + 1),
+            BulletType::Reflector => Self::SHIELD_SPEED,
+        }
+    }
+    pub fn update(
+        &mut self,
+        map: &BaseMap,
+        players: &[Player],
+        other_bullets_1: &[Bullet],
+        other_bullets_2: &[Bullet],
+    ) -> Option<BulletEvent> {
+        self.pos += self.dir.scaled_vec(self.speed());
+        for player in players {
+            if !player.alive || !self.collides(player) {
+                continue;
+            }
+            self.should_die = true;
+            if self.kind != BulletType::Bullet {
+                return None;
+            }
+            if self.tag.matches_player(player.tag) {
+                return Some(BulletEvent::PushChargePlayer(player.tag, self.dir));
+            } else {
+                return Some(BulletEvent::KillPlayer(player.tag));
+            }
+        }
+        for other in other_bullets_1.iter().chain(other_bullets_2.iter()) {
+            if !self.collides(other) {
+                continue;
+            }
+            // Charged bullets plow through weaker ones of the same kind.
+            if self.kind == other.kind && self.charge <= other.charge {
+                self.should_die = true;
+                return None;
+            }
+            if other.kind == BulletType::Reflector {
+                self.dir = self.dir.flipped();
+            }
+        }
+        let tile = map.tile_at_pixel(self.hitbox().center());
+        if bullet_is_passable(tile, self.dir) {
+            self.dir = tile.deflected(self.dir);
+        } else {
+            self.should_die = true;
+        }
+        None
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum BulletEvent {
+    KillPlayer(PlayerTag),
+    PushChargePlayer(PlayerTag, Direction),
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::{any, prop_assert, prop_assert_eq, proptest, Strategy};
+
+    use super::*;
+    use crate::map::{tests::EMPTY_MAP, MapTile};
+    use crate::utils::tests::direction;
+    use Direction::*;
+
+    /// Fires a bullet over the middle of a `tile` and returns it after a frame.
+    fn bullet_over(tile: MapTile, dir: Direction) -> Bullet {
+        let map = EMPTY_MAP.with(5, 5, tile);
+        let offset = VectType::new(n_from_parts(2, 0), n_from_parts(2, 0));
+        let pos = map.index_to_pixel((5, 5)) + offset;
+        let mut bullet = Bullet::new(pos, dir, BulletTag::Player1, BulletType::Bullet);
+        assert_eq!(bullet.update(&map, &[], &[], &[]), None);
+        bullet
+    }
+
+    #[test]
+    fn test_mirror_reflections() {
+        let cases = [
+            (
+                MapTile::UpMirror,
+                [(Right, Up), (Up, Right), (Down, Left), (Left, Down)],
+            ),
+            (
+                MapTile::DownMirror,
+                [(Right, Down), (Down, Right), (Up, Left), (Left, Up)],
+            ),
+            (
+                MapTile::HorizMirror,
+                [(Up, Down), (Down, Up), (Up, Down), (Down, Up)],
+            ),
+            (
+                MapTile::VertMirror,
+                [(Left, Right), (Right, Left), (Left, Right), (Right, Left)],
+            ),
+            (
+                MapTile::Empty,
+                [(Left, Left), (Right, Right), (Up, Up), (Down, Down)],
+            ),
+        ];
+        for (tile, dirs) in cases {
+            for (from, to) in dirs {
+                let bullet = bullet_over(tile, from);
+                assert!(
+                    !bullet.should_die,
+                    "{:?} killed a bullet going {:?}",
+                    tile, from
+                );
+                assert_eq!(bullet.dir, to, "{:?} going {:?}", tile, from);
+            }
+        }
+    }
+
+    #[test]
+    fn test_blocked_bullets_die() {
+        let cases = [
+            (MapTile::Block, [Up, Down, Left, Right]),
+            (MapTile::HorizMirror, [Left, Right, Left, Right]),
+            (MapTile::VertMirror, [Up, Down, Up, Down]),
+            (MapTile::HorizPipe, [Up, Down, Up, Down]),
+            (MapTile::VertPipe, [Left, Right, Left, Right]),
+        ];
+        for (tile, dirs) in cases {
+            for dir in dirs {
+                assert!(
+                    bullet_over(tile, dir).should_die,
+                    "{:?} going {:?}",
+                    tile,
+                    dir
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_charged_bullets_win() {
+        let pos = EMPTY_MAP.index_to_pixel((5, 5));
+        let weak = Bullet::new(pos, Left, BulletTag::Player2, BulletType::Bullet);
+        let mut strong = weak.with_charge(2);
+        strong.tag = BulletTag::Player1;
+        strong.dir = Right;
+
+        let mut a = strong;
+        a.update(&EMPTY_MAP, &[], &[weak], &[]);
+        assert!(!a.should_die);
+        let mut b = weak;
+        b.update(&EMPTY_MAP, &[], &[strong], &[]);
+        assert!(b.should_die);
+    }
+
+    fn tile() -> impl Strategy<Value = MapTile> {
+        any::<u8>().prop_map(MapTile::from_u8)
+    }
+
+    proptest! {
+        #[test]
+        fn test_tiles_block_both_ways(tile in tile(), dir in direction()) {
+            prop_assert_eq!(
+                bullet_over(tile, dir).should_die,
+                bullet_over(tile, dir.flipped()).should_die
+            );
+        }
+
+        #[test]
+        fn test_reflections_retrace_their_path(tile in tile(), dir in direction()) {
+            if bullet_is_passable(tile, dir) {
+                let out = bullet_over(tile, dir).dir;
+                let back = bullet_over(tile, out.flipped());
+                prop_assert!(!back.should_die);
+                prop_assert_eq!(back.dir, dir.flipped());
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use core::hash::{Hash, Hasher};
+
+/// A 32-bit FNV-1a hasher.
+///
+/// Unlike the `std` hashers this isn't randomly seeded, so the same state
+/// hashes the same on every console.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Fnv1a {
+    state: u32,
+}
+
+impl Fnv1a {
+    const OFFSET_BASIS: u32 = 0x811C_9DC5;
+    const PRIME: u32 = 0x0100_0193;
+
+    pub const fn new() -> Self {
+        Self {
+            state: Self::OFFSET_BASIS,
+        }
+    }
+}
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= *byte as u32;
+            self.state = self.state.wrapping_mul(Self::PRIME);
+        }
+    }
+    // Lengths get hashed as `usize`s; pin them to 32 bits so the host and the
+    // GBA agree.
+    fn write_usize(&mut self, n: usize) {
+        self.write_u32(n as u32);
+    }
+    fn write_isize(&mut self, n: isize) {
+        self.write_i32(n as i32);
+    }
+    fn finish(&self) -> u64 {
+        self.state as u64
+    }
+}
+
+/// Hashes `value` with `Fnv1a`.
+pub fn checksum<T: Hash + ?Sized>(value: &T) -> u32 {
+    let mut hasher = Fnv1a::new();
+    value.hash(&mut hasher);
+    hasher.state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        map::{self, MapTile},
+        sim::Simulation,
+        ControlsRepr, Direction,
+    };
+
+    #[test]
+    fn test_fnv1a_reference_values() {
+        // From the reference FNV test suite.
+        let hash = |bytes: &[u8]| {
+            let mut hasher = Fnv1a::new();
+            hasher.write(bytes);
+            hasher.finish()
+        };
+        assert_eq!(hash(b""), 0x811C_9DC5);
+        assert_eq!(hash(b"a"), 0xE40C_292C);
+        assert_eq!(hash(b"foobar"), 0xBF9C_F968);
+    }
+
+    #[test]
+    fn test_checksum_sees_state_changes() {
+        let map = map::generate(7, map::HONEYCOMB_BASE, Default::default());
+        let sim = Simulation::new(map, 4);
+        let base = checksum(&sim);
+        assert_eq!(base, checksum(&sim.clone()));
+
+        let mut moved = sim.clone();
+        moved.players[2].pos.x += 1;
+        assert_ne!(base, checksum(&moved));
+
+        let mut turned = sim.clone();
+        turned.players[0].dir = Direction::Up;
+        assert_ne!(base, checksum(&turned));
+
+        let mut flipped = sim.clone();
+        flipped.map.set(3, 3, MapTile::UpMirror);
+        flipped.map.flip_all();
+        assert_ne!(base, checksum(&flipped));
+
+        let mut fired = sim.clone();
+        let mut inputs = [ControlsRepr::default(); 4];
+        inputs[1].fired_bullet = true;
+        fired.step(&inputs);
+        let mut idle = sim.clone();
+        idle.step(&[ControlsRepr::default(); 4]);
+        assert_ne!(checksum(&fired), checksum(&idle));
+    }
+}
@@ -0,0 +1,25 @@
+//! The gameplay logic for Speglar-GBA, with no dependencies on the GBA itself.
+//!
+//! Everything in here builds for the host as well as the GBA, so the game
+//! rules can be tested with `cargo test -p speglar-core` on any host; the
+//! tests get `std`, which the property tests need.
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod bullet;
+pub mod checksum;
+pub mod link;
+pub mod lobby;
+pub mod logs;
+pub mod map;
+pub mod netplay;
+pub mod packet;
+pub mod player;
+pub mod rng;
+pub mod sim;
+pub mod utils;
+
+pub use bullet::*;
+pub use player::*;
+pub use utils::*;
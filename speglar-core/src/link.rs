@@ -0,0 +1,325 @@
+//! The link port's registers, read and written through a `RegisterBackend`
+//! so the same code can drive the real hardware or a `SimulatedLink`. The
+//! hardware backend lives in the GBA crate, since it's only sound there.
+//!
+//! Only the registers the multiplayer protocol needs live here, along with the
+//! timers it keeps time with; the GBA side hooks up the interrupt and adds
+//! blocking waits on top.
+use crate::{read_bit, write_bit};
+
+pub mod bitbang;
+pub mod clock;
+pub mod joybus;
+pub mod multiboot;
+pub mod multiplayer;
+pub mod normal;
+pub mod queues;
+mod sim;
+pub mod spsc;
+pub mod uart;
+pub use sim::*;
+
+pub const SIOMULTI0: usize = 0x4000120;
+pub const SIOCNT: usize = 0x4000128;
+pub const SIOMLT_SEND: usize = 0x400012A;
+pub const RCNT: usize = 0x4000134;
+
+/// Somewhere 16-bit registers can be read and written, by address.
+pub trait RegisterBackend: Copy {
+    fn read(&self, addr: usize) -> u16;
+    fn write(&self, addr: usize, value: u16);
+}
+
+pub struct RegisterWrapper<B> {
+    addr: usize,
+    backend: B,
+}
+
+impl<B: RegisterBackend + Default> RegisterWrapper<B> {
+    pub fn new(addr: usize) -> Self {
+        Self::with_backend(addr, B::default())
+    }
+}
+
+impl<B> RegisterWrapper<B> {
+    pub const fn with_backend(addr: usize, backend: B) -> Self {
+        Self { addr, backend }
+    }
+}
+
+impl<B: RegisterBackend> RegisterWrapper<B> {
+    pub fn read(&self) -> u16 {
+        self.backend.read(self.addr)
+    }
+    pub fn write(&self, n: u16) {
+        self.backend.write(self.addr, n)
+    }
+    pub fn read_bit(&self, n: u8) -> bool {
+        read_bit(self.read(), n)
+    }
+    pub fn write_bit(&self, n: u8, value: bool) {
+        self.write(write_bit(self.read(), n, value));
+    }
+}
+
+/// Lets a register wrapper use the methods of the more general wrapper in
+/// `$field`, optionally passing a backend type parameter through.
+#[macro_export]
+macro_rules! method_wraps {
+    ($child:ident<$backend:ident>, $field:ident, $parent:ident) => {
+        impl<$backend> ::core::convert::AsRef<$parent<$backend>> for $child<$backend> {
+            fn as_ref(&self) -> &$parent<$backend> {
+                &self.$field
+            }
+        }
+        impl<$backend> ::core::ops::Deref for $child<$backend> {
+            type Target = $parent<$backend>;
+            fn deref(&self) -> &Self::Target {
+                self.as_ref()
+            }
+        }
+        impl<$backend> ::core::convert::AsMut<$parent<$backend>> for $child<$backend> {
+            fn as_mut(&mut self) -> &mut $parent<$backend> {
+                &mut self.$field
+            }
+        }
+        impl<$backend> ::core::ops::DerefMut for $child<$backend> {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                self.as_mut()
+            }
+        }
+    };
+    ($child:ty, $field:ident, $parent:ty) => {
+        impl ::core::convert::AsRef<$parent> for $child {
+            fn as_ref(&self) -> &$parent {
+                &self.$field
+            }
+        }
+        impl ::core::ops::Deref for $child {
+            type Target = $parent;
+            fn deref(&self) -> &Self::Target {
+                self.as_ref()
+            }
+        }
+        impl ::core::convert::AsMut<$parent> for $child {
+            fn as_mut(&mut self) -> &mut $parent {
+                &mut self.$field
+            }
+        }
+        impl ::core::ops::DerefMut for $child {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                self.as_mut()
+            }
+        }
+    };
+}
+
+#[repr(u8)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Default)]
+pub enum BaudRate {
+    #[default]
+    B9600 = 0,
+    B38400 = 1,
+    B57600 = 2,
+    B115200 = 3,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum SerialMode {
+    Normal,
+    Multiplayer,
+    Uart,
+    Joybus,
+    Gpio,
+}
+
+pub struct RcntWrapper<B> {
+    reg: RegisterWrapper<B>,
+}
+method_wraps!(RcntWrapper<B>, reg, RegisterWrapper);
+
+impl<B: RegisterBackend + Default> Default for RcntWrapper<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: RegisterBackend + Default> RcntWrapper<B> {
+    pub fn new() -> Self {
+        Self::with_backend(B::default())
+    }
+    pub fn get() -> Self {
+        Self::new()
+    }
+}
+
+impl<B> RcntWrapper<B> {
+    pub const fn with_backend(backend: B) -> Self {
+        Self {
+            reg: RegisterWrapper::with_backend(RCNT, backend),
+        }
+    }
+}
+
+impl<B: RegisterBackend> RcntWrapper<B> {
+    pub fn sc_data(&self) -> bool {
+        self.reg.read_bit(0)
+    }
+    pub fn write_sc_data(&self, value: bool) {
+        self.reg.write_bit(0, value)
+    }
+    pub fn sd_data(&self) -> bool {
+        self.reg.read_bit(1)
+    }
+    pub fn write_sd_data(&self, value: bool) {
+        self.reg.write_bit(1, value)
+    }
+    pub fn si_data(&self) -> bool {
+        self.reg.read_bit(2)
+    }
+    pub fn write_si_data(&self, value: bool) {
+        self.reg.write_bit(2, value)
+    }
+    pub fn so_data(&self) -> bool {
+        self.reg.read_bit(3)
+    }
+    pub fn write_so_data(&self, value: bool) {
+        self.reg.write_bit(3, value)
+    }
+
+    pub fn sc_is_output(&self) -> bool {
+        self.reg.read_bit(4)
+    }
+    pub fn set_sc_direction(&self, is_output: bool) {
+        self.reg.write_bit(4, is_output)
+    }
+    pub fn sd_is_output(&self) -> bool {
+        self.reg.read_bit(5)
+    }
+    pub fn set_sd_direction(&self, is_output: bool) {
+        self.reg.write_bit(5, is_output)
+    }
+    pub fn si_is_output(&self) -> bool {
+        self.reg.read_bit(6)
+    }
+    pub fn set_si_direction(&self, is_output: bool) {
+        self.reg.write_bit(6, is_output)
+    }
+    pub fn so_is_output(&self) -> bool {
+        self.reg.read_bit(7)
+    }
+    pub fn set_so_direction(&self, is_output: bool) {
+        self.reg.write_bit(7, is_output)
+    }
+
+    pub fn serial_line_directions(&self) -> (bool, bool, bool, bool) {
+        let value = self.reg.read();
+        let masked = value & (0xF << 4);
+        (
+            masked & (1 << 4) != 0,
+            masked & (1 << 5) != 0,
+            masked & (1 << 6) != 0,
+            masked & (1 << 7) != 0,
+        )
+    }
+
+    pub fn write_directions(
+        &self,
+        sc_output: bool,
+        sd_output: bool,
+        si_output: bool,
+        so_output: bool,
+    ) {
+        let old = self.reg.read();
+        let masked = old & !(0xF << 4);
+        let dirmask = ((sc_output as u16) << 4)
+            | ((sd_output as u16) << 5)
+            | ((si_output as u16) << 6)
+            | ((so_output as u16) << 7);
+        let new = masked | dirmask;
+        self.reg.write(new);
+    }
+    pub fn si_interrupt_enabled(&self) -> bool {
+        self.reg.read_bit(8)
+    }
+    pub fn enable_si_interrupt(&self, enable: bool) {
+        self.reg.write_bit(8, enable)
+    }
+
+    pub fn set_mode(&self, mode: SerialMode) {
+        let (fourteen, fifteen) = match mode {
+            SerialMode::Joybus => (true, true),
+            SerialMode::Gpio => (false, true),
+            _ => (false, false),
+        };
+        self.reg.write_bit(14, fourteen);
+        self.reg.write_bit(15, fifteen);
+    }
+    pub fn mode(&self) -> Option<SerialMode> {
+        let final_bit = self.reg.read_bit(15);
+        let second_last = self.reg.read_bit(14);
+        match (second_last, final_bit) {
+            (_, false) => None,
+            (true, true) => Some(SerialMode::Joybus),
+            (false, true) => Some(SerialMode::Gpio),
+        }
+    }
+}
+
+pub struct SiocntWrapper<B> {
+    reg: RegisterWrapper<B>,
+}
+
+method_wraps!(SiocntWrapper<B>, reg, RegisterWrapper);
+
+impl<B: RegisterBackend + Default> Default for SiocntWrapper<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: RegisterBackend + Default> SiocntWrapper<B> {
+    pub fn new() -> Self {
+        Self::with_backend(B::default())
+    }
+    pub fn get() -> Self {
+        Self::new()
+    }
+}
+
+impl<B> SiocntWrapper<B> {
+    pub const fn with_backend(backend: B) -> Self {
+        Self {
+            reg: RegisterWrapper::with_backend(SIOCNT, backend),
+        }
+    }
+}
+
+impl<B: RegisterBackend> SiocntWrapper<B> {
+    pub fn mode(&self) -> SerialMode {
+        let value = self.reg.read();
+        if !read_bit(value, 13) {
+            SerialMode::Normal
+        } else if read_bit(value, 12) {
+            SerialMode::Uart
+        } else {
+            SerialMode::Multiplayer
+        }
+    }
+    pub fn set_mode(&self, mode: SerialMode) {
+        let prev = self.reg.read();
+        let next = match mode {
+            SerialMode::Normal => write_bit(prev, 13, false),
+            SerialMode::Multiplayer => write_bit(write_bit(prev, 12, false), 13, true),
+            SerialMode::Uart => write_bit(write_bit(prev, 12, true), 13, true),
+            _ => prev,
+        };
+        self.reg.write(next);
+    }
+    pub fn irq_enabled(&self) -> bool {
+        self.reg.read_bit(14)
+    }
+    pub fn enable_irq(&self, v: bool) {
+        self.reg.write_bit(14, v)
+    }
+}
@@ -0,0 +1,55 @@
+//! The parts of the bit-banged masters in the GBA crate that don't touch the
+//! pins.
+
+/// The usual SPI modes, as clock polarity and phase.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum SpiMode {
+    /// Clock idles low, data sampled on the rising edge.
+    #[default]
+    Mode0,
+    /// Clock idles low, data sampled on the falling edge.
+    Mode1,
+    /// Clock idles high, data sampled on the falling edge.
+    Mode2,
+    /// Clock idles high, data sampled on the rising edge.
+    Mode3,
+}
+
+impl SpiMode {
+    /// The mode for a clock polarity (CPOL) and phase (CPHA).
+    pub const fn from_cpol_cpha(idles_high: bool, samples_late: bool) -> Self {
+        match (idles_high, samples_late) {
+            (false, false) => SpiMode::Mode0,
+            (false, true) => SpiMode::Mode1,
+            (true, false) => SpiMode::Mode2,
+            (true, true) => SpiMode::Mode3,
+        }
+    }
+    /// Whether the clock idles high.
+    pub const fn idles_high(self) -> bool {
+        matches!(self, SpiMode::Mode2 | SpiMode::Mode3)
+    }
+    /// Whether data is sampled on the second edge of each clock cycle.
+    pub const fn samples_late(self) -> bool {
+        matches!(self, SpiMode::Mode1 | SpiMode::Mode3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpol_cpha() {
+        for (mode, cpol, cpha) in [
+            (SpiMode::Mode0, false, false),
+            (SpiMode::Mode1, false, true),
+            (SpiMode::Mode2, true, false),
+            (SpiMode::Mode3, true, true),
+        ] {
+            assert_eq!(SpiMode::from_cpol_cpha(cpol, cpha), mode);
+            assert_eq!(mode.idles_high(), cpol);
+            assert_eq!(mode.samples_late(), cpha);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! A free-running clock on timers 2 and 3, for timeouts and bit-banging.
+//!
+//! Timer 2 ticks at the system clock / 64 and timer 3 counts its overflows,
+//! giving a 32-bit count of roughly 4us ticks that wraps after about four and
+//! a half hours.
+use super::*;
+
+pub const TM2CNT_L: usize = 0x4000108;
+pub const TM2CNT_H: usize = 0x400010A;
+pub const TM3CNT_L: usize = 0x400010C;
+pub const TM3CNT_H: usize = 0x400010E;
+
+/*
+  TMxCNT_H:
+  Bit   Expl.
+  0-1   Prescaler Selection (0=F/1, 1=F/64, 2=F/256, 3=F/1024)
+  2     Count-up Timing   (0=Normal, 1=See below)  ;DON'T USE FOR TIMER 0
+  3-5   Not used
+  6     Timer IRQ Enable  (0=Disable, 1=IRQ on Timer overflow)
+  7     Timer Start/Stop  (0=Stop, 1=Operate)
+  8-15  Not used
+*/
+const PRESCALE_64: u16 = 1;
+const COUNT_UP: u16 = 1 << 2;
+const ENABLE: u16 = 1 << 7;
+
+pub const TICKS_PER_SECOND: u32 = 16_777_216 / 64;
+
+pub const fn millis_to_ticks(millis: u32) -> u32 {
+    ((millis as u64 * TICKS_PER_SECOND as u64) / 1000) as u32
+}
+
+pub struct Clock<B> {
+    backend: B,
+}
+
+impl<B: RegisterBackend + Default> Default for Clock<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: RegisterBackend + Default> Clock<B> {
+    pub fn new() -> Self {
+        Self::with_backend(B::default())
+    }
+}
+
+impl<B> Clock<B> {
+    pub const fn with_backend(backend: B) -> Self {
+        Self { backend }
+    }
+}
+
+impl<B: RegisterBackend> Clock<B> {
+    /// Starts the clock if it isn't already running.
+    pub fn start(&self) {
+        if self.backend.read(TM3CNT_H) & ENABLE != 0 {
+            return;
+        }
+        self.backend.write(TM2CNT_L, 0);
+        self.backend.write(TM3CNT_L, 0);
+        self.backend.write(TM3CNT_H, COUNT_UP | ENABLE);
+        self.backend.write(TM2CNT_H, PRESCALE_64 | ENABLE);
+    }
+
+    /// The current time in ticks.
+    pub fn now(&self) -> u32 {
+        // Timer 2 might overflow between the two reads, so retry until timer 3
+        // reads the same on both sides.
+        loop {
+            let high = self.backend.read(TM3CNT_L);
+            let low = self.backend.read(TM2CNT_L);
+            if self.backend.read(TM3CNT_L) == high {
+                return ((high as u32) << 16) | low as u32;
+            }
+        }
+    }
+
+    /// Busy-waits for at least `ticks` ticks; the clock needs to be running.
+    ///
+    /// We may start partway through a tick, so this waits for one more tick
+    /// edge than asked rather than ever returning early.
+    pub fn wait_ticks(&self, ticks: u32) {
+        let start = self.now();
+        while self.now().wrapping_sub(start) <= ticks {}
+    }
+}
@@ -0,0 +1,167 @@
+//! The JOY BUS control and status registers; the 32-bit data registers are
+//! only ever read and written whole, so they stay with the GBA driver.
+use super::*;
+
+pub const JOYCNT: usize = 0x4000140;
+/// Only the low half of JOYSTAT has anything in it.
+pub const JOYSTAT: usize = 0x4000158;
+
+/// JOYCNT flags for the commands the host has sent.
+pub const RESET_FLAG: u16 = 1 << 0;
+pub const RECEIVE_FLAG: u16 = 1 << 1;
+pub const SEND_FLAG: u16 = 1 << 2;
+pub const ALL_FLAGS: u16 = RESET_FLAG | RECEIVE_FLAG | SEND_FLAG;
+
+pub struct JoycntWrapper<B> {
+    reg: RegisterWrapper<B>,
+}
+crate::method_wraps!(JoycntWrapper<B>, reg, RegisterWrapper);
+
+/*
+  Bit   Expl.
+  0     Device Reset Flag     (Command FFh)     (Read/Acknowledge)
+  1     Receive Complete Flag (Command 15h)     (Read/Acknowledge)
+  2     Send Complete Flag    (Command 14h)     (Read/Acknowledge)
+  3-5   Not used
+  6     IRQ Enable            (0=Disable, 1=IRQ when any of Bit 0-2 become set)
+  7-15  Not used
+
+  The flags are acknowledged by writing a 1 to them.
+*/
+impl<B: RegisterBackend + Default> JoycntWrapper<B> {
+    pub fn get() -> Self {
+        Self::with_backend(B::default())
+    }
+}
+
+impl<B> JoycntWrapper<B> {
+    pub const fn with_backend(backend: B) -> Self {
+        Self {
+            reg: RegisterWrapper::with_backend(JOYCNT, backend),
+        }
+    }
+}
+
+impl<B: RegisterBackend> JoycntWrapper<B> {
+    pub fn reset_flag(&self) -> bool {
+        self.reg.read_bit(0)
+    }
+    pub fn receive_flag(&self) -> bool {
+        self.reg.read_bit(1)
+    }
+    pub fn send_flag(&self) -> bool {
+        self.reg.read_bit(2)
+    }
+    /// Clears the flags in `mask`, leaving the others alone.
+    pub fn acknowledge(&self, mask: u16) {
+        // Writing back a set flag would clear it, so only keep the IRQ bit.
+        let keep = self.reg.read() & (1 << 6);
+        self.reg.write(keep | (mask & ALL_FLAGS));
+    }
+    pub fn irq_enabled(&self) -> bool {
+        self.reg.read_bit(6)
+    }
+    pub fn enable_irq(&self, enable: bool) {
+        let keep = self.reg.read() & !ALL_FLAGS;
+        self.reg.write(write_bit(keep, 6, enable));
+    }
+}
+
+pub struct JoystatWrapper<B> {
+    reg: RegisterWrapper<B>,
+}
+crate::method_wraps!(JoystatWrapper<B>, reg, RegisterWrapper);
+
+/*
+  Bit   Expl.
+  0     Not used
+  1     Receive Status Flag   (1=JOY_RECV written by the host, not yet read)
+  2     Not used
+  3     Send Status Flag      (1=JOY_TRANS written, not yet read by the host)
+  4-5   General Purpose Flag  (Not assigned, may be used for whatever purpose)
+  6-31  Not used
+*/
+impl<B: RegisterBackend + Default> JoystatWrapper<B> {
+    pub fn get() -> Self {
+        Self::with_backend(B::default())
+    }
+}
+
+impl<B> JoystatWrapper<B> {
+    pub const fn with_backend(backend: B) -> Self {
+        Self {
+            reg: RegisterWrapper::with_backend(JOYSTAT, backend),
+        }
+    }
+}
+
+impl<B: RegisterBackend> JoystatWrapper<B> {
+    pub fn receive_pending(&self) -> bool {
+        self.reg.read_bit(1)
+    }
+    pub fn send_pending(&self) -> bool {
+        self.reg.read_bit(3)
+    }
+    pub fn general_flags(&self) -> u8 {
+        ((self.reg.read() >> 4) & 3) as u8
+    }
+    pub fn set_general_flags(&self, flags: u8) {
+        let old = self.reg.read();
+        let new = (old & !(3 << 4)) | ((flags as u16 & 3) << 4);
+        self.reg.write(new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::tests::RegisterFile;
+
+    #[test]
+    fn test_acknowledge() {
+        let regs = RegisterFile::default();
+        let joycnt = JoycntWrapper::with_backend(&regs);
+        for irq in [false, true] {
+            let irq_bit = (irq as u16) << 6;
+            for mask in 0..=ALL_FLAGS {
+                // Every flag set, so writing any of them back would clear it.
+                joycnt.write(irq_bit | ALL_FLAGS);
+                joycnt.acknowledge(mask);
+                assert_eq!(joycnt.read(), irq_bit | mask);
+            }
+        }
+        // Nothing outside the flags gets acknowledged.
+        joycnt.write(0);
+        joycnt.acknowledge(0xFFFF);
+        assert_eq!(joycnt.read(), ALL_FLAGS);
+    }
+
+    #[test]
+    fn test_enable_irq() {
+        let regs = RegisterFile::default();
+        let joycnt = JoycntWrapper::with_backend(&regs);
+        joycnt.write(ALL_FLAGS);
+        joycnt.enable_irq(true);
+        assert_eq!(joycnt.read(), 1 << 6);
+        assert!(joycnt.irq_enabled());
+        joycnt.write(ALL_FLAGS | (1 << 6));
+        joycnt.enable_irq(false);
+        assert_eq!(joycnt.read(), 0);
+    }
+
+    #[test]
+    fn test_general_flags() {
+        let regs = RegisterFile::default();
+        let joystat = JoystatWrapper::with_backend(&regs);
+        joystat.write(0b1010);
+        for flags in 0..4 {
+            joystat.set_general_flags(flags);
+            assert_eq!(joystat.general_flags(), flags);
+            assert_eq!(joystat.read(), 0b1010 | ((flags as u16) << 4));
+        }
+        joystat.set_general_flags(0xFF);
+        assert_eq!(joystat.general_flags(), 3);
+        assert!(joystat.receive_pending());
+        assert!(joystat.send_pending());
+    }
+}
@@ -0,0 +1,467 @@
+//! Sends a program to linked GBAs with no cartridge in, which the BIOS then
+//! boots from RAM.
+//!
+//! We do the handshake ourselves over the multiplayer registers, then hand the
+//! actual transfer to the BIOS's `MultiBoot` call through `MultibootBios`, so
+//! everything up to that call runs against a `SimulatedLink` too.
+//! FROM https://rust-console.github.io/gbatek-gbaonly/#biosmultibootsinglegamepak
+use super::clock::millis_to_ticks;
+use super::multiplayer::{MultiplayerLink, PlayerId, TransferError};
+use super::*;
+use crate::logs::println;
+
+/// The cartridge header at the start of every image, which goes out during the
+/// handshake rather than with the rest.
+pub const HEADER_LEN: usize = 0xC0;
+/// The most the BIOS will send after the header, since it all has to fit in
+/// the children's 256K of work RAM.
+pub const MAX_BODY_LEN: usize = 0x3FF40;
+/// The least the BIOS will send after the header.
+pub const MIN_BODY_LEN: usize = 0x100;
+
+/// How many times to look for children before giving up, 1/16 s apart.
+const DETECT_ATTEMPTS: u32 = 16;
+const DETECT_DELAY_MILLIS: u32 = 1000 / 16;
+/// The colour and animation of the logo on the children's screens while they
+/// download.
+const PALETTE_DATA: u8 = 0xD1;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MultibootError {
+    /// Only the parent can send.
+    NotParent,
+    /// Multiboot only runs at 115200 baud.
+    WrongBaudRate,
+    /// The image is too short or too long, or the part after the header isn't
+    /// a multiple of 16 bytes.
+    BadImageLength(usize),
+    /// No children answered.
+    NoChildren,
+    /// A child replied with something the handshake didn't expect.
+    UnexpectedReply {
+        player: PlayerId,
+        sent: u16,
+        reply: u16,
+    },
+    /// The BIOS reported that the transfer failed.
+    TransferFailed,
+    Transfer(TransferError),
+}
+
+impl From<TransferError> for MultibootError {
+    fn from(value: TransferError) -> Self {
+        MultibootError::Transfer(value)
+    }
+}
+
+/// The BIOS `MultiBoot` call's parameters.
+///
+/// Everything not named is used by the BIOS itself, and needs to start out
+/// zero.
+#[repr(C)]
+pub struct MultiBootParam {
+    reserved0: [u8; 0x14],
+    handshake_data: u8,
+    reserved1: [u8; 4],
+    client_data: [u8; 3],
+    palette_data: u8,
+    reserved2: u8,
+    /// Bits 1-3 for children 1-3.
+    client_bit: u8,
+    reserved3: u8,
+    boot_srcp: *const u8,
+    boot_endp: *const u8,
+    reserved4: [u8; 0x24],
+}
+
+/// What the handshake needs from the console beyond its registers.
+pub trait MultibootBios {
+    /// Runs BIOS function 25h in multiplayer mode with `param`, returning 0
+    /// on success.
+    fn multiboot(&mut self, param: &MultiBootParam) -> u32;
+    /// Busy-waits for at least `ticks` ticks of the link's clock.
+    fn wait_ticks(&mut self, ticks: u32);
+    /// Called over and over while a transfer is in progress.
+    fn idle(&mut self) {}
+}
+
+/// Sends `image`, a multiboot ROM (linked to run from work RAM), to every
+/// child that answers. Returns which players got it.
+///
+/// `serial` needs to be at 115200 baud, and shouldn't have run
+/// `initialize_id`, since the children's BIOS won't answer that.
+pub fn send_multiboot<B: RegisterBackend>(
+    serial: &mut MultiplayerLink<'_, B>,
+    bios: &mut impl MultibootBios,
+    image: &'static [u8],
+) -> Result<[bool; 4], MultibootError> {
+    let body_len = image.len().saturating_sub(HEADER_LEN);
+    if !(MIN_BODY_LEN..=MAX_BODY_LEN).contains(&body_len) || body_len % 0x10 != 0 {
+        return Err(MultibootError::BadImageLength(image.len()));
+    }
+    let siocnt = serial.port().siocnt();
+    if !siocnt.is_parent() {
+        return Err(MultibootError::NotParent);
+    }
+    if siocnt.baud_rate() != BaudRate::B115200 {
+        return Err(MultibootError::WrongBaudRate);
+    }
+    serial.clock().start();
+    // The BIOS polls the registers itself, so keep the interrupt out of it.
+    let had_interrupt = serial.interrupt_enabled();
+    serial.enable_interrupt(false);
+    let retvl = run_handshake(serial, bios, image);
+    serial.enable_interrupt(had_interrupt);
+    retvl
+}
+
+fn run_handshake<B: RegisterBackend>(
+    serial: &mut MultiplayerLink<'_, B>,
+    bios: &mut impl MultibootBios,
+    image: &'static [u8],
+) -> Result<[bool; 4], MultibootError> {
+    let children = detect_children(serial, bios)?;
+    let client_bit = children_mask(children);
+    println!("Multiboot: found children {:?}", children);
+
+    let ready = |id: PlayerId| 0x7200 | (1 << id as u16);
+    expect_reply(serial, bios, children, 0x6100 | client_bit as u16, ready)?;
+    for (idx, chunk) in image[..HEADER_LEN].chunks(2).enumerate() {
+        let word = u16::from_le_bytes([chunk[0], chunk[1]]);
+        let remaining = (HEADER_LEN / 2 - idx) as u16;
+        expect_reply(serial, bios, children, word, |id| {
+            (remaining << 8) | (1 << id as u16)
+        })?;
+    }
+    expect_reply(serial, bios, children, 0x6200, |id| 1 << id as u16)?;
+    expect_reply(serial, bios, children, 0x6200 | client_bit as u16, ready)?;
+
+    // The children answer with a random byte each once they're ready for the
+    // palette, which goes into the final checksum.
+    let palette_word = 0x6300 | PALETTE_DATA as u16;
+    let mut client_data = [0xFFu8; 3];
+    let start = serial.clock().now();
+    loop {
+        let replies = exchange(serial, bios, palette_word)?;
+        let all_ready = PlayerId::ALL[1..]
+            .iter()
+            .all(|id| !children[*id as usize] || replies[*id as usize] >> 8 == 0x73);
+        if all_ready {
+            for id in &PlayerId::ALL[1..] {
+                if children[*id as usize] {
+                    client_data[*id as usize - 1] = replies[*id as usize] as u8;
+                }
+            }
+            break;
+        }
+        if timed_out(serial, start) {
+            return Err(TransferError::Timeout.into());
+        }
+    }
+    let handshake_data = client_data
+        .iter()
+        .fold(0x11u8, |acc, data| acc.wrapping_add(*data));
+    exchange(serial, bios, 0x6400 | handshake_data as u16)?;
+    bios.wait_ticks(millis_to_ticks(DETECT_DELAY_MILLIS));
+
+    let param = MultiBootParam {
+        reserved0: [0; 0x14],
+        handshake_data,
+        reserved1: [0; 4],
+        client_data,
+        palette_data: PALETTE_DATA,
+        reserved2: 0,
+        client_bit,
+        reserved3: 0,
+        boot_srcp: image[HEADER_LEN..].as_ptr(),
+        boot_endp: image.as_ptr_range().end,
+        reserved4: [0; 0x24],
+    };
+    println!("Multiboot: handshake done, sending {} bytes", image.len());
+    if bios.multiboot(&param) != 0 {
+        return Err(MultibootError::TransferFailed);
+    }
+    Ok(children)
+}
+
+/// Sends the "anyone there?" word until every child that's answering at all
+/// has answered properly.
+fn detect_children<B: RegisterBackend>(
+    serial: &mut MultiplayerLink<'_, B>,
+    bios: &mut impl MultibootBios,
+) -> Result<[bool; 4], MultibootError> {
+    for _ in 0..DETECT_ATTEMPTS {
+        let replies = exchange(serial, bios, 0x6200)?;
+        let mut children = [false; 4];
+        let mut waiting = false;
+        for id in &PlayerId::ALL[1..] {
+            match replies[*id as usize] {
+                reply if reply == 0x7200 | (1 << *id as u16) => children[*id as usize] = true,
+                0xFFFF => {}
+                // Connected, but its BIOS hasn't caught up yet.
+                _ => waiting = true,
+            }
+        }
+        if !waiting && children.contains(&true) {
+            return Ok(children);
+        }
+        bios.wait_ticks(millis_to_ticks(DETECT_DELAY_MILLIS));
+    }
+    Err(MultibootError::NoChildren)
+}
+
+fn children_mask(children: [bool; 4]) -> u8 {
+    PlayerId::ALL
+        .iter()
+        .filter(|id| children[**id as usize])
+        .fold(0, |acc, id| acc | (1 << *id as u8))
+}
+
+/// Sends `word` and checks every child replied with `expected(id)`.
+fn expect_reply<B: RegisterBackend>(
+    serial: &mut MultiplayerLink<'_, B>,
+    bios: &mut impl MultibootBios,
+    children: [bool; 4],
+    word: u16,
+    expected: impl Fn(PlayerId) -> u16,
+) -> Result<(), MultibootError> {
+    let replies = exchange(serial, bios, word)?;
+    for id in PlayerId::ALL {
+        let reply = replies[id as usize];
+        if children[id as usize] && reply != expected(id) {
+            return Err(MultibootError::UnexpectedReply {
+                player: id,
+                sent: word,
+                reply,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Does a single transfer of `word`, waiting for it to finish, and returns
+/// what everyone sent.
+fn exchange<B: RegisterBackend>(
+    serial: &mut MultiplayerLink<'_, B>,
+    bios: &mut impl MultibootBios,
+    word: u16,
+) -> Result<[u16; 4], MultibootError> {
+    serial.write_send_reg(word);
+    let start = serial.clock().now();
+    // The parent starts the transfer whether or not the children are ready;
+    // the replies will tell.
+    while let Err(TransferError::AlreadyInProgress) = serial.start_transfer() {
+        if timed_out(serial, start) {
+            return Err(TransferError::Timeout.into());
+        }
+        bios.idle();
+    }
+    let siocnt = serial.port().siocnt();
+    while siocnt.busy() {
+        if timed_out(serial, start) {
+            return Err(TransferError::Timeout.into());
+        }
+        bios.idle();
+    }
+    Ok(PlayerId::ALL.map(|id| serial.port().comm_reg(id).raw_read()))
+}
+
+/// Whether the link's timeout has passed since `start`.
+fn timed_out<B: RegisterBackend>(serial: &MultiplayerLink<'_, B>, start: u32) -> bool {
+    serial.clock().now().wrapping_sub(start) > millis_to_ticks(serial.timeout_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::link::multiplayer::MultiplayerPort;
+    use crate::link::queues::TransferQueues;
+    use crate::link::{SimulatedGba, SimulatedLink};
+
+    static IMAGE: [u8; HEADER_LEN + MIN_BODY_LEN] = image();
+
+    const fn image() -> [u8; HEADER_LEN + MIN_BODY_LEN] {
+        let mut image = [0; HEADER_LEN + MIN_BODY_LEN];
+        let mut idx = 0;
+        while idx < image.len() {
+            image[idx] = idx as u8;
+            idx += 1;
+        }
+        image
+    }
+
+    /// Where a child's BIOS has got to in the handshake.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    enum Stage {
+        Waiting,
+        /// Taking the header, with this many words to go.
+        Header(u16),
+        Confirming,
+        Ready,
+        /// Waiting for the palette and then the checksum.
+        Palette,
+        Done {
+            handshake_data: u8,
+        },
+    }
+
+    /// The BIOS of a GBA with no cartridge in, which sets its reply for the
+    /// next transfer after each one, like the real thing.
+    struct SimulatedChild<'a> {
+        id: PlayerId,
+        port: MultiplayerPort<SimulatedGba<'a>>,
+        random: u8,
+        stage: Stage,
+    }
+
+    impl<'a> SimulatedChild<'a> {
+        fn new(link: &'a SimulatedLink, id: PlayerId, random: u8) -> Self {
+            let mut port = MultiplayerPort::new(link.gba(id), BaudRate::B115200).unwrap();
+            port.write_send_reg(0x7200 | (1 << id as u16));
+            Self {
+                id,
+                port,
+                random,
+                stage: Stage::Waiting,
+            }
+        }
+        fn on_transfer(&mut self) {
+            let bit = 1 << self.id as u16;
+            let word = self.port.comm_reg(PlayerId::Parent).raw_read();
+            let (stage, reply) = match self.stage {
+                Stage::Waiting if word >> 8 == 0x61 => {
+                    (Stage::Header(HEADER_LEN as u16 / 2), (0x60 << 8) | bit)
+                }
+                Stage::Waiting => (Stage::Waiting, 0x7200 | bit),
+                Stage::Header(1) => (Stage::Confirming, bit),
+                Stage::Header(left) => (Stage::Header(left - 1), ((left - 1) << 8) | bit),
+                Stage::Confirming if word == 0x6200 => (Stage::Ready, 0x7200 | bit),
+                Stage::Ready if word >> 8 == 0x62 => (Stage::Palette, 0x7300 | self.random as u16),
+                Stage::Palette if word >> 8 == 0x64 => (
+                    Stage::Done {
+                        handshake_data: word as u8,
+                    },
+                    0,
+                ),
+                Stage::Palette => (Stage::Palette, 0x7300 | self.random as u16),
+                stage => panic!("Child {:?} got {:#06x} in {:?}", self.id, word, stage),
+            };
+            self.stage = stage;
+            self.port.write_send_reg(reply);
+        }
+    }
+
+    /// Stands in for the parent's BIOS: finishes each transfer for the
+    /// children, and records what the `MultiBoot` call was given.
+    struct SimulatedBios<'a> {
+        link: &'a SimulatedLink,
+        children: Vec<SimulatedChild<'a>>,
+        param: Option<(u8, [u8; 3], u8, usize)>,
+    }
+
+    impl MultibootBios for SimulatedBios<'_> {
+        fn multiboot(&mut self, param: &MultiBootParam) -> u32 {
+            assert_eq!(param.boot_srcp, IMAGE[HEADER_LEN..].as_ptr());
+            assert_eq!(param.palette_data, PALETTE_DATA);
+            let len = param.boot_endp as usize - param.boot_srcp as usize;
+            self.param = Some((
+                param.handshake_data,
+                param.client_data,
+                param.client_bit,
+                len,
+            ));
+            0
+        }
+        fn wait_ticks(&mut self, ticks: u32) {
+            self.link.advance_clock(ticks + 1);
+        }
+        fn idle(&mut self) {
+            if self.link.complete_transfer() {
+                for child in &mut self.children {
+                    child.on_transfer();
+                }
+            }
+        }
+    }
+
+    fn parent<'a>(
+        link: &'a SimulatedLink,
+        queues: &'a TransferQueues,
+    ) -> MultiplayerLink<'a, SimulatedGba<'a>> {
+        let port = MultiplayerPort::new(link.gba(PlayerId::Parent), BaudRate::B115200).unwrap();
+        MultiplayerLink::new(port, queues)
+    }
+
+    #[test]
+    fn test_handshake() {
+        let link = SimulatedLink::new(4);
+        link.unplug(PlayerId::P2);
+        let queues = TransferQueues::new();
+        let mut serial = parent(&link, &queues);
+        let mut bios = SimulatedBios {
+            link: &link,
+            children: Vec::from([
+                SimulatedChild::new(&link, PlayerId::P1, 0x42),
+                SimulatedChild::new(&link, PlayerId::P3, 0x9C),
+            ]),
+            param: None,
+        };
+        assert_eq!(
+            send_multiboot(&mut serial, &mut bios, &IMAGE),
+            Ok([false, true, false, true])
+        );
+        // P2 isn't there, so its byte stays 0xFF.
+        let handshake_data = 0x11u8
+            .wrapping_add(0x42)
+            .wrapping_add(0xFF)
+            .wrapping_add(0x9C);
+        assert_eq!(
+            bios.param,
+            Some((handshake_data, [0x42, 0xFF, 0x9C], 0b1010, MIN_BODY_LEN))
+        );
+        for child in &bios.children {
+            assert_eq!(child.stage, Stage::Done { handshake_data });
+        }
+    }
+
+    #[test]
+    fn test_no_children() {
+        let link = SimulatedLink::new(1);
+        let queues = TransferQueues::new();
+        let mut serial = parent(&link, &queues);
+        let mut bios = SimulatedBios {
+            link: &link,
+            children: Vec::new(),
+            param: None,
+        };
+        assert_eq!(
+            send_multiboot(&mut serial, &mut bios, &IMAGE),
+            Err(MultibootError::NoChildren)
+        );
+        assert_eq!(bios.param, None);
+    }
+
+    #[test]
+    fn test_bad_image_length() {
+        static SHORT: [u8; HEADER_LEN + MIN_BODY_LEN - 0x10] =
+            [0; HEADER_LEN + MIN_BODY_LEN - 0x10];
+        let link = SimulatedLink::new(2);
+        let queues = TransferQueues::new();
+        let mut serial = parent(&link, &queues);
+        let mut bios = SimulatedBios {
+            link: &link,
+            children: Vec::new(),
+            param: None,
+        };
+        assert_eq!(
+            send_multiboot(&mut serial, &mut bios, &SHORT),
+            Err(MultibootError::BadImageLength(SHORT.len()))
+        );
+        assert_eq!(
+            send_multiboot(&mut serial, &mut bios, &IMAGE[..IMAGE.len() - 1]),
+            Err(MultibootError::BadImageLength(IMAGE.len() - 1))
+        );
+    }
+}
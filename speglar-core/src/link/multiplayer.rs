@@ -0,0 +1,488 @@
+use core::mem;
+
+use super::clock::{millis_to_ticks, Clock};
+use super::queues::{TransferQueues, DROP_TRANSFERS};
+use super::*;
+use crate::PlayerTag;
+
+#[repr(u8)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Default)]
+pub enum PlayerId {
+    #[default]
+    Parent = 0,
+    P1 = 1,
+    P2 = 2,
+    P3 = 3,
+}
+
+impl PlayerId {
+    pub const ALL: [PlayerId; 4] = [PlayerId::Parent, PlayerId::P1, PlayerId::P2, PlayerId::P3];
+}
+
+impl From<PlayerId> for PlayerTag {
+    fn from(value: PlayerId) -> Self {
+        PlayerTag::from_u8(value as u8)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InitializationError {
+    /// The "error" flag was tripped in the SIOCNT register.
+    FailedOkayCheck,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TransferError {
+    /// The "error" flag was tripped in the SIOCNT register.
+    FailedOkayCheck,
+    /// Not all GBAs were ready for the transfer (though the transfer was still attempted)
+    FailedReadyCheck,
+    /// There was a transfer already in progress when the new one was requested.
+    AlreadyInProgress,
+    /// A blocking operation didn't finish within the timeout.
+    Timeout,
+    /// A GBA that was connected has stopped responding.
+    Disconnected(PlayerId),
+}
+
+/// How long blocking operations wait by default before giving up.
+pub const DEFAULT_TIMEOUT_MILLIS: u32 = 3000;
+
+/// Whether a player is still taking part in transfers.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ConnectionState {
+    Connected,
+    /// Their register has read as "no data" for the last `DROP_TRANSFERS`
+    /// transfers.
+    Dropped,
+}
+
+/// What `initialize_id` sends until it sees it come back in our own slot.
+const ID_SENTINEL: u16 = 0xFEAD;
+
+/// The multiplayer mode registers of one GBA, and what we've worked out
+/// about its place on the link.
+///
+/// Nothing in here blocks or relies on interrupts, so it runs the same
+/// against a `SimulatedLink` as on the hardware.
+pub struct MultiplayerPort<B> {
+    backend: B,
+    is_parent: bool,
+    playerid: Option<PlayerId>,
+    rate: BaudRate,
+}
+
+impl<B: RegisterBackend> MultiplayerPort<B> {
+    pub fn new(backend: B, rate: BaudRate) -> Result<Self, InitializationError> {
+        let mut retvl = Self {
+            backend,
+            is_parent: false,
+            playerid: None,
+            rate,
+        };
+        retvl.initialize()?;
+        Ok(retvl)
+    }
+
+    fn initialize(&mut self) -> Result<(), InitializationError> {
+        // FROM https://rust-console.github.io/gbatek-gbaonly/#siomultiplayermode:
+        let rcnt = self.rcnt();
+        let siocnt = self.siocnt();
+
+        rcnt.set_mode(SerialMode::Multiplayer);
+        siocnt.set_mode(SerialMode::Multiplayer);
+        siocnt.set_baud_rate(self.rate);
+
+        if siocnt.error_flag() {
+            return Err(InitializationError::FailedOkayCheck);
+        }
+        self.is_parent = siocnt.is_parent();
+        Ok(())
+    }
+
+    pub fn rcnt(&self) -> RcntWrapper<B> {
+        RcntWrapper::with_backend(self.backend)
+    }
+    pub fn siocnt(&self) -> MultiplayerSiocnt<B> {
+        MultiplayerSiocnt::with_backend(self.backend)
+    }
+    pub fn comm_reg(&self, player: PlayerId) -> MultiplayerCommReg<B> {
+        MultiplayerCommReg::with_backend(player, self.backend)
+    }
+
+    pub fn is_parent(&self) -> bool {
+        self.is_parent
+    }
+    pub fn id(&self) -> Option<PlayerId> {
+        self.playerid
+    }
+
+    pub fn write_send_reg(&mut self, data: u16) {
+        self.backend.write(SIOMLT_SEND, data)
+    }
+    /// What `player` sent in the last transfer, if they sent anything.
+    pub fn read_player_reg(&self, player: PlayerId) -> Option<u16> {
+        self.comm_reg(player).read()
+    }
+
+    /// Starts a transfer if we're the parent, then reports whether anything
+    /// was wrong with it.
+    pub fn start_transfer(&self) -> Result<(), TransferError> {
+        let siocnt = self.siocnt();
+        if siocnt.busy() {
+            return Err(TransferError::AlreadyInProgress);
+        }
+        let all_ready = self.all_ready();
+        if self.is_parent {
+            siocnt.start_transfer();
+        }
+        if !all_ready {
+            return Err(TransferError::FailedReadyCheck);
+        }
+        if siocnt.error_flag() {
+            return Err(TransferError::FailedOkayCheck);
+        }
+        Ok(())
+    }
+    pub fn enable_interrupt(&self, should_enable: bool) {
+        self.siocnt().enable_irq(should_enable)
+    }
+    pub fn interrupt_enabled(&self) -> bool {
+        self.siocnt().irq_enabled()
+    }
+    /// Checks whether or not all other connected GBAs are ready for transfer.
+    pub fn all_ready(&self) -> bool {
+        self.siocnt().gbas_ready()
+    }
+
+    /// Tells the other connected GBAs that we are ready for the next transfer.
+    pub fn mark_ready(&mut self) {
+        // Since we mark ourselves as unready by switching multiplayer modes, we
+        // mark ourselves as ready just by going back into multiplayer mode
+        self.initialize().ok();
+    }
+    /// Tells the other connected GBAs that we aren't ready to transfer yet.
+    ///
+    /// This is accomplished by changing to a different Serial Mode that doesn't
+    /// set the SD pin to HIGH.
+    pub fn mark_unready(&mut self) {
+        // Joybus mode has SD low always (source: https://mgba-emu.github.io/gbatek/#sio-joy-bus-mode)
+        self.rcnt().set_mode(SerialMode::Joybus);
+    }
+
+    /// Starts working out which player we are; keep calling
+    /// `poll_initialize_id` until it has an answer.
+    pub fn begin_initialize_id(&mut self) {
+        self.playerid = None;
+        self.mark_unready();
+        self.write_send_reg(ID_SENTINEL);
+        self.enable_interrupt(true);
+        self.mark_ready();
+    }
+    /// Checks whether a transfer has come back with our sentinel in our own
+    /// slot, at which point the ID the hardware gave us is settled; otherwise
+    /// pushes another transfer along.
+    pub fn poll_initialize_id(&mut self) -> Result<Option<PlayerId>, TransferError> {
+        if self.playerid.is_some() {
+            return Ok(self.playerid);
+        }
+        // Check before starting anything, since starting a transfer clears
+        // what the last one left in the registers.
+        let my_id = self.siocnt().id();
+        if self.comm_reg(my_id).read() == Some(ID_SENTINEL) {
+            self.playerid = Some(my_id);
+            return Ok(self.playerid);
+        }
+        match self.start_transfer() {
+            // Either the parent beat us to it, or the others are lagging and
+            // we just need to wait for them.
+            Ok(())
+            | Err(TransferError::AlreadyInProgress)
+            | Err(TransferError::FailedReadyCheck) => {}
+            Err(other) => return Err(other),
+        }
+        Ok(None)
+    }
+}
+
+/// A `MultiplayerPort` together with the `TransferQueues` its serial
+/// interrupt fills, which is everything the lobby and netplay need from the
+/// link.
+///
+/// Whoever owns the interrupt has to call `TransferQueues::on_transfer` from
+/// it; against a `SimulatedLink` that's `SimulatedLink::run_interrupts`.
+pub struct MultiplayerLink<'q, B> {
+    port: MultiplayerPort<B>,
+    queues: &'q TransferQueues,
+    timeout_millis: u32,
+    /// The transfer count the last time `check_connection` saw it move, and
+    /// when that was.
+    last_progress: (u16, u32),
+}
+
+impl<'q, B: RegisterBackend> MultiplayerLink<'q, B> {
+    pub fn new(port: MultiplayerPort<B>, queues: &'q TransferQueues) -> Self {
+        Clock::with_backend(port.backend).start();
+        Self {
+            port,
+            queues,
+            timeout_millis: DEFAULT_TIMEOUT_MILLIS,
+            last_progress: (0, 0),
+        }
+    }
+    pub fn port(&self) -> &MultiplayerPort<B> {
+        &self.port
+    }
+    pub fn clock(&self) -> Clock<B> {
+        Clock::with_backend(self.port.backend)
+    }
+    /// Sets how long blocking operations wait before failing with
+    /// `TransferError::Timeout`.
+    pub fn set_timeout_millis(&mut self, millis: u32) {
+        self.timeout_millis = millis;
+    }
+    pub fn timeout_millis(&self) -> u32 {
+        self.timeout_millis
+    }
+
+    pub fn write_send_reg(&mut self, data: u16) {
+        self.port.write_send_reg(data)
+    }
+    /// What `player` sent in the last transfer, falling back on what the
+    /// interrupt saw while a new transfer is clearing the registers.
+    pub fn read_player_reg(&self, player: PlayerId) -> Option<u16> {
+        self.port
+            .read_player_reg(player)
+            .or_else(|| self.queues.last(player))
+    }
+    /// Pops the oldest completed transfer recorded by the interrupt, as the
+    /// raw values of each player's register.
+    ///
+    /// Unlike `read_player_reg`, this doesn't miss transfers that complete
+    /// between two reads, as long as it is called often enough to keep the
+    /// queue from filling up.
+    pub fn next_transfer(&mut self) -> Option<[u16; 4]> {
+        self.queues.next_transfer()
+    }
+    /// Queues `word` to go out in a later transfer, returning `false` if the
+    /// queue is full.
+    ///
+    /// The interrupt loads one queued word into the send register after each
+    /// transfer, so every queued word is sent exactly once; when the queue
+    /// runs dry the last word just keeps getting resent.
+    pub fn queue_send(&mut self, word: u16) -> bool {
+        self.queues.queue_send(word)
+    }
+    /// How many words passed to `queue_send` haven't been sent yet.
+    pub fn queued_sends(&self) -> usize {
+        self.queues.queued_sends()
+    }
+    /// Discards every transfer recorded so far.
+    pub fn clear_transfers(&mut self) {
+        self.queues.clear_transfers()
+    }
+    /// How many transfers have completed, wrapping.
+    pub fn transfer_count(&self) -> u16 {
+        self.queues.count()
+    }
+    /// Which players had data in the last transfer; after `initialize_id`
+    /// this is every connected GBA.
+    pub fn connected_players(&self) -> [bool; 4] {
+        PlayerId::ALL.map(|id| self.read_player_reg(id).is_some())
+    }
+    /// Whether `player` has dropped out, going by the transfers the interrupt
+    /// has seen. Players that were never connected count as dropped.
+    pub fn connection_state(&self, player: PlayerId) -> ConnectionState {
+        if self.queues.missed_transfers(player) >= DROP_TRANSFERS {
+            ConnectionState::Dropped
+        } else {
+            ConnectionState::Connected
+        }
+    }
+    /// Checks that everyone in `expected` is still around, failing once one
+    /// of them has dropped or once no transfer has completed for the timeout.
+    ///
+    /// A child can't see other GBAs drop if the parent stops transferring, so
+    /// this should be called regularly by anything waiting on the link.
+    pub fn check_connection(&mut self, expected: [bool; 4]) -> Result<(), TransferError> {
+        for id in PlayerId::ALL {
+            if expected[id as usize]
+                && Some(id) != self.port.id()
+                && self.connection_state(id) == ConnectionState::Dropped
+            {
+                return Err(TransferError::Disconnected(id));
+            }
+        }
+        let count = self.queues.count();
+        let now = self.clock().now();
+        let (last_count, last_time) = self.last_progress;
+        if count != last_count {
+            self.last_progress = (count, now);
+        } else if now.wrapping_sub(last_time) > millis_to_ticks(self.timeout_millis) {
+            return Err(TransferError::Timeout);
+        }
+        Ok(())
+    }
+    /// Restarts the clock `check_connection` uses to notice transfers have
+    /// stopped, for after deliberately pausing the link.
+    pub fn reset_connection_timer(&mut self) {
+        self.last_progress = (self.queues.count(), self.clock().now());
+    }
+
+    /// See `MultiplayerPort::begin_initialize_id`.
+    pub fn begin_initialize_id(&mut self) {
+        self.port.begin_initialize_id()
+    }
+    /// See `MultiplayerPort::poll_initialize_id`.
+    pub fn poll_initialize_id(&mut self) -> Result<Option<PlayerId>, TransferError> {
+        self.port.poll_initialize_id()
+    }
+    pub fn start_transfer(&self) -> Result<(), TransferError> {
+        self.port.start_transfer()
+    }
+    pub fn enable_interrupt(&self, should_enable: bool) {
+        self.port.enable_interrupt(should_enable)
+    }
+    pub fn interrupt_enabled(&self) -> bool {
+        self.port.interrupt_enabled()
+    }
+    /// Checks whether or not all other connected GBAs are ready for transfer.
+    pub fn all_ready(&self) -> bool {
+        self.port.all_ready()
+    }
+    /// Tells the other connected GBAs that we are ready for the next transfer.
+    pub fn mark_ready(&mut self) {
+        self.port.mark_ready()
+    }
+    /// Tells the other connected GBAs that we aren't ready to transfer yet.
+    pub fn mark_unready(&mut self) {
+        self.port.mark_unready()
+    }
+    pub fn is_parent(&self) -> bool {
+        self.port.is_parent()
+    }
+    pub fn id(&self) -> Option<PlayerId> {
+        self.port.id()
+    }
+}
+
+pub struct MultiplayerSiocnt<B> {
+    inner: SiocntWrapper<B>,
+}
+
+crate::method_wraps!(MultiplayerSiocnt<B>, inner, SiocntWrapper);
+
+/*
+  Bit   Expl.
+  0-1   Baud Rate     (0-3: 9600,38400,57600,115200 bps)
+  2     SI-Terminal   (0=Parent, 1=Child)                  (Read Only)
+  3     SD-Terminal   (0=Bad connection, 1=All GBAs Ready) (Read Only)
+  4-5   Multi-Player ID     (0=Parent, 1-3=1st-3rd child)  (Read Only)
+  6     Multi-Player Error  (0=Normal, 1=Error)            (Read Only)
+  7     Start/Busy Bit      (0=Inactive, 1=Start/Busy) (Read Only for Slaves)
+  8-11  Not used            (R/W, should be 0)
+  12    Must be "0" for Multi-Player mode
+  13    Must be "1" for Multi-Player mode
+  14    IRQ Enable          (0=Disable, 1=Want IRQ upon completion)
+  15    Not used            (Read only, always 0)
+*/
+impl<B: RegisterBackend + Default> MultiplayerSiocnt<B> {
+    pub fn get() -> Self {
+        Self::with_backend(B::default())
+    }
+}
+
+impl<B> MultiplayerSiocnt<B> {
+    pub const fn with_backend(backend: B) -> Self {
+        Self {
+            inner: SiocntWrapper::with_backend(backend),
+        }
+    }
+}
+
+impl<B: RegisterBackend> MultiplayerSiocnt<B> {
+    pub fn baud_rate(&self) -> BaudRate {
+        let v = self.read();
+        let bits = (v & 3) as u8;
+        unsafe { mem::transmute(bits) }
+    }
+
+    pub fn set_baud_rate(&self, rate: BaudRate) {
+        let old = self.read();
+        let new = (old & !3) | rate as u16;
+        self.write(new)
+    }
+
+    pub fn is_child(&self) -> bool {
+        self.read_bit(2)
+    }
+
+    pub fn is_parent(&self) -> bool {
+        !self.is_child()
+    }
+    pub fn gbas_ready(&self) -> bool {
+        self.read_bit(3)
+    }
+
+    pub fn id(&self) -> PlayerId {
+        let regval = self.read();
+        let raw = ((regval & (3 << 4)) >> 4) as u8;
+        unsafe { mem::transmute(raw) }
+    }
+
+    pub fn error_flag(&self) -> bool {
+        self.read_bit(6)
+    }
+
+    pub fn start_transfer(&self) {
+        self.write_bit(7, true)
+    }
+    pub fn busy(&self) -> bool {
+        self.read_bit(7)
+    }
+}
+
+pub struct MultiplayerCommReg<B> {
+    player_id: PlayerId,
+    reg: RegisterWrapper<B>,
+}
+
+impl<B: RegisterBackend + Default> MultiplayerCommReg<B> {
+    pub fn new(player_id: PlayerId) -> Self {
+        Self::with_backend(player_id, B::default())
+    }
+    /// Every player's register, in `PlayerId::ALL` order.
+    pub fn all() -> [Self; 4] {
+        PlayerId::ALL.map(Self::new)
+    }
+}
+
+impl<B> MultiplayerCommReg<B> {
+    pub const fn with_backend(player_id: PlayerId, backend: B) -> Self {
+        let addr = SIOMULTI0 + 2 * player_id as usize;
+        Self {
+            player_id,
+            reg: RegisterWrapper::with_backend(addr, backend),
+        }
+    }
+    pub const fn player_id(&self) -> PlayerId {
+        self.player_id
+    }
+}
+
+impl<B: RegisterBackend> MultiplayerCommReg<B> {
+    pub fn read(&self) -> Option<u16> {
+        let raw = self.raw_read();
+        if raw == 0xFFFF {
+            None
+        } else {
+            Some(raw)
+        }
+    }
+    pub fn raw_read(&self) -> u16 {
+        self.reg.read()
+    }
+    pub fn is_transfering(&self) -> bool {
+        self.raw_read() == 0xFFFF
+    }
+}
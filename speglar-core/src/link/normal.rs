@@ -0,0 +1,142 @@
+use super::*;
+
+/// Which side drives the shift clock; a link needs exactly one of each.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum ClockSource {
+    #[default]
+    Internal,
+    External,
+}
+
+/// How fast the shift clock runs when we're the one driving it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum ClockSpeed {
+    #[default]
+    K256,
+    M2,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum TransferLength {
+    #[default]
+    Bits8,
+    Bits32,
+}
+
+pub struct NormalSiocnt<B> {
+    inner: SiocntWrapper<B>,
+}
+
+crate::method_wraps!(NormalSiocnt<B>, inner, SiocntWrapper);
+
+/*
+  Bit   Expl.
+  0     Shift Clock (SC)        (0=External, 1=Internal)
+  1     Internal Shift Clock    (0=256KHz, 1=2MHz)
+  2     SI State (opponents SO) (0=Low, 1=High/None) --- (Read Only)
+  3     SO during inactivity    (0=Low, 1=High) (applied ONLY when Bit7=0)
+  4-6   Not used                (Read only, always 0 ???)
+  7     Start Bit               (0=Inactive/Ready, 1=Start/Active)
+  8-11  Not used                (R/W, should be 0)
+  12    Transfer Length         (0=8bit, 1=32bit)
+  13    Must be "0" for Normal Mode
+  14    IRQ Enable              (0=Disable, 1=Want IRQ upon completion)
+  15    Not used                (Read only, always 0)
+*/
+impl<B: RegisterBackend + Default> NormalSiocnt<B> {
+    pub fn get() -> Self {
+        Self::with_backend(B::default())
+    }
+}
+
+impl<B> NormalSiocnt<B> {
+    pub const fn with_backend(backend: B) -> Self {
+        Self {
+            inner: SiocntWrapper::with_backend(backend),
+        }
+    }
+}
+
+impl<B: RegisterBackend> NormalSiocnt<B> {
+    pub fn clock(&self) -> ClockSource {
+        if self.read_bit(0) {
+            ClockSource::Internal
+        } else {
+            ClockSource::External
+        }
+    }
+    pub fn set_clock(&self, clock: ClockSource) {
+        self.write_bit(0, clock == ClockSource::Internal)
+    }
+    pub fn speed(&self) -> ClockSpeed {
+        if self.read_bit(1) {
+            ClockSpeed::M2
+        } else {
+            ClockSpeed::K256
+        }
+    }
+    pub fn set_speed(&self, speed: ClockSpeed) {
+        self.write_bit(1, speed == ClockSpeed::M2)
+    }
+    pub fn si_state(&self) -> bool {
+        self.read_bit(2)
+    }
+    pub fn set_so_inactive(&self, high: bool) {
+        self.write_bit(3, high)
+    }
+    pub fn start_transfer(&self) {
+        self.write_bit(7, true)
+    }
+    pub fn busy(&self) -> bool {
+        self.read_bit(7)
+    }
+    pub fn length(&self) -> TransferLength {
+        if self.read_bit(12) {
+            TransferLength::Bits32
+        } else {
+            TransferLength::Bits8
+        }
+    }
+    pub fn set_length(&self, length: TransferLength) {
+        self.write_bit(12, length == TransferLength::Bits32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::tests::RegisterFile;
+
+    #[test]
+    fn test_round_trip() {
+        let regs = RegisterFile::default();
+        let siocnt = NormalSiocnt::with_backend(&regs);
+        siocnt.set_mode(SerialMode::Normal);
+        for clock in [ClockSource::Internal, ClockSource::External] {
+            for speed in [ClockSpeed::K256, ClockSpeed::M2] {
+                for length in [TransferLength::Bits8, TransferLength::Bits32] {
+                    siocnt.set_clock(clock);
+                    siocnt.set_speed(speed);
+                    siocnt.set_length(length);
+                    assert_eq!(siocnt.clock(), clock);
+                    assert_eq!(siocnt.speed(), speed);
+                    assert_eq!(siocnt.length(), length);
+                    assert_eq!(siocnt.mode(), SerialMode::Normal);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bits() {
+        let regs = RegisterFile::default();
+        let siocnt = NormalSiocnt::with_backend(&regs);
+        siocnt.set_clock(ClockSource::Internal);
+        assert_eq!(siocnt.read(), 1);
+        siocnt.set_speed(ClockSpeed::M2);
+        assert_eq!(siocnt.read(), 0b11);
+        siocnt.set_clock(ClockSource::External);
+        siocnt.set_length(TransferLength::Bits32);
+        assert_eq!(siocnt.read(), 0b10 | (1 << 12));
+    }
+}
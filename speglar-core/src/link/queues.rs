@@ -0,0 +1,111 @@
+use portable_atomic::{AtomicU16, Ordering};
+
+use super::multiplayer::{MultiplayerCommReg, PlayerId};
+use super::spsc::SpscQueue;
+use super::*;
+
+pub const TRANSFER_QUEUE_LEN: usize = 16;
+pub const SEND_QUEUE_LEN: usize = 32;
+/// How many transfers in a row a player's register has to read `0xFFFF` for
+/// before we consider them dropped.
+pub const DROP_TRANSFERS: u16 = 16;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_DATA: AtomicU16 = AtomicU16::new(0xFFFF);
+#[allow(clippy::declare_interior_mutable_const)]
+const NEVER_SEEN: AtomicU16 = AtomicU16::new(DROP_TRANSFERS);
+
+/// Everything the serial interrupt records about completed transfers, and
+/// the words it sends in the ones after.
+///
+/// The interrupt calls `on_transfer` and everything else reads what it left,
+/// so only one side ever writes each field; that keeps this to plain atomic
+/// loads and stores, which is all the GBA has.
+pub struct TransferQueues {
+    /// Each player's register as of the last transfer.
+    last: [AtomicU16; 4],
+    /// Every completed transfer, read by `next_transfer`; transfers that
+    /// arrive while the queue is full are dropped.
+    transfers: SpscQueue<[u16; 4], TRANSFER_QUEUE_LEN>,
+    /// Words waiting to be loaded into `SIOMLT_SEND`, written by `queue_send`.
+    sends: SpscQueue<u16, SEND_QUEUE_LEN>,
+    /// How many transfers in a row each player's register has read `0xFFFF`.
+    missed: [AtomicU16; 4],
+    /// How many transfers have completed, wrapping.
+    count: AtomicU16,
+}
+
+impl Default for TransferQueues {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransferQueues {
+    pub const fn new() -> Self {
+        Self {
+            last: [NO_DATA; 4],
+            transfers: SpscQueue::new(),
+            sends: SpscQueue::new(),
+            missed: [NEVER_SEEN; 4],
+            count: AtomicU16::new(0),
+        }
+    }
+
+    /// Records the transfer that just completed on `backend` and loads the
+    /// next queued word to send; call this from the serial interrupt.
+    pub fn on_transfer<B: RegisterBackend>(&self, backend: B) {
+        self.count.store(
+            self.count.load(Ordering::Relaxed).wrapping_add(1),
+            Ordering::Release,
+        );
+        let transfer = PlayerId::ALL.map(|id| {
+            let raw = MultiplayerCommReg::with_backend(id, backend).raw_read();
+            self.last[id as usize].store(raw, Ordering::Release);
+            let missed = &self.missed[id as usize];
+            if raw == 0xFFFF {
+                let prev = missed.load(Ordering::Relaxed);
+                missed.store(prev.saturating_add(1), Ordering::Release);
+            } else {
+                missed.store(0, Ordering::Release);
+            }
+            raw
+        });
+        self.transfers.push(transfer);
+        if let Some(word) = self.sends.pop() {
+            backend.write(SIOMLT_SEND, word);
+        }
+    }
+
+    /// Pops the oldest completed transfer, as the raw values of each player's
+    /// register.
+    pub fn next_transfer(&self) -> Option<[u16; 4]> {
+        self.transfers.pop()
+    }
+    /// Discards every transfer recorded so far.
+    pub fn clear_transfers(&self) {
+        self.transfers.clear()
+    }
+    /// Queues `word` to go out in a later transfer, returning `false` if the
+    /// queue is full.
+    pub fn queue_send(&self, word: u16) -> bool {
+        self.sends.push(word)
+    }
+    /// How many words passed to `queue_send` haven't been sent yet.
+    pub fn queued_sends(&self) -> usize {
+        self.sends.len()
+    }
+    /// What `player` sent in the last transfer, if anything.
+    pub fn last(&self, player: PlayerId) -> Option<u16> {
+        let raw = self.last[player as usize].load(Ordering::Acquire);
+        (raw != 0xFFFF).then_some(raw)
+    }
+    /// How many transfers in a row `player` has sent nothing in.
+    pub fn missed_transfers(&self, player: PlayerId) -> u16 {
+        self.missed[player as usize].load(Ordering::Acquire)
+    }
+    /// How many transfers have completed, wrapping.
+    pub fn count(&self) -> u16 {
+        self.count.load(Ordering::Acquire)
+    }
+}
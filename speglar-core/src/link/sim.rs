@@ -0,0 +1,506 @@
+use core::cell::RefCell;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use super::clock::{TM2CNT_L, TM3CNT_L};
+use super::multiplayer::PlayerId;
+use super::queues::TransferQueues;
+use super::*;
+use crate::{read_bit, write_bit};
+
+/// SIOCNT bits that are the hardware's to set; see `MultiplayerSiocnt`.
+const SIOCNT_READ_ONLY: u16 = 0b1111_1100 | (1 << 15);
+
+/// One GBA plugged into a `SimulatedLink`.
+#[derive(Clone, Debug, Default)]
+struct SimulatedGbaState {
+    plugged_in: bool,
+    /// Every register that's only ever read back as it was written.
+    registers: BTreeMap<usize, u16>,
+    /// The writable bits of SIOCNT.
+    siocnt: u16,
+    /// The multiplayer ID the last transfer gave this GBA.
+    id: u16,
+    received: [u16; 4],
+    interrupt: bool,
+}
+
+impl SimulatedGbaState {
+    fn register(&self, addr: usize) -> u16 {
+        self.registers.get(&addr).copied().unwrap_or(0)
+    }
+    /// Whether this GBA will take part in the next transfer, which also makes
+    /// it pull SD high.
+    fn is_ready(&self) -> bool {
+        let rcnt = self.register(RCNT);
+        self.plugged_in
+            && !read_bit(rcnt, 15)
+            && read_bit(self.siocnt, 13)
+            && !read_bit(self.siocnt, 12)
+    }
+}
+
+#[derive(Debug, Default)]
+struct SimulatedLinkState {
+    gbas: Vec<SimulatedGbaState>,
+    busy: bool,
+    /// What every GBA's timers 2 and 3 read as together.
+    ticks: u32,
+}
+
+/// An in-memory link cable with up to four GBAs on it, for running the
+/// multiplayer code on the host.
+///
+/// Each GBA's registers are reached through `gba`, which hands out a
+/// `RegisterBackend`. Only multiplayer mode is modelled: the parent setting
+/// the start bit begins a transfer, which then runs until `complete_transfer`
+/// is called, as if the interrupt had just fired everywhere. Timers 2 and 3
+/// read as the clock in `link::clock`, which only moves on `advance_clock`.
+#[derive(Debug, Default)]
+pub struct SimulatedLink {
+    state: RefCell<SimulatedLinkState>,
+}
+
+impl SimulatedLink {
+    /// A link with `count` GBAs plugged in, the first being the parent.
+    pub fn new(count: usize) -> Self {
+        assert!((1..=4).contains(&count), "A link has 1 to 4 GBAs");
+        let gbas = (0..4)
+            .map(|idx| SimulatedGbaState {
+                plugged_in: idx < count,
+                received: [0xFFFF; 4],
+                ..Default::default()
+            })
+            .collect();
+        Self {
+            state: RefCell::new(SimulatedLinkState {
+                gbas,
+                busy: false,
+                ticks: 0,
+            }),
+        }
+    }
+
+    /// The registers of the GBA in `slot`, which is also the ID the hardware
+    /// hands it.
+    pub fn gba(&self, slot: PlayerId) -> SimulatedGba<'_> {
+        SimulatedGba { link: self, slot }
+    }
+
+    pub fn plug_in(&self, slot: PlayerId) {
+        self.state.borrow_mut().gbas[slot as usize].plugged_in = true;
+    }
+    /// Pulls the GBA in `slot` off the link; it sends nothing from the next
+    /// transfer on.
+    pub fn unplug(&self, slot: PlayerId) {
+        self.state.borrow_mut().gbas[slot as usize].plugged_in = false;
+    }
+
+    /// Whether a transfer has started and not yet completed.
+    pub fn is_busy(&self) -> bool {
+        self.state.borrow().busy
+    }
+
+    /// Finishes the transfer in progress, handing every GBA on the link what
+    /// everyone sent. Returns `false` if there wasn't one.
+    pub fn complete_transfer(&self) -> bool {
+        let mut state = self.state.borrow_mut();
+        if !state.busy {
+            return false;
+        }
+        state.busy = false;
+        let mut sent = [0xFFFF; 4];
+        for (idx, gba) in state.gbas.iter().enumerate() {
+            if gba.is_ready() {
+                sent[idx] = gba.register(SIOMLT_SEND);
+            }
+        }
+        for (idx, gba) in state.gbas.iter_mut().enumerate() {
+            if !gba.plugged_in {
+                continue;
+            }
+            gba.received = sent;
+            gba.id = idx as u16;
+            if read_bit(gba.siocnt, 14) {
+                gba.interrupt = true;
+            }
+        }
+        true
+    }
+
+    /// Runs the serial interrupt on every GBA that's had one since the last
+    /// call, recording its transfer into its own entry in `queues`, indexed
+    /// by slot.
+    pub fn run_interrupts(&self, queues: &[TransferQueues]) {
+        for (slot, queues) in PlayerId::ALL.into_iter().zip(queues) {
+            if self.take_interrupt(slot) {
+                queues.on_transfer(self.gba(slot));
+            }
+        }
+    }
+
+    /// Moves every GBA's clock on by `ticks`.
+    pub fn advance_clock(&self, ticks: u32) {
+        let mut state = self.state.borrow_mut();
+        state.ticks = state.ticks.wrapping_add(ticks);
+    }
+
+    /// Whether the GBA in `slot` has had a serial interrupt since the last
+    /// call, clearing it.
+    pub fn take_interrupt(&self, slot: PlayerId) -> bool {
+        let mut state = self.state.borrow_mut();
+        core::mem::take(&mut state.gbas[slot as usize].interrupt)
+    }
+
+    fn read(&self, slot: PlayerId, addr: usize) -> u16 {
+        let state = self.state.borrow();
+        let gba = &state.gbas[slot as usize];
+        match addr {
+            SIOCNT => {
+                let all_ready = state
+                    .gbas
+                    .iter()
+                    .filter(|other| other.plugged_in)
+                    .all(SimulatedGbaState::is_ready);
+                let mut value = gba.siocnt;
+                value = write_bit(value, 2, slot != PlayerId::Parent);
+                value = write_bit(value, 3, all_ready);
+                value |= gba.id << 4;
+                write_bit(value, 7, state.busy)
+            }
+            _ if (SIOMULTI0..SIOMULTI0 + 8).contains(&addr) => gba.received[(addr - SIOMULTI0) / 2],
+            TM2CNT_L => state.ticks as u16,
+            TM3CNT_L => (state.ticks >> 16) as u16,
+            _ => gba.register(addr),
+        }
+    }
+
+    fn write(&self, slot: PlayerId, addr: usize, value: u16) {
+        let mut state = self.state.borrow_mut();
+        if addr != SIOCNT {
+            state.gbas[slot as usize].registers.insert(addr, value);
+            return;
+        }
+        state.gbas[slot as usize].siocnt = value & !SIOCNT_READ_ONLY;
+        // Only the parent gets to start transfers.
+        let starting = slot == PlayerId::Parent && read_bit(value, 7) && !state.busy;
+        if starting && state.gbas[slot as usize].is_ready() {
+            state.busy = true;
+            for gba in state.gbas.iter_mut() {
+                gba.received = [0xFFFF; 4];
+            }
+        }
+    }
+}
+
+/// The registers of one GBA on a `SimulatedLink`.
+#[derive(Clone, Copy, Debug)]
+pub struct SimulatedGba<'a> {
+    link: &'a SimulatedLink,
+    slot: PlayerId,
+}
+
+impl RegisterBackend for SimulatedGba<'_> {
+    fn read(&self, addr: usize) -> u16 {
+        self.link.read(self.slot, addr)
+    }
+    fn write(&self, addr: usize, value: u16) {
+        self.link.write(self.slot, addr, value)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::link::clock::{millis_to_ticks, Clock, TICKS_PER_SECOND};
+    use crate::link::multiplayer::{
+        ConnectionState, MultiplayerLink, MultiplayerPort, TransferError, DEFAULT_TIMEOUT_MILLIS,
+    };
+    use crate::link::queues::DROP_TRANSFERS;
+    use crate::packet::{encode_frame, FrameDecoder, FrameEvent};
+
+    fn ports(link: &SimulatedLink, count: usize) -> Vec<MultiplayerPort<SimulatedGba<'_>>> {
+        PlayerId::ALL[..count]
+            .iter()
+            .map(|id| MultiplayerPort::new(link.gba(*id), BaudRate::B115200).unwrap())
+            .collect()
+    }
+
+    /// Runs `initialize_id` on every port together, like consoles that were
+    /// all switched on at once.
+    fn initialize_ids(link: &SimulatedLink, ports: &mut [MultiplayerPort<SimulatedGba<'_>>]) {
+        for port in ports.iter_mut() {
+            port.begin_initialize_id();
+        }
+        for _ in 0..8 {
+            let mut done = true;
+            for port in ports.iter_mut() {
+                done &= port.poll_initialize_id().unwrap().is_some();
+            }
+            if done {
+                return;
+            }
+            link.complete_transfer();
+        }
+        panic!("IDs were never settled");
+    }
+
+    /// The first `queues.len()` GBAs on `link`, with their IDs settled and
+    /// their interrupts feeding their entry in `queues`.
+    pub(crate) fn linked<'a>(
+        link: &'a SimulatedLink,
+        queues: &'a [TransferQueues],
+    ) -> Vec<MultiplayerLink<'a, SimulatedGba<'a>>> {
+        let mut links: Vec<_> = PlayerId::ALL
+            .iter()
+            .zip(queues)
+            .map(|(id, queues)| {
+                let port = MultiplayerPort::new(link.gba(*id), BaudRate::B115200).unwrap();
+                MultiplayerLink::new(port, queues)
+            })
+            .collect();
+        for serial in links.iter_mut() {
+            serial.begin_initialize_id();
+        }
+        for _ in 0..8 {
+            let mut done = true;
+            for serial in links.iter_mut() {
+                done &= serial.poll_initialize_id().unwrap().is_some();
+            }
+            if done {
+                return links;
+            }
+            finish_transfer(link, queues);
+        }
+        panic!("IDs were never settled");
+    }
+
+    /// Completes the transfer in progress, if there is one, and runs the
+    /// interrupts it fired.
+    pub(crate) fn finish_transfer(link: &SimulatedLink, queues: &[TransferQueues]) {
+        link.complete_transfer();
+        link.run_interrupts(queues);
+    }
+
+    /// Registers that read back exactly what was written, for checking how a
+    /// wrapper packs its bits with nothing else on the link.
+    #[derive(Default)]
+    pub(crate) struct RegisterFile(RefCell<BTreeMap<usize, u16>>);
+
+    impl RegisterBackend for &RegisterFile {
+        fn read(&self, addr: usize) -> u16 {
+            self.0.borrow().get(&addr).copied().unwrap_or(0)
+        }
+        fn write(&self, addr: usize, value: u16) {
+            self.0.borrow_mut().insert(addr, value);
+        }
+    }
+
+    /// Has the parent run one transfer with everyone sending `words`.
+    fn exchange(
+        link: &SimulatedLink,
+        ports: &mut [MultiplayerPort<SimulatedGba<'_>>],
+        words: &[u16],
+    ) -> Result<(), TransferError> {
+        for (port, word) in ports.iter_mut().zip(words) {
+            port.write_send_reg(*word);
+        }
+        let retvl = ports[0].start_transfer();
+        link.complete_transfer();
+        retvl
+    }
+
+    #[test]
+    fn test_initialize_id() {
+        let link = SimulatedLink::new(3);
+        let mut ports = ports(&link, 3);
+        assert!(ports[0].is_parent());
+        assert!(!ports[1].is_parent());
+        initialize_ids(&link, &mut ports);
+        let ids: Vec<_> = ports.iter().map(|port| port.id()).collect();
+        assert_eq!(
+            ids,
+            [
+                Some(PlayerId::Parent),
+                Some(PlayerId::P1),
+                Some(PlayerId::P2)
+            ]
+        );
+        // Turning the interrupt on for that gets it fired everywhere.
+        assert!(link.take_interrupt(PlayerId::P2));
+        assert!(!link.take_interrupt(PlayerId::P2));
+    }
+
+    #[test]
+    fn test_transfer() {
+        let link = SimulatedLink::new(3);
+        let mut ports = ports(&link, 3);
+        ports[2].write_send_reg(0x2222);
+        // Children can't start transfers.
+        assert_eq!(ports[1].start_transfer(), Ok(()));
+        assert!(!link.is_busy());
+
+        ports[0].write_send_reg(0x0000);
+        ports[1].write_send_reg(0x1111);
+        assert_eq!(ports[0].start_transfer(), Ok(()));
+        assert!(link.is_busy());
+        assert!(ports[1].comm_reg(PlayerId::Parent).is_transfering());
+        assert_eq!(
+            ports[2].start_transfer(),
+            Err(TransferError::AlreadyInProgress)
+        );
+        assert!(link.complete_transfer());
+        assert!(!link.complete_transfer());
+        for port in &ports {
+            let received = PlayerId::ALL.map(|id| port.read_player_reg(id));
+            assert_eq!(received, [Some(0x0000), Some(0x1111), Some(0x2222), None]);
+        }
+    }
+
+    #[test]
+    fn test_unready_and_unplugged() {
+        let link = SimulatedLink::new(3);
+        let mut ports = ports(&link, 3);
+        ports[1].mark_unready();
+        assert!(!ports[0].all_ready());
+        // The transfer still happens, just without them.
+        assert_eq!(
+            exchange(&link, &mut ports, &[1, 2, 3]),
+            Err(TransferError::FailedReadyCheck)
+        );
+        assert_eq!(ports[0].read_player_reg(PlayerId::P1), None);
+        assert_eq!(ports[0].read_player_reg(PlayerId::P2), Some(3));
+
+        ports[1].mark_ready();
+        assert_eq!(exchange(&link, &mut ports, &[1, 2, 3]), Ok(()));
+        assert_eq!(ports[0].read_player_reg(PlayerId::P1), Some(2));
+
+        link.unplug(PlayerId::P2);
+        assert_eq!(exchange(&link, &mut ports, &[4, 5, 6]), Ok(()));
+        let received = PlayerId::ALL.map(|id| ports[1].read_player_reg(id));
+        assert_eq!(received, [Some(4), Some(5), None, None]);
+
+        // Someone plugging in partway through can still get their ID.
+        link.plug_in(PlayerId::P3);
+        let mut late = MultiplayerPort::new(link.gba(PlayerId::P3), BaudRate::B115200).unwrap();
+        late.begin_initialize_id();
+        assert_eq!(late.poll_initialize_id(), Ok(None));
+        exchange(&link, &mut ports, &[7, 8, 9]).unwrap();
+        assert_eq!(late.poll_initialize_id(), Ok(Some(PlayerId::P3)));
+        assert_eq!(ports[0].read_player_reg(PlayerId::P3), Some(0xFEAD));
+    }
+
+    #[test]
+    fn test_frames_over_link() {
+        let link = SimulatedLink::new(2);
+        let mut ports = ports(&link, 2);
+        initialize_ids(&link, &mut ports);
+        let mut words = Vec::new();
+        encode_frame(3, &[0xFF, 0xF1, 0x00, 0x42], false, &mut words);
+        let mut decoder = FrameDecoder::new();
+        let mut events = Vec::new();
+        for word in words {
+            exchange(&link, &mut ports, &[word, 0]).unwrap();
+            let received = ports[1].read_player_reg(PlayerId::Parent).unwrap();
+            events.extend(decoder.push(received));
+        }
+        match events.as_slice() {
+            [FrameEvent::Frame { header, payload }] => {
+                assert_eq!(header.seq, 3);
+                assert_eq!(payload, &[0xFF, 0xF1, 0x00, 0x42]);
+            }
+            other => panic!("Expected a single frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_queues() {
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        links[0].clear_transfers();
+        links[1].clear_transfers();
+        assert!(links[1].queue_send(0x1111));
+        assert!(links[1].queue_send(0x2222));
+        assert_eq!(links[1].queued_sends(), 2);
+        links[1].write_send_reg(0x1000);
+        for _ in 0..4 {
+            links[0].start_transfer().unwrap();
+            finish_transfer(&link, &queues);
+        }
+        // Each transfer loads the next queued word, which goes out in the one
+        // after; the last one keeps going once the queue runs dry.
+        let sent: Vec<_> = core::iter::from_fn(|| links[0].next_transfer())
+            .map(|transfer| transfer[1])
+            .collect();
+        assert_eq!(sent, [0x1000, 0x1111, 0x2222, 0x2222]);
+        assert_eq!(links[1].queued_sends(), 0);
+        assert_eq!(links[1].next_transfer().map(|t| t[1]), Some(0x1000));
+    }
+
+    #[test]
+    fn test_check_connection() {
+        let link = SimulatedLink::new(3);
+        let queues = [
+            TransferQueues::new(),
+            TransferQueues::new(),
+            TransferQueues::new(),
+        ];
+        let mut links = linked(&link, &queues);
+        let expected = links[0].connected_players();
+        assert_eq!(expected, [true, true, true, false]);
+        assert_eq!(links[0].check_connection(expected), Ok(()));
+        assert_eq!(
+            links[0].connection_state(PlayerId::P3),
+            ConnectionState::Dropped
+        );
+
+        link.unplug(PlayerId::P2);
+        for _ in 0..DROP_TRANSFERS {
+            assert_eq!(links[0].check_connection(expected), Ok(()));
+            links[0].start_transfer().unwrap();
+            finish_transfer(&link, &queues);
+        }
+        assert_eq!(
+            links[0].check_connection(expected),
+            Err(TransferError::Disconnected(PlayerId::P2))
+        );
+        assert_eq!(
+            links[1].check_connection([true, true, false, false]),
+            Ok(())
+        );
+
+        // With the parent quiet, the children only notice through the clock.
+        link.advance_clock(millis_to_ticks(DEFAULT_TIMEOUT_MILLIS));
+        assert_eq!(
+            links[1].check_connection([true, true, false, false]),
+            Ok(())
+        );
+        link.advance_clock(1);
+        assert_eq!(
+            links[1].check_connection([true, true, false, false]),
+            Err(TransferError::Timeout)
+        );
+        links[1].reset_connection_timer();
+        assert_eq!(
+            links[1].check_connection([true, true, false, false]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_clock() {
+        let link = SimulatedLink::new(2);
+        let clocks = [
+            Clock::with_backend(link.gba(PlayerId::Parent)),
+            Clock::with_backend(link.gba(PlayerId::P1)),
+        ];
+        clocks[0].start();
+        assert_eq!(clocks[0].now(), 0);
+        // Timer 3 carries timer 2's overflows, and every GBA shares the time.
+        link.advance_clock(0x1_FFFF);
+        link.advance_clock(1);
+        assert_eq!(clocks.map(|clock| clock.now()), [0x2_0000; 2]);
+        assert_eq!(millis_to_ticks(1000), TICKS_PER_SECOND);
+    }
+}
@@ -0,0 +1,203 @@
+//! A fixed-size queue between one writer and one reader, such as an interrupt
+//! and the code it interrupts.
+//!
+//! The GBA can only load and store atomically, so the head and tail are
+//! free-running counters that only one side ever writes each of, and the
+//! items themselves sit in atomics too. Using either end from two places at
+//! once garbles the items rather than being undefined behaviour.
+use portable_atomic::{AtomicU16, AtomicU32, AtomicU8, Ordering};
+
+/// Something that can go in an `SpscQueue`, by being stored in `Slot`.
+pub trait QueueItem: Copy {
+    type Slot;
+    const EMPTY: Self::Slot;
+    fn load(slot: &Self::Slot) -> Self;
+    fn store(slot: &Self::Slot, value: Self);
+}
+
+macro_rules! atomic_item {
+    ($ty:ty, $atomic:ty) => {
+        impl QueueItem for $ty {
+            type Slot = $atomic;
+            #[allow(clippy::declare_interior_mutable_const)]
+            const EMPTY: Self::Slot = <$atomic>::new(0);
+            fn load(slot: &Self::Slot) -> Self {
+                slot.load(Ordering::Relaxed)
+            }
+            fn store(slot: &Self::Slot, value: Self) {
+                slot.store(value, Ordering::Relaxed)
+            }
+        }
+    };
+}
+
+atomic_item!(u8, AtomicU8);
+atomic_item!(u16, AtomicU16);
+atomic_item!(u32, AtomicU32);
+
+impl<T: QueueItem, const N: usize> QueueItem for [T; N] {
+    type Slot = [T::Slot; N];
+    const EMPTY: Self::Slot = [const { T::EMPTY }; N];
+    fn load(slot: &Self::Slot) -> Self {
+        core::array::from_fn(|idx| T::load(&slot[idx]))
+    }
+    fn store(slot: &Self::Slot, value: Self) {
+        for (slot, value) in slot.iter().zip(value) {
+            T::store(slot, value);
+        }
+    }
+}
+
+impl<T: QueueItem, U: QueueItem> QueueItem for (T, U) {
+    type Slot = (T::Slot, U::Slot);
+    const EMPTY: Self::Slot = (T::EMPTY, U::EMPTY);
+    fn load(slot: &Self::Slot) -> Self {
+        (T::load(&slot.0), U::load(&slot.1))
+    }
+    fn store(slot: &Self::Slot, value: Self) {
+        T::store(&slot.0, value.0);
+        U::store(&slot.1, value.1);
+    }
+}
+
+/// Up to `N` items on their way from `push` to `pop`, with items pushed while
+/// it's full dropped.
+///
+/// Only one side should push and only one side should pop or `clear`; on the
+/// GBA that's usually an interrupt and the main loop. `N` has to be a power of
+/// two, so the counters can wrap.
+pub struct SpscQueue<T: QueueItem, const N: usize> {
+    slots: [T::Slot; N],
+    head: AtomicU16,
+    tail: AtomicU16,
+}
+
+impl<T: QueueItem, const N: usize> Default for SpscQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: QueueItem, const N: usize> SpscQueue<T, N> {
+    pub const fn new() -> Self {
+        assert!(
+            N.is_power_of_two() && N <= 1 << 15,
+            "Queue length should be a power of two up to 2^15"
+        );
+        Self {
+            slots: [const { T::EMPTY }; N],
+            head: AtomicU16::new(0),
+            tail: AtomicU16::new(0),
+        }
+    }
+
+    /// Adds `value` to the back, returning `false` if it's full.
+    pub fn push(&self, value: T) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head.wrapping_sub(tail) as usize >= N {
+            return false;
+        }
+        T::store(&self.slots[head as usize % N], value);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+    /// Takes the oldest item off the front.
+    pub fn pop(&self) -> Option<T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if tail == head {
+            return None;
+        }
+        let value = T::load(&self.slots[tail as usize % N]);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+    /// Drops everything pushed so far; only for the side that pops.
+    pub fn clear(&self) {
+        self.tail
+            .store(self.head.load(Ordering::Acquire), Ordering::Release);
+    }
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        head.wrapping_sub(tail) as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        let queue = SpscQueue::<u16, 4>::new();
+        assert_eq!(queue.pop(), None);
+        for value in 1..=4 {
+            assert!(queue.push(value));
+        }
+        // Full, so this one's dropped.
+        assert!(!queue.push(5));
+        assert_eq!(queue.len(), 4);
+        for value in 1..=4 {
+            assert_eq!(queue.pop(), Some(value));
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_wrapping() {
+        let queue = SpscQueue::<[u16; 4], 8>::new();
+        // Enough to take the counters round a few times.
+        for value in 0..0x30000u32 {
+            let item = [value as u16, (value >> 16) as u16, 0xFFFF, 0];
+            assert!(queue.push(item));
+            if value % 3 == 0 {
+                assert!(queue.push(item));
+                assert_eq!(queue.pop(), Some(item));
+            }
+            assert_eq!(queue.pop(), Some(item));
+            assert!(queue.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_clear() {
+        let queue = SpscQueue::<(u32, u8), 4>::new();
+        queue.push((1, 2));
+        queue.push((3, 4));
+        queue.clear();
+        assert_eq!(queue.pop(), None);
+        assert!(queue.push((5, 6)));
+        assert_eq!(queue.pop(), Some((5, 6)));
+    }
+
+    #[test]
+    fn test_threads() {
+        const COUNT: u32 = 100_000;
+        let queue = SpscQueue::<u32, 16>::new();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for value in 0..COUNT {
+                    while !queue.push(value) {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            let mut expected = 0;
+            while expected < COUNT {
+                match queue.pop() {
+                    Some(value) => {
+                        assert_eq!(value, expected);
+                        expected += 1;
+                    }
+                    None => std::thread::yield_now(),
+                }
+            }
+        });
+        assert!(queue.is_empty());
+    }
+}
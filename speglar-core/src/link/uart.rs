@@ -0,0 +1,168 @@
+use core::mem;
+
+use super::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Parity {
+    #[default]
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum DataLength {
+    Seven,
+    #[default]
+    Eight,
+}
+
+pub struct UartSiocnt<B> {
+    inner: SiocntWrapper<B>,
+}
+
+crate::method_wraps!(UartSiocnt<B>, inner, SiocntWrapper);
+
+/*
+  Bit   Expl.
+  0-1   Baud Rate  (0-3: 9600,38400,57600,115200 bps)
+  2     CTS Flag   (0=Send always/blindly, 1=Send only when SC=LOW)
+  3     Parity Control (0=Even, 1=Odd)
+  4     Send Data Flag      (0=Not Full,  1=Full)    (Read Only)
+  5     Receive Data Flag   (0=Not Empty, 1=Empty)   (Read Only)
+  6     Error Flag          (0=No Error,  1=Error)   (Read Only)
+  7     Data Length         (0=7bits,   1=8bits)
+  8     FIFO Enable Flag    (0=Disable, 1=Enable)
+  9     Parity Enable Flag  (0=Disable, 1=Enable)
+  10    Send Enable Flag    (0=Disable, 1=Enable)
+  11    Receive Enable Flag (0=Disable, 1=Enable)
+  12    Must be "1" for UART mode
+  13    Must be "1" for UART mode
+  14    IRQ Enable          (0=Disable, 1=IRQ when any Bit 4/5/6 become set)
+  15    Not used            (Read only, always 0)
+*/
+impl<B: RegisterBackend + Default> UartSiocnt<B> {
+    pub fn get() -> Self {
+        Self::with_backend(B::default())
+    }
+}
+
+impl<B> UartSiocnt<B> {
+    pub const fn with_backend(backend: B) -> Self {
+        Self {
+            inner: SiocntWrapper::with_backend(backend),
+        }
+    }
+}
+
+impl<B: RegisterBackend> UartSiocnt<B> {
+    pub fn baud_rate(&self) -> BaudRate {
+        let v = self.read();
+        let bits = (v & 3) as u8;
+        unsafe { mem::transmute(bits) }
+    }
+    pub fn set_baud_rate(&self, rate: BaudRate) {
+        let old = self.read();
+        let new = (old & !3) | rate as u16;
+        self.write(new)
+    }
+    pub fn cts(&self) -> bool {
+        self.read_bit(2)
+    }
+    pub fn set_cts(&self, cts: bool) {
+        self.write_bit(2, cts)
+    }
+    pub fn parity(&self) -> Parity {
+        match (self.read_bit(9), self.read_bit(3)) {
+            (false, _) => Parity::None,
+            (true, false) => Parity::Even,
+            (true, true) => Parity::Odd,
+        }
+    }
+    pub fn set_parity(&self, parity: Parity) {
+        self.write_bit(9, parity != Parity::None);
+        self.write_bit(3, parity == Parity::Odd);
+    }
+    pub fn send_full(&self) -> bool {
+        self.read_bit(4)
+    }
+    pub fn receive_empty(&self) -> bool {
+        self.read_bit(5)
+    }
+    /// Note that reading SIOCNT clears this flag.
+    pub fn error_flag(&self) -> bool {
+        self.read_bit(6)
+    }
+    pub fn data_length(&self) -> DataLength {
+        if self.read_bit(7) {
+            DataLength::Eight
+        } else {
+            DataLength::Seven
+        }
+    }
+    pub fn set_data_length(&self, length: DataLength) {
+        self.write_bit(7, length == DataLength::Eight)
+    }
+    pub fn fifo_enabled(&self) -> bool {
+        self.read_bit(8)
+    }
+    pub fn enable_fifo(&self, enable: bool) {
+        self.write_bit(8, enable)
+    }
+    pub fn enable_send(&self, enable: bool) {
+        self.write_bit(10, enable)
+    }
+    pub fn enable_receive(&self, enable: bool) {
+        self.write_bit(11, enable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::tests::RegisterFile;
+
+    const RATES: [BaudRate; 4] = [
+        BaudRate::B9600,
+        BaudRate::B38400,
+        BaudRate::B57600,
+        BaudRate::B115200,
+    ];
+    const PARITIES: [Parity; 3] = [Parity::None, Parity::Even, Parity::Odd];
+    const LENGTHS: [DataLength; 2] = [DataLength::Seven, DataLength::Eight];
+
+    #[test]
+    fn test_round_trip() {
+        let regs = RegisterFile::default();
+        let siocnt = UartSiocnt::with_backend(&regs);
+        siocnt.set_mode(SerialMode::Uart);
+        for rate in RATES {
+            for parity in PARITIES {
+                for length in LENGTHS {
+                    siocnt.set_baud_rate(rate);
+                    siocnt.set_parity(parity);
+                    siocnt.set_data_length(length);
+                    assert_eq!(siocnt.baud_rate(), rate);
+                    assert_eq!(siocnt.parity(), parity);
+                    assert_eq!(siocnt.data_length(), length);
+                    assert_eq!(siocnt.mode(), SerialMode::Uart);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bits() {
+        let regs = RegisterFile::default();
+        let siocnt = UartSiocnt::with_backend(&regs);
+        siocnt.set_baud_rate(BaudRate::B57600);
+        assert_eq!(siocnt.read(), 2);
+        siocnt.set_parity(Parity::Odd);
+        assert_eq!(siocnt.read(), 2 | (1 << 9) | (1 << 3));
+        siocnt.set_parity(Parity::Even);
+        assert_eq!(siocnt.read(), 2 | (1 << 9));
+        siocnt.set_parity(Parity::None);
+        siocnt.set_data_length(DataLength::Eight);
+        assert_eq!(siocnt.read(), 2 | (1 << 7));
+    }
+}
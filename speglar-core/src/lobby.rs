@@ -0,0 +1,610 @@
+//! The lobby linked GBAs gather in before a match.
+//!
+//! Everyone picks a character and marks themselves ready, while the parent
+//! picks the rules and the arena; once everyone's ready the parent starts the
+//! match. GBAs can plug in (and run `initialize_id`) or drop out at any point
+//! before that, since the parent keeps transferring the whole time.
+use crate::{
+    checksum::checksum,
+    link::{
+        multiplayer::{ConnectionState, MultiplayerLink, PlayerId, TransferError},
+        RegisterBackend,
+    },
+    logs::{println, warning},
+    rng::Rng,
+    PlayerTag,
+};
+
+/// The highest arena number the parent can pick.
+pub const MAX_MAP_NUMBER: u16 = 999;
+/// The arena number for arenas nobody can pick on purpose: only the parent
+/// has the seed, and hands it out with `netplay::share_seed` after the lobby.
+pub const RANDOM_MAP: u16 = 0;
+pub const MAX_ROUNDS_TO_WIN: u8 = 9;
+pub const DEFAULT_ROUNDS_TO_WIN: u8 = 3;
+/// The time limits the parent can pick from, in seconds; 0 is no limit.
+const TIME_LIMITS: [u16; 4] = [0, 60, 90, 120];
+
+/// A player's choices in the lobby.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Slot {
+    /// Whose sprite and colours they'll play as.
+    pub character: PlayerTag,
+    pub ready: bool,
+}
+
+/// What the parent has picked for the match.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct LobbySettings {
+    pub rounds_to_win: u8,
+    /// An index into `TIME_LIMITS`.
+    time_limit: u8,
+    /// Which arenas get played, as the match seed, or `RANDOM_MAP`.
+    pub map: u16,
+}
+
+impl Default for LobbySettings {
+    fn default() -> Self {
+        Self {
+            rounds_to_win: DEFAULT_ROUNDS_TO_WIN,
+            time_limit: 2,
+            map: RANDOM_MAP,
+        }
+    }
+}
+
+impl LobbySettings {
+    /// The round time limit in seconds, or 0 for none.
+    pub const fn time_limit_secs(&self) -> u16 {
+        TIME_LIMITS[self.time_limit as usize]
+    }
+    /// A few bits of everything, so a child can tell it has the same settings
+    /// as the parent before starting.
+    fn check(&self) -> u16 {
+        checksum(self) as u16 & PAYLOAD_MASK
+    }
+}
+
+/// Which of the settings the parent is changing.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Setting {
+    #[default]
+    RoundsToWin,
+    TimeLimit,
+    Map,
+}
+
+/// The buttons the lobby listens to, and whether each was just pressed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct LobbyInput {
+    pub start: bool,
+    pub a: bool,
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    pub l: bool,
+    pub r: bool,
+}
+
+const WORD_TAG: u16 = 0x8000;
+const KIND_SHIFT: u16 = 12;
+const PAYLOAD_MASK: u16 = 0x0FFF;
+
+/*
+  Bit   Expl.
+  0-11  Payload, depending on the kind:
+          Player: Bits 0-1 the character, bit 2 set once ready
+          Rules:  Bits 0-3 rounds to win, bits 4-5 the time limit
+          Map:    The arena number
+          Start:  `LobbySettings::check` of the settings being started with
+  12-14 Kind (0=Player, 1=Rules, 2=Map, 3=Start)
+  15    Always 1, so these can't be mistaken for an `InputWord`
+*/
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum LobbyWord {
+    Player(Slot),
+    Rules { rounds_to_win: u8, time_limit: u8 },
+    Map(u16),
+    Start { check: u16 },
+}
+
+impl LobbyWord {
+    const fn encode(self) -> u16 {
+        let (kind, payload) = match self {
+            LobbyWord::Player(slot) => (0, slot.character as u16 | (slot.ready as u16) << 2),
+            LobbyWord::Rules {
+                rounds_to_win,
+                time_limit,
+            } => (1, rounds_to_win as u16 | (time_limit as u16) << 4),
+            LobbyWord::Map(map) => (2, map),
+            LobbyWord::Start { check } => (3, check),
+        };
+        WORD_TAG | kind << KIND_SHIFT | (payload & PAYLOAD_MASK)
+    }
+    const fn decode(raw: u16) -> Option<Self> {
+        if raw & WORD_TAG == 0 {
+            return None;
+        }
+        let payload = raw & PAYLOAD_MASK;
+        match (raw >> KIND_SHIFT) & 0x7 {
+            0 => Some(LobbyWord::Player(Slot {
+                character: PlayerTag::from_u8(payload as u8),
+                ready: payload & (1 << 2) != 0,
+            })),
+            1 => {
+                let rounds_to_win = (payload & 0xF) as u8;
+                let time_limit = ((payload >> 4) & 0x3) as u8;
+                if rounds_to_win == 0 || rounds_to_win > MAX_ROUNDS_TO_WIN {
+                    return None;
+                }
+                Some(LobbyWord::Rules {
+                    rounds_to_win,
+                    time_limit,
+                })
+            }
+            2 if payload <= MAX_MAP_NUMBER => Some(LobbyWord::Map(payload)),
+            3 => Some(LobbyWord::Start { check: payload }),
+            _ => None,
+        }
+    }
+}
+
+/// What everyone agreed on in the lobby.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct LobbyOutcome {
+    pub settings: LobbySettings,
+    /// Who was in the lobby when the match started, indexed by `PlayerId`.
+    pub slots: [Option<Slot>; 4],
+}
+
+impl LobbyOutcome {
+    /// The match seed for the picked arena number, spread over all 64 bits so
+    /// that neighbouring numbers play nothing alike; `None` for `RANDOM_MAP`,
+    /// where the parent picks the seed.
+    pub const fn seed(&self) -> Option<u64> {
+        if self.settings.map == RANDOM_MAP {
+            return None;
+        }
+        let (_, seed) = Rng::with_seed(self.settings.map as u64).next_u64_const();
+        Some(seed)
+    }
+}
+
+/// The parent's words, sent one per transfer in turn.
+const PARENT_ROTATION: usize = 3;
+
+pub struct Lobby<'a, 'q, B> {
+    serial: &'a mut MultiplayerLink<'q, B>,
+    local_id: PlayerId,
+    /// The last thing we heard from each player, or `None` if they aren't in
+    /// the lobby (yet).
+    slots: [Option<Slot>; 4],
+    settings: LobbySettings,
+    /// The setting the parent's d-pad changes.
+    selected: Setting,
+    /// Which of its words the parent sends next.
+    rotation: usize,
+    /// The start word, once the parent has started the match.
+    starting: Option<u16>,
+    /// The children that have echoed the start word.
+    started: [bool; 4],
+}
+
+impl<'a, 'q, B: RegisterBackend> Lobby<'a, 'q, B> {
+    /// Opens the lobby over `serial`, which must have already run
+    /// `initialize_id` and have its interrupt filling the transfer queues.
+    pub fn new(serial: &'a mut MultiplayerLink<'q, B>) -> Self {
+        let local_id = serial
+            .id()
+            .expect("initialize_id should be called before opening the lobby");
+        serial.clear_transfers();
+        serial.mark_ready();
+        serial.reset_connection_timer();
+        let mut slots = [None; 4];
+        slots[local_id as usize] = Some(Slot {
+            character: local_id.into(),
+            ready: false,
+        });
+        let settings = LobbySettings::default();
+        Self {
+            serial,
+            local_id,
+            slots,
+            settings,
+            selected: Setting::default(),
+            rotation: 0,
+            starting: None,
+            started: [false; 4],
+        }
+    }
+
+    pub fn local_id(&self) -> PlayerId {
+        self.local_id
+    }
+    pub fn slots(&self) -> [Option<Slot>; 4] {
+        self.slots
+    }
+    pub fn settings(&self) -> LobbySettings {
+        self.settings
+    }
+    /// The setting being changed, if we're the parent.
+    pub fn selected(&self) -> Option<Setting> {
+        (self.local_id == PlayerId::Parent).then_some(self.selected)
+    }
+
+    fn local_slot(&mut self) -> &mut Slot {
+        self.slots[self.local_id as usize].get_or_insert(Slot {
+            character: self.local_id.into(),
+            ready: false,
+        })
+    }
+
+    /// Whether the parent can start: everyone connected has said who they are
+    /// and is ready, on different characters.
+    pub fn can_start(&self) -> bool {
+        let mut taken = [false; 4];
+        for id in PlayerId::ALL {
+            let connected = id == self.local_id
+                || self.serial.connection_state(id) == ConnectionState::Connected;
+            match self.slots[id as usize] {
+                Some(slot) if slot.ready && !taken[slot.character as usize] => {
+                    taken[slot.character as usize] = true;
+                }
+                Some(_) => return false,
+                None if connected => return false,
+                None => {}
+            }
+        }
+        true
+    }
+
+    /// Runs the lobby for a frame, returning what everyone agreed on once the
+    /// match starts.
+    pub fn update(&mut self, btns: LobbyInput) -> Option<LobbyOutcome> {
+        if self.starting.is_none() {
+            self.handle_input(btns);
+        }
+        if self.local_id == PlayerId::Parent && self.serial.all_ready() {
+            match self.serial.start_transfer() {
+                Ok(())
+                | Err(TransferError::AlreadyInProgress)
+                | Err(TransferError::FailedReadyCheck) => {}
+                Err(e) => {
+                    warning!("Lobby transfer failed: {:?}", e);
+                }
+            }
+        }
+        while let Some(transfer) = self.serial.next_transfer() {
+            if let Some(outcome) = self.receive(transfer) {
+                return Some(outcome);
+            }
+            self.rotation = (self.rotation + 1) % PARENT_ROTATION;
+        }
+        self.update_connections();
+        if self.local_id == PlayerId::Parent && self.starting.is_some() {
+            let all_started = PlayerId::ALL
+                .into_iter()
+                .filter(|id| *id != self.local_id && self.slots[*id as usize].is_some())
+                .all(|id| self.started[id as usize]);
+            if all_started {
+                println!("Starting the match with {:?}", self.settings);
+                return Some(self.outcome());
+            }
+        }
+        let word = self.next_word();
+        self.serial.write_send_reg(word);
+        None
+    }
+
+    fn outcome(&self) -> LobbyOutcome {
+        LobbyOutcome {
+            settings: self.settings,
+            slots: self.slots,
+        }
+    }
+
+    fn handle_input(&mut self, btns: LobbyInput) {
+        let is_parent = self.local_id == PlayerId::Parent;
+        if is_parent && btns.start && self.can_start() {
+            self.starting = Some(
+                LobbyWord::Start {
+                    check: self.settings.check(),
+                }
+                .encode(),
+            );
+            return;
+        }
+        if btns.a {
+            let slot = self.local_slot();
+            slot.ready = !slot.ready;
+        }
+        if !self.local_slot().ready {
+            if btns.left {
+                self.cycle_character(3);
+            } else if btns.right {
+                self.cycle_character(1);
+            }
+        }
+        if is_parent {
+            self.handle_settings_input(btns);
+        }
+    }
+
+    /// Moves our character along by `step`, skipping any someone else has.
+    fn cycle_character(&mut self, step: u8) {
+        let mut taken = [false; 4];
+        for (id, slot) in self.slots.iter().enumerate() {
+            if let Some(slot) = slot.filter(|_| id != self.local_id as usize) {
+                taken[slot.character as usize] = true;
+            }
+        }
+        let slot = self.local_slot();
+        let mut character = slot.character;
+        for _ in 0..4 {
+            character = PlayerTag::from_u8(character as u8 + step);
+            if !taken[character as usize] {
+                break;
+            }
+        }
+        slot.character = character;
+    }
+
+    fn handle_settings_input(&mut self, btns: LobbyInput) {
+        if btns.up {
+            self.selected = match self.selected {
+                Setting::RoundsToWin => Setting::Map,
+                Setting::TimeLimit => Setting::RoundsToWin,
+                Setting::Map => Setting::TimeLimit,
+            };
+        } else if btns.down {
+            self.selected = match self.selected {
+                Setting::RoundsToWin => Setting::TimeLimit,
+                Setting::TimeLimit => Setting::Map,
+                Setting::Map => Setting::RoundsToWin,
+            };
+        }
+        let up = btns.r;
+        let down = btns.l;
+        if !up && !down {
+            return;
+        }
+        let settings = &mut self.settings;
+        match self.selected {
+            Setting::RoundsToWin => {
+                settings.rounds_to_win = if up {
+                    settings.rounds_to_win % MAX_ROUNDS_TO_WIN + 1
+                } else {
+                    (settings.rounds_to_win + MAX_ROUNDS_TO_WIN - 2) % MAX_ROUNDS_TO_WIN + 1
+                };
+            }
+            Setting::TimeLimit => {
+                let len = TIME_LIMITS.len() as u8;
+                settings.time_limit = if up {
+                    (settings.time_limit + 1) % len
+                } else {
+                    (settings.time_limit + len - 1) % len
+                };
+            }
+            Setting::Map => {
+                let len = MAX_MAP_NUMBER + 1;
+                settings.map = if up {
+                    (settings.map + 1) % len
+                } else {
+                    (settings.map + len - 1) % len
+                };
+            }
+        }
+    }
+
+    fn receive(&mut self, transfer: [u16; 4]) -> Option<LobbyOutcome> {
+        for id in PlayerId::ALL {
+            if id == self.local_id {
+                continue;
+            }
+            let raw = transfer[id as usize];
+            if let Some(start) = self.starting {
+                // A child that's already moved on to sending inputs has
+                // clearly seen the start.
+                if raw == start || raw & WORD_TAG == 0 {
+                    self.started[id as usize] = true;
+                }
+            }
+            match LobbyWord::decode(raw) {
+                Some(LobbyWord::Player(slot)) => self.slots[id as usize] = Some(slot),
+                // Only the parent gets a say in the settings.
+                Some(_) if id != PlayerId::Parent => {}
+                Some(LobbyWord::Rules {
+                    rounds_to_win,
+                    time_limit,
+                }) => {
+                    self.settings.rounds_to_win = rounds_to_win;
+                    self.settings.time_limit = time_limit;
+                }
+                Some(LobbyWord::Map(map)) => self.settings.map = map,
+                Some(LobbyWord::Start { check }) => {
+                    // Otherwise we've missed a change to the settings, which
+                    // the parent will send again.
+                    if check == self.settings.check() {
+                        self.serial.write_send_reg(raw);
+                        println!("Starting the match with {:?}", self.settings);
+                        return Some(self.outcome());
+                    }
+                }
+                None => {}
+            }
+        }
+        None
+    }
+
+    /// Clears out anyone who's left.
+    fn update_connections(&mut self) {
+        for id in PlayerId::ALL {
+            if id != self.local_id
+                && self.serial.connection_state(id) == ConnectionState::Dropped
+                && self.slots[id as usize].take().is_some()
+            {
+                println!("{:?} left the lobby", id);
+            }
+        }
+        // Without the parent nobody hears from anyone, so hold on to nothing
+        // until it's back.
+        if let Err(TransferError::Timeout) = self.serial.check_connection([false; 4]) {
+            for id in PlayerId::ALL {
+                if id != self.local_id {
+                    self.slots[id as usize] = None;
+                }
+            }
+            self.serial.reset_connection_timer();
+        }
+    }
+
+    fn next_word(&mut self) -> u16 {
+        if self.local_id != PlayerId::Parent {
+            let slot = *self.local_slot();
+            return LobbyWord::Player(slot).encode();
+        }
+        match self.rotation {
+            0 => LobbyWord::Rules {
+                rounds_to_win: self.settings.rounds_to_win,
+                time_limit: self.settings.time_limit,
+            }
+            .encode(),
+            1 => LobbyWord::Map(self.settings.map).encode(),
+            _ => match self.starting {
+                Some(start) => start,
+                None => LobbyWord::Player(*self.local_slot()).encode(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeSet;
+
+    use super::*;
+    use crate::link::{
+        queues::TransferQueues,
+        tests::{finish_transfer, linked},
+        SimulatedLink,
+    };
+
+    const NOTHING: LobbyInput = LobbyInput {
+        start: false,
+        a: false,
+        left: false,
+        right: false,
+        up: false,
+        down: false,
+        l: false,
+        r: false,
+    };
+    const A: LobbyInput = LobbyInput { a: true, ..NOTHING };
+
+    #[test]
+    fn test_words() {
+        let words = [
+            LobbyWord::Player(Slot {
+                character: PlayerTag::P3,
+                ready: true,
+            }),
+            LobbyWord::Rules {
+                rounds_to_win: MAX_ROUNDS_TO_WIN,
+                time_limit: 3,
+            },
+            LobbyWord::Map(MAX_MAP_NUMBER),
+            LobbyWord::Start { check: 0xABC },
+        ];
+        for word in words {
+            assert_eq!(LobbyWord::decode(word.encode()), Some(word));
+        }
+        assert_eq!(LobbyWord::decode(0x7FFF), None);
+        assert_eq!(LobbyWord::decode(LobbyWord::Map(1000).encode()), None);
+    }
+
+    #[test]
+    fn test_seeds() {
+        let seeds: BTreeSet<u64> = (RANDOM_MAP + 1..=MAX_MAP_NUMBER)
+            .map(|map| {
+                let settings = LobbySettings {
+                    map,
+                    ..LobbySettings::default()
+                };
+                let outcome = LobbyOutcome {
+                    settings,
+                    slots: [None; 4],
+                };
+                // Rounds are seeded from this with the low bit set.
+                outcome.seed().unwrap() | 1
+            })
+            .collect();
+        assert_eq!(seeds.len(), MAX_MAP_NUMBER as usize);
+        let random = LobbyOutcome {
+            settings: LobbySettings::default(),
+            slots: [None; 4],
+        };
+        assert_eq!(random.seed(), None);
+    }
+
+    #[test]
+    fn test_start() {
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let (parent_link, child_link) = links.split_at_mut(1);
+        let mut parent = Lobby::new(&mut parent_link[0]);
+        let mut child = Lobby::new(&mut child_link[0]);
+
+        // The parent turns the rounds to win up while the child readies.
+        let more_rounds = LobbyInput { r: true, ..NOTHING };
+        assert_eq!(parent.update(more_rounds), None);
+        assert_eq!(child.update(A), None);
+        finish_transfer(&link, &queues);
+        assert!(!parent.can_start());
+        assert_eq!(parent.update(A), None);
+        assert_eq!(child.update(NOTHING), None);
+        finish_transfer(&link, &queues);
+        let mut frames = 0;
+        while !parent.can_start()
+            || child.settings() != parent.settings()
+            || child.slots() != parent.slots()
+        {
+            assert_eq!(parent.update(NOTHING), None);
+            assert_eq!(child.update(NOTHING), None);
+            finish_transfer(&link, &queues);
+            frames += 1;
+            assert!(frames < 16, "The lobby never settled");
+        }
+        assert_eq!(child.settings().rounds_to_win, DEFAULT_ROUNDS_TO_WIN + 1);
+        assert_eq!(child.settings().map, parent.settings().map);
+
+        // Only the parent can start; the child hears about it, echoes the
+        // start word back and goes, then the parent follows.
+        let start = LobbyInput {
+            start: true,
+            ..NOTHING
+        };
+        let mut parent_outcome = parent.update(start);
+        let mut child_outcome = child.update(start);
+        for _ in 0..16 {
+            if parent_outcome.is_some() {
+                break;
+            }
+            finish_transfer(&link, &queues);
+            if child_outcome.is_none() {
+                child_outcome = child.update(NOTHING);
+            }
+            parent_outcome = parent.update(NOTHING);
+        }
+        let (parent_outcome, child_outcome) = (parent_outcome.unwrap(), child_outcome.unwrap());
+        assert_eq!(parent_outcome, child_outcome);
+        assert_eq!(parent_outcome.seed(), child_outcome.seed());
+        let expected = Slot {
+            character: PlayerTag::P2,
+            ready: true,
+        };
+        assert_eq!(parent_outcome.slots[1], Some(expected));
+        assert_eq!(parent_outcome.slots[2], None);
+    }
+}
@@ -0,0 +1,65 @@
+//! Logging for the code in here, which can't reach mGBA's debug output
+//! itself.
+//!
+//! Nothing gets logged until the game hands over somewhere to send it with
+//! `set_sink`; on the host, nothing ever does.
+use alloc::format;
+use core::fmt;
+use portable_atomic::{AtomicPtr, Ordering};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+/// Where log messages end up.
+pub type LogSink = fn(LogLevel, fmt::Arguments);
+
+/// The current `LogSink`, or null for none; there's no atomic for function
+/// pointers, so it's stored as a data pointer instead.
+static SINK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Sends everything logged from now on to `sink`.
+pub fn set_sink(sink: LogSink) {
+    SINK.store(sink as *mut (), Ordering::Release);
+}
+
+pub fn log(level: LogLevel, msg: fmt::Arguments) {
+    let sink = SINK.load(Ordering::Acquire);
+    if sink.is_null() {
+        return;
+    }
+    // SAFETY: the only non-null values ever stored come from a `LogSink`.
+    let sink: LogSink = unsafe { core::mem::transmute(sink) };
+    sink(level, msg);
+}
+
+/// Logs `value`'s pretty-printed `Debug` output a line at a time, so every
+/// line gets its own prefix.
+pub fn dump(label: &str, value: &impl fmt::Debug) {
+    if SINK.load(Ordering::Acquire).is_null() {
+        return;
+    }
+    log(LogLevel::Error, format_args!("{}:", label));
+    for line in format!("{:#?}", value).lines() {
+        log(LogLevel::Error, format_args!("  {}", line));
+    }
+}
+
+macro_rules! warning {
+    ( $( $x:expr ),*) => {{
+        $crate::logs::log($crate::logs::LogLevel::Warning, format_args!($($x,)*));
+    }};
+}
+
+macro_rules! println {
+    ( $( $x:expr ),*) => {{
+        $crate::logs::log($crate::logs::LogLevel::Info, format_args!($($x,)*));
+    }};
+}
+
+pub(crate) use println;
+pub(crate) use warning;
@@ -0,0 +1,229 @@
+use core::hash::Hash;
+
+use alloc::{string::String, vec::Vec};
+
+mod generation;
+use crate::{RectExt, RectType};
+pub use generation::*;
+mod layouts;
+pub use layouts::*;
+mod symmetry;
+pub use symmetry::*;
+mod text;
+pub use text::*;
+mod tiles;
+pub use tiles::*;
+mod validation;
+pub use validation::*;
+
+use crate::{VectType, N};
+
+// The GBA's screen size, in pixels.
+const WIDTH: i32 = 240;
+const HEIGHT: i32 = 160;
+
+pub const BUFFER_TILES: i32 = 1;
+pub const TILE_SIZE: i32 = 8;
+const MAP_BYTE_WIDTH: usize = {
+    let screen_tile_width = WIDTH / TILE_SIZE;
+    let map_width = screen_tile_width - 2 * BUFFER_TILES;
+    (map_width / 2) as usize
+};
+pub const MAP_WIDTH: usize = MAP_BYTE_WIDTH * 2;
+pub const MAP_HEIGHT: usize = ((HEIGHT / TILE_SIZE) - 2 * BUFFER_TILES) as usize;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BaseMap {
+    data: [[u8; MAP_BYTE_WIDTH]; MAP_HEIGHT],
+    spawns: [(usize, usize); 4],
+}
+
+impl BaseMap {
+    pub const fn from_raw(
+        data: [[MapTile; MAP_WIDTH]; MAP_HEIGHT],
+        spawns: [(usize, usize); 4],
+    ) -> Self {
+        let mut buffer = [[0u8; MAP_BYTE_WIDTH]; MAP_HEIGHT];
+        let mut xidx = 0;
+        while xidx < MAP_WIDTH {
+            let mut yidx = 0;
+            while yidx < MAP_HEIGHT {
+                let cur_tile = data[yidx][xidx];
+                let byte_xidx = xidx / 2;
+                let base_mask = cur_tile.to_u8();
+
+                let mut cur_byte = buffer[yidx][byte_xidx];
+                if xidx % 2 == 0 {
+                    cur_byte |= base_mask << 4;
+                } else {
+                    cur_byte |= base_mask;
+                }
+                buffer[yidx][byte_xidx] = cur_byte;
+                yidx += 1;
+            }
+            xidx += 1;
+        }
+        Self {
+            data: buffer,
+            spawns,
+        }
+    }
+    #[allow(dead_code)]
+    pub fn flip(&mut self, x: usize, y: usize) {
+        //TODO: Optimize
+        self.set(x, y, self.get(x, y).flipped());
+    }
+    pub fn set(&mut self, x: usize, y: usize, tile: MapTile) {
+        let elm = self.data[y][x / 2];
+        let mask = tile as u8;
+        let nelm = if x % 2 == 0 {
+            (mask << 4) | (elm & 0x0F)
+        } else {
+            (elm & 0xF0) | mask
+        };
+        self.data[y][x / 2] = nelm;
+    }
+    pub const fn with(mut self, x: usize, y: usize, tile: MapTile) -> Self {
+        let elm = self.data[y][x / 2];
+        let mask = tile as u8;
+        let nelm = if x % 2 == 0 {
+            (mask << 4) | (elm & 0x0F)
+        } else {
+            (elm & 0xF0) | mask
+        };
+        self.data[y][x / 2] = nelm;
+        self
+    }
+    pub fn tile_at_pixel(&self, pos: VectType) -> MapTile {
+        self.pixel_to_index(pos)
+            .map_or(MapTile::Empty, |(x, y)| self.get(x, y))
+    }
+    pub fn pixel_to_index(&self, pos: VectType) -> Option<(usize, usize)> {
+        let (x_raw, y_raw) = (pos / TILE_SIZE).get();
+        let x_raw = x_raw - BUFFER_TILES;
+        let y_raw = y_raw - BUFFER_TILES;
+        if x_raw < N::new(0) || y_raw < N::new(0) {
+            return None;
+        }
+        let x = x_raw.trunc() as usize;
+        let y = y_raw.trunc() as usize;
+        if x >= MAP_WIDTH || y >= MAP_HEIGHT {
+            return None;
+        }
+        Some((x, y))
+    }
+    pub fn tiles_intersecting(&self, hbox: RectType) -> impl Iterator<Item = MapTile> + '_ {
+        let mut poses = Vec::new();
+
+        let corners = [hbox.tl(), hbox.tr(), hbox.bl(), hbox.br()];
+        for corner in corners {
+            let mc = self.pixel_to_index(corner);
+            if poses.contains(&mc) {
+                continue;
+            }
+            poses.push(mc);
+        }
+
+        poses
+            .into_iter()
+            .map(|opt| opt.map_or(MapTile::Empty, |(x, y)| self.get(x, y)))
+    }
+    pub fn index_to_pixel(&self, (xidx, yidx): (usize, usize)) -> VectType {
+        let x = N::from(xidx as i32 + BUFFER_TILES) * TILE_SIZE;
+        let y = N::from(yidx as i32 + BUFFER_TILES) * TILE_SIZE;
+        VectType::new(x, y)
+    }
+    pub const fn get(&self, x: usize, y: usize) -> MapTile {
+        let elm = self.data[y][x / 2];
+        if x % 2 == 0 {
+            MapTile::from_u8(elm >> 4)
+        } else {
+            MapTile::from_u8(elm)
+        }
+    }
+    pub fn flip_all(&mut self) {
+        for x in 0..MAP_WIDTH {
+            for y in 0..MAP_HEIGHT {
+                self.set(x, y, self.get(x, y).flipped())
+            }
+        }
+    }
+
+    pub const fn player_spawns(&self) -> [(usize, usize); 4] {
+        self.spawns
+    }
+
+    /// Draws the map as text, one `MapTile::repr` per tile and a number
+    /// for each spawn on an empty tile; `BaseMap::parse` reads it back.
+    pub fn pretty_print(&self) -> String {
+        let mut retvl = String::with_capacity(MAP_WIDTH * MAP_HEIGHT + MAP_HEIGHT);
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                let tile = self.get(x, y);
+                let spawn = self.spawns.iter().position(|spawn| *spawn == (x, y));
+                match spawn {
+                    Some(idx) if tile == MapTile::Empty => retvl.push(SPAWN_MARKERS[idx]),
+                    _ => retvl.push(tile.repr()),
+                }
+            }
+            retvl.push('\n');
+        }
+        retvl
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const EMPTY_MAP: BaseMap = BaseMap::from_raw(
+        [[MapTile::Empty; MAP_WIDTH]; MAP_HEIGHT],
+        [(0, 0), (0, 1), (0, 2), (0, 3)],
+    );
+
+    #[test]
+    fn test_set_get_roundtrip() {
+        let mut map = EMPTY_MAP;
+        for raw in 0..8u8 {
+            let tile = MapTile::from_u8(raw);
+            // Neighbouring tiles share a byte, so check both halves.
+            for x in [4, 5] {
+                map.set(x, 3, tile);
+                assert_eq!(map.get(x, 3), tile);
+            }
+            assert_eq!(map.clone().with(6, 7, tile).get(6, 7), tile);
+        }
+        map.set(4, 3, MapTile::Block);
+        map.set(5, 3, MapTile::VertPipe);
+        assert_eq!(map.get(4, 3), MapTile::Block);
+        assert_eq!(map.get(5, 3), MapTile::VertPipe);
+    }
+
+    #[test]
+    fn test_pixel_index_roundtrip() {
+        for x in 0..MAP_WIDTH {
+            for y in 0..MAP_HEIGHT {
+                let px = EMPTY_MAP.index_to_pixel((x, y));
+                assert_eq!(EMPTY_MAP.pixel_to_index(px), Some((x, y)));
+                let inside = px + VectType::new(N::new(TILE_SIZE - 1), N::new(TILE_SIZE - 1));
+                assert_eq!(EMPTY_MAP.pixel_to_index(inside), Some((x, y)));
+            }
+        }
+        assert_eq!(
+            EMPTY_MAP.pixel_to_index(VectType::new(N::new(0), N::new(0))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_flip_all() {
+        let mut map = EMPTY_MAP
+            .with(1, 1, MapTile::UpMirror)
+            .with(2, 1, MapTile::DownMirror)
+            .with(3, 1, MapTile::HorizMirror);
+        map.flip_all();
+        assert_eq!(map.get(1, 1), MapTile::DownMirror);
+        assert_eq!(map.get(2, 1), MapTile::UpMirror);
+        assert_eq!(map.get(3, 1), MapTile::HorizMirror);
+    }
+}
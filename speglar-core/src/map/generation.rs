@@ -0,0 +1,401 @@
+use crate::rng::Rng;
+use crate::Direction;
+
+use super::*;
+
+/// How many of one kind of tile `generate` places, picked uniformly from
+/// `min..=max`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Density {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl Density {
+    pub const NONE: Density = Density::new(0, 0);
+    pub const fn new(min: u8, max: u8) -> Self {
+        Self { min, max }
+    }
+}
+
+/// How much of each kind of tile `generate` scatters over a base map.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct GenerationSettings {
+    /// `Block`s, which go in completely open spaces.
+    pub blocks: Density,
+    /// `HorizPipe`s and `VertPipe`s, which go in tunnels running the same way.
+    pub pipes: Density,
+    /// `HorizMirror`s and `VertMirror`s, which go in dead ends to bounce
+    /// bullets back out the way they came.
+    pub straight_mirrors: Density,
+    /// `UpMirror`s and `DownMirror`s, which go wherever a bullet could turn.
+    pub diagonal_mirrors: Density,
+    /// How many more tiles of sight lines one spawn can have over another
+    /// before `generate` throws the map out; see `validate`.
+    pub max_sight_gap: u16,
+    /// Which ways the map has to look the same; the base map gets made to fit
+    /// with `Symmetry::apply` first.
+    pub symmetry: Symmetry,
+}
+
+impl GenerationSettings {
+    pub const DEFAULT: GenerationSettings = GenerationSettings {
+        blocks: Density::new(4, 8),
+        pipes: Density::new(4, 8),
+        straight_mirrors: Density::new(2, 6),
+        diagonal_mirrors: Density::new(16, 32),
+        max_sight_gap: 24,
+        symmetry: Symmetry::None,
+    };
+
+    /// Only diagonal mirrors, like the original generator.
+    pub const fn mirrors_only(min_mirrors: u8, max_mirrors: u8) -> Self {
+        Self {
+            blocks: Density::NONE,
+            pipes: Density::NONE,
+            straight_mirrors: Density::NONE,
+            diagonal_mirrors: Density::new(min_mirrors, max_mirrors),
+            max_sight_gap: Self::DEFAULT.max_sight_gap,
+            symmetry: Symmetry::None,
+        }
+    }
+}
+
+impl Default for GenerationSettings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// How many random spots to try per kind of tile before settling for fewer
+/// than we picked, in case the map has run out of places to put them.
+const PLACEMENT_ATTEMPTS: u32 = 4096;
+
+/// How many maps `generate` tries before giving up on passing `validate`.
+const MAX_REROLLS: u32 = 32;
+
+/// Scatters tiles over `base` as `settings` asks, rerolling until every spawn
+/// can reach every other and none can see much further than the rest.
+///
+/// If no roll passes, this gives back `base` with nothing added, which makes
+/// `base` passing `validate` on its own worth checking.
+pub const fn generate(seed: u64, base: BaseMap, settings: GenerationSettings) -> BaseMap {
+    let base = settings.symmetry.apply(base);
+    // Each roll carries on from where the last left the RNG; reseeding with
+    // consecutive xorshift outputs tends to give back near-identical maps.
+    let mut rng = Rng::with_seed(seed);
+    let mut attempt = 0;
+    while attempt < MAX_REROLLS {
+        // `clone` isn't const, but the fields are all `Copy`.
+        let copy = BaseMap {
+            data: base.data,
+            spawns: base.spawns,
+        };
+        let (candidate, nrng) = roll(rng, copy, settings);
+        rng = nrng;
+        if validate(&candidate, settings.max_sight_gap).is_ok() {
+            return candidate;
+        }
+        attempt += 1;
+    }
+    base
+}
+
+/// One roll of `generate`, with no checks on what comes out.
+const fn roll(rng: Rng, base: BaseMap, settings: GenerationSettings) -> (BaseMap, Rng) {
+    // Blocks go first, since they're what turns open space into the dead ends
+    // and tunnels the others look for.
+    let symmetry = settings.symmetry;
+    let (retvl, rng) = scatter(base, rng, Placement::Blocks, settings.blocks, symmetry);
+    let (retvl, rng) = scatter(retvl, rng, Placement::Pipes, settings.pipes, symmetry);
+    let (retvl, rng) = scatter(
+        retvl,
+        rng,
+        Placement::StraightMirrors,
+        settings.straight_mirrors,
+        symmetry,
+    );
+    scatter(
+        retvl,
+        rng,
+        Placement::DiagonalMirrors,
+        settings.diagonal_mirrors,
+        symmetry,
+    )
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Placement {
+    Blocks,
+    Pipes,
+    StraightMirrors,
+    DiagonalMirrors,
+}
+
+impl Placement {
+    /// Picks the tile to put in a spot a bullet could leave in the directions
+    /// given, or `None` if this kind doesn't go there.
+    const fn pick(self, exits: (bool, bool, bool, bool), rng: Rng) -> (Rng, Option<MapTile>) {
+        use MapTile::*;
+
+        let tile = match (self, exits) {
+            (Placement::Blocks, (true, true, true, true)) => Block,
+            (Placement::Blocks, _) => return (rng, None),
+
+            (Placement::Pipes, (true, true, false, false)) => VertPipe,
+            (Placement::Pipes, (false, false, true, true)) => HorizPipe,
+            (Placement::Pipes, _) => return (rng, None),
+
+            // The mirror faces the only way in, so bullets go straight back out.
+            (Placement::StraightMirrors, (true, false, false, false))
+            | (Placement::StraightMirrors, (false, true, false, false)) => HorizMirror,
+            (Placement::StraightMirrors, (false, false, true, false))
+            | (Placement::StraightMirrors, (false, false, false, true)) => VertMirror,
+            (Placement::StraightMirrors, _) => return (rng, None),
+
+            // Left to Up and Down to Right is an Upmirror
+            (Placement::DiagonalMirrors, (true, false, true, false))
+            | (Placement::DiagonalMirrors, (false, true, false, true)) => UpMirror,
+
+            // Left to Down and Up to Right is a Downmirror
+            (Placement::DiagonalMirrors, (true, false, false, true))
+            | (Placement::DiagonalMirrors, (false, true, true, false)) => DownMirror,
+
+            // Completely open or 3 ways out; while we COULD try to bias based
+            // on direction/where the hole is, for now we won't
+            (Placement::DiagonalMirrors, (true, true, true, _))
+            | (Placement::DiagonalMirrors, (true, true, _, true))
+            | (Placement::DiagonalMirrors, (true, _, true, true))
+            | (Placement::DiagonalMirrors, (_, true, true, true)) => {
+                let (nrng, flag) = rng.bool_const();
+                let tile = if flag { UpMirror } else { DownMirror };
+                return (nrng, Some(tile));
+            }
+
+            // Dead ends, tunnels and unreachable spots can't turn a bullet.
+            (Placement::DiagonalMirrors, _) => return (rng, None),
+        };
+        (rng, Some(tile))
+    }
+}
+
+/// Puts a random number of `kind`'s tiles, picked from `density`, in random
+/// empty spots that suit them, along with their images under `symmetry`.
+///
+/// The number picked counts every image, and is rounded down to a whole
+/// number of groups so the map stays symmetric.
+const fn scatter(
+    mut map: BaseMap,
+    rng: Rng,
+    kind: Placement,
+    density: Density,
+    symmetry: Symmetry,
+) -> (BaseMap, Rng) {
+    let group_size = symmetry.group_size();
+    let (mut rng, count) = rng.u8_const(density.min, density.max);
+    let mut remaining = count / group_size;
+    let mut attempts = 0;
+    'attempts: while remaining > 0 && attempts < PLACEMENT_ATTEMPTS {
+        attempts += 1;
+        let (nrng, next_x) = rng.usize_const(1, MAP_WIDTH - 2);
+        let (nrng, next_y) = nrng.usize_const(1, MAP_HEIGHT - 2);
+        rng = nrng;
+        // Spawns stay empty, which also keeps them printable by `pretty_print`.
+        if !matches!(map.get(next_x, next_y), MapTile::Empty) || is_spawn(&map, next_x, next_y) {
+            continue;
+        }
+        let (nrng, next_tile) = kind.pick(bullet_exits(&map, next_x, next_y), rng);
+        rng = nrng;
+        let next_tile = match next_tile {
+            Some(tile) => tile,
+            None => continue,
+        };
+        // The map's already symmetric, so the images should be as free as
+        // the spot we picked, but check anyway.
+        let images = symmetry.images((next_x, next_y), next_tile);
+        let mut iidx = 0;
+        while iidx + 1 < group_size as usize {
+            let ((x, y), _) = images[iidx];
+            if !matches!(map.get(x, y), MapTile::Empty) || is_spawn(&map, x, y) {
+                continue 'attempts;
+            }
+            iidx += 1;
+        }
+        map = map.with(next_x, next_y, next_tile);
+        let mut iidx = 0;
+        while iidx + 1 < group_size as usize {
+            let ((x, y), tile) = images[iidx];
+            map = map.with(x, y, tile);
+            iidx += 1;
+        }
+        remaining -= 1;
+    }
+    (map, rng)
+}
+
+/// Whether a bullet in `(x, y)` could move up, down, left and right out of it.
+const fn bullet_exits(map: &BaseMap, x: usize, y: usize) -> (bool, bool, bool, bool) {
+    (
+        bullet_is_passable(map.get(x, y - 1), Direction::Up),
+        bullet_is_passable(map.get(x, y + 1), Direction::Down),
+        bullet_is_passable(map.get(x - 1, y), Direction::Left),
+        bullet_is_passable(map.get(x + 1, y), Direction::Right),
+    )
+}
+
+const fn is_spawn(map: &BaseMap, x: usize, y: usize) -> bool {
+    let mut sidx = 0;
+    while sidx < map.spawns.len() {
+        if map.spawns[sidx].0 == x && map.spawns[sidx].1 == y {
+            return true;
+        }
+        sidx += 1;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_tiles(map: &BaseMap, tiles: &[MapTile]) -> usize {
+        let mut count = 0;
+        for x in 0..MAP_WIDTH {
+            for y in 0..MAP_HEIGHT {
+                if tiles.contains(&map.get(x, y)) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    fn count_mirrors(map: &BaseMap) -> usize {
+        count_tiles(map, &[MapTile::UpMirror, MapTile::DownMirror])
+    }
+
+    #[test]
+    fn test_generate_is_deterministic() {
+        for seed in 1..50 {
+            let a = generate(
+                seed,
+                HONEYCOMB_BASE,
+                GenerationSettings::mirrors_only(16, 32),
+            );
+            let b = generate(
+                seed,
+                HONEYCOMB_BASE,
+                GenerationSettings::mirrors_only(16, 32),
+            );
+            assert_eq!(a, b, "Seed {} differed", seed);
+        }
+    }
+
+    #[test]
+    fn test_generate_only_adds_mirrors() {
+        for seed in 1..200 {
+            let map = generate(
+                seed,
+                HONEYCOMB_BASE,
+                GenerationSettings::mirrors_only(16, 32),
+            );
+            let mirrors = count_mirrors(&map);
+            assert!(
+                (16..=32).contains(&mirrors),
+                "Seed {} placed {} mirrors:\n{}",
+                seed,
+                mirrors,
+                map.pretty_print()
+            );
+            for x in 0..MAP_WIDTH {
+                for y in 0..MAP_HEIGHT {
+                    let base = HONEYCOMB_BASE.get(x, y);
+                    let tile = map.get(x, y);
+                    assert!(
+                        tile == base || base == MapTile::Empty,
+                        "Seed {} changed ({}, {}) from {:?} to {:?}",
+                        seed,
+                        x,
+                        y,
+                        base,
+                        tile
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_places_every_kind() {
+        use MapTile::*;
+
+        let settings = GenerationSettings::DEFAULT;
+        let mut seen_straight = false;
+        for seed in 1..200 {
+            let map = generate(seed, HONEYCOMB_BASE, settings);
+            let added =
+                |tiles: &[MapTile]| count_tiles(&map, tiles) - count_tiles(&HONEYCOMB_BASE, tiles);
+            assert!((4..=8).contains(&added(&[Block])), "Seed {}", seed);
+            assert!(
+                (4..=8).contains(&added(&[HorizPipe, VertPipe])),
+                "Seed {}",
+                seed
+            );
+            assert!(added(&[HorizMirror, VertMirror]) <= 6, "Seed {}", seed);
+            assert!(
+                (16..=32).contains(&added(&[UpMirror, DownMirror])),
+                "Seed {}",
+                seed
+            );
+            // Dead ends only show up once blocks are in, so there may not be
+            // any room for straight mirrors.
+            seen_straight |= added(&[HorizMirror, VertMirror]) > 0;
+
+            for (x, y) in map.player_spawns() {
+                assert!(
+                    map.get(x, y).allows_player(),
+                    "Seed {} blocked a spawn",
+                    seed
+                );
+            }
+        }
+        assert!(seen_straight);
+    }
+
+    #[test]
+    fn test_generate_fits_tiles_to_their_spot() {
+        for seed in 1..200 {
+            let map = generate(seed, HONEYCOMB_BASE, GenerationSettings::DEFAULT);
+            for x in 1..MAP_WIDTH - 1 {
+                for y in 1..MAP_HEIGHT - 1 {
+                    let (u, d, l, r) = bullet_exits(&map, x, y);
+                    let fits = match map.get(x, y) {
+                        MapTile::HorizPipe => l && r,
+                        MapTile::VertPipe => u && d,
+                        MapTile::HorizMirror => (u || d) && !l && !r,
+                        MapTile::VertMirror => (l || r) && !u && !d,
+                        _ => true,
+                    };
+                    assert!(
+                        fits,
+                        "Seed {} misplaced ({}, {}):\n{}",
+                        seed,
+                        x,
+                        y,
+                        map.pretty_print()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_passes_validation() {
+        for seed in 1..200 {
+            let settings = GenerationSettings::DEFAULT;
+            let map = generate(seed, HONEYCOMB_BASE, settings);
+            assert_ne!(map, HONEYCOMB_BASE, "Seed {} never passed", seed);
+            assert_eq!(validate(&map, settings.max_sight_gap), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! The arenas `generate` builds on: a few drawn by hand, and caves grown from
+//! a seed.
+use crate::rng::Rng;
+
+use super::*;
+
+type Tiles = [[MapTile; MAP_WIDTH]; MAP_HEIGHT];
+
+const BLOCK_ROW: [MapTile; MAP_WIDTH] = [MapTile::Block; MAP_WIDTH];
+const EMPTY_ROW: [MapTile; MAP_WIDTH] = {
+    let mut retvl = [MapTile::Empty; MAP_WIDTH];
+    retvl[0] = MapTile::Block;
+    retvl[MAP_WIDTH - 1] = MapTile::Block;
+    retvl
+};
+/// Nothing but the outer wall.
+const WALLED: Tiles = {
+    let mut retvl = [EMPTY_ROW; MAP_HEIGHT];
+    retvl[0] = BLOCK_ROW;
+    retvl[MAP_HEIGHT - 1] = BLOCK_ROW;
+    retvl
+};
+
+/// One spawn in each corner, in the order `Symmetry` expects.
+const CORNER_SPAWNS: [(usize, usize); 4] = [
+    (1, 1),
+    (1, MAP_HEIGHT - 2),
+    (MAP_WIDTH - 2, 1),
+    (MAP_WIDTH - 2, MAP_HEIGHT - 2),
+];
+
+pub const HONEYCOMB_BASE: BaseMap = {
+    let mut retvl = WALLED;
+
+    let mut xidx = 0;
+    while xidx < MAP_WIDTH {
+        let mut yidx = 0;
+        while yidx < MAP_HEIGHT {
+            retvl[yidx][xidx] = MapTile::Block;
+            if yidx == MAP_HEIGHT / 2 - 1 {
+                yidx += 1;
+            }
+            yidx += 2;
+        }
+        if xidx == MAP_WIDTH / 2 - 2 {
+            xidx += 1;
+        }
+        xidx += 2;
+    }
+    let retvl = BaseMap::from_raw(retvl, CORNER_SPAWNS);
+    verify_spawns(&retvl);
+    assert!(validate(&retvl, 0).is_ok());
+    retvl
+};
+
+/// Nothing in the way at all.
+pub const OPEN_BASE: BaseMap = quartered(WALLED);
+
+/// A plus of walls in the middle, with room to run all the way round it.
+pub const CROSS_BASE: BaseMap = {
+    let retvl = fill(WALLED, (13, 4), (13, 8), MapTile::Block);
+    quartered(fill(retvl, (6, 8), (13, 8), MapTile::Block))
+};
+
+/// Two walled rings round the middle, with doors on different sides so
+/// getting in takes a lap.
+pub const RINGS_BASE: BaseMap = {
+    let retvl = fill(WALLED, (4, 3), (10, 3), MapTile::Block);
+    let retvl = fill(retvl, (4, 3), (4, 6), MapTile::Block);
+    let retvl = fill(retvl, (9, 6), (13, 6), MapTile::Block);
+    quartered(fill(retvl, (9, 6), (9, 8), MapTile::Block))
+};
+
+/// Long corridors that zigzag down to the middle.
+pub const MAZE_BASE: BaseMap = {
+    let retvl = fill(WALLED, (3, 3), (13, 3), MapTile::Block);
+    let retvl = fill(retvl, (1, 6), (10, 6), MapTile::Block);
+    quartered(fill(retvl, (10, 6), (10, 7), MapTile::Block))
+};
+
+/// Square pillars in a grid.
+pub const PILLARS_BASE: BaseMap = {
+    let mut retvl = WALLED;
+    let mut x = 3;
+    while x < MAP_WIDTH / 2 {
+        let mut y = 3;
+        while y < MAP_HEIGHT / 2 {
+            retvl = fill(retvl, (x, y), (x + 1, y + 1), MapTile::Block);
+            y += 3;
+        }
+        x += 4;
+    }
+    quartered(retvl)
+};
+
+/// Little walled-off corners to hide in, with a few mirrors and pipes
+/// already in place.
+pub const BUNKERS_BASE: BaseMap = {
+    let retvl = BaseMap::from_text(include_str!("arenas/bunkers.txt"));
+    verify_spawns(&retvl);
+    assert!(validate(&retvl, 0).is_ok());
+    retvl
+};
+
+/// Every hand-drawn base.
+pub const BASES: [BaseMap; 7] = [
+    HONEYCOMB_BASE,
+    OPEN_BASE,
+    CROSS_BASE,
+    RINGS_BASE,
+    MAZE_BASE,
+    PILLARS_BASE,
+    BUNKERS_BASE,
+];
+
+/// Picks a base for `seed`: usually one from `BASES`, but one time in
+/// `BASES.len() + 1` a cave grown from the seed instead.
+pub const fn pick_base(seed: u64) -> BaseMap {
+    let idx = (seed % (BASES.len() as u64 + 1)) as usize;
+    if idx < BASES.len() {
+        // `clone` isn't const, but the fields are all `Copy`.
+        BaseMap {
+            data: BASES[idx].data,
+            spawns: BASES[idx].spawns,
+        }
+    } else {
+        cave_base(seed)
+    }
+}
+
+/// Roughly how many tiles out of 100 start out as blocks in `cave_base`.
+const CAVE_FILL: u64 = 40;
+/// How many rounds of smoothing a cave gets.
+const CAVE_STEPS: u32 = 4;
+/// How many caves to grow before settling for `OPEN_BASE`.
+const CAVE_ATTEMPTS: u32 = 16;
+
+/// Grows a cave from `seed` with a cellular automaton: the top left quarter
+/// starts out as random noise, then each tile turns into a block if most of
+/// the tiles around it are. That quarter gets mirrored into the rest, and
+/// caves that leave someone walled in get thrown out.
+pub const fn cave_base(seed: u64) -> BaseMap {
+    let mut rng = Rng::with_seed(seed);
+    let mut attempt = 0;
+    while attempt < CAVE_ATTEMPTS {
+        let mut tiles = WALLED;
+        let mut x = 1;
+        while x < MAP_WIDTH / 2 {
+            let mut y = 1;
+            while y < MAP_HEIGHT / 2 {
+                let (nrng, roll) = rng.u64_const(0, 99);
+                rng = nrng;
+                if roll < CAVE_FILL {
+                    tiles[y][x] = MapTile::Block;
+                }
+                y += 1;
+            }
+            x += 1;
+        }
+        let mut step = 0;
+        while step < CAVE_STEPS {
+            tiles = smooth(tiles);
+            step += 1;
+        }
+        // Leave the spawns some room to move.
+        tiles = fill(tiles, (1, 1), (2, 2), MapTile::Empty);
+
+        let retvl = Symmetry::Quarters.apply(BaseMap::from_raw(tiles, CORNER_SPAWNS));
+        if validate(&retvl, 0).is_ok() {
+            return retvl;
+        }
+        attempt += 1;
+    }
+    quartered(WALLED)
+}
+
+/// One step of `cave_base`'s automaton over the top left quarter, treating
+/// everything past it as it'll be once mirrored.
+const fn smooth(tiles: Tiles) -> Tiles {
+    let mut retvl = tiles;
+    let mut x = 1;
+    while x < MAP_WIDTH / 2 {
+        let mut y = 1;
+        while y < MAP_HEIGHT / 2 {
+            let mut blocks = 0;
+            let mut nx = x - 1;
+            while nx <= x + 1 {
+                let mut ny = y - 1;
+                while ny <= y + 1 {
+                    // Past the middle is the mirror image of this side.
+                    let sx = if nx < MAP_WIDTH / 2 {
+                        nx
+                    } else {
+                        MAP_WIDTH - 1 - nx
+                    };
+                    let sy = if ny < MAP_HEIGHT / 2 {
+                        ny
+                    } else {
+                        MAP_HEIGHT - 1 - ny
+                    };
+                    if matches!(tiles[sy][sx], MapTile::Block) {
+                        blocks += 1;
+                    }
+                    ny += 1;
+                }
+                nx += 1;
+            }
+            retvl[y][x] = if blocks >= 5 {
+                MapTile::Block
+            } else {
+                MapTile::Empty
+            };
+            y += 1;
+        }
+        x += 1;
+    }
+    retvl
+}
+
+/// Fills from `(x0, y0)` to `(x1, y1)`, inclusive, with `tile`.
+const fn fill(
+    mut tiles: Tiles,
+    (x0, y0): (usize, usize),
+    (x1, y1): (usize, usize),
+    tile: MapTile,
+) -> Tiles {
+    let mut x = x0;
+    while x <= x1 {
+        let mut y = y0;
+        while y <= y1 {
+            tiles[y][x] = tile;
+            y += 1;
+        }
+        x += 1;
+    }
+    tiles
+}
+
+/// Finishes a layout drawn in its top left quarter, checking it's playable.
+const fn quartered(tiles: Tiles) -> BaseMap {
+    let retvl = Symmetry::Quarters.apply(BaseMap::from_raw(tiles, CORNER_SPAWNS));
+    verify_spawns(&retvl);
+    assert!(validate(&retvl, 0).is_ok());
+    retvl
+}
+
+const fn verify_spawns(map: &BaseMap) {
+    let mut sidx = 0;
+    while sidx < map.spawns.len() {
+        let (x, y) = map.spawns[sidx];
+        let tile_tag = map.get(x, y) as u8;
+        assert!(tile_tag == MapTile::Empty as u8);
+        sidx += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use proptest::prelude::{
+        any, prop_assert, prop_assert_eq, prop_oneof, proptest, Just, ProptestConfig, Strategy,
+    };
+
+    fn symmetry() -> impl Strategy<Value = Symmetry> {
+        prop_oneof![
+            Just(Symmetry::None),
+            Just(Symmetry::LeftRight),
+            Just(Symmetry::TopBottom),
+            Just(Symmetry::Quarters),
+            Just(Symmetry::HalfTurn),
+        ]
+    }
+
+    #[test]
+    fn test_bases() {
+        for (idx, base) in BASES.iter().enumerate() {
+            assert_eq!(validate(base, 0), Ok(()));
+            assert_eq!(base.player_spawns(), CORNER_SPAWNS);
+            if idx != 0 {
+                assert_eq!(&Symmetry::Quarters.apply(base.clone()), base);
+            }
+            for other in &BASES[idx + 1..] {
+                assert_ne!(base, other);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cave_base() {
+        let mut caves = Vec::new();
+        for seed in 1..50 {
+            let cave = cave_base(seed);
+            assert_eq!(cave, cave_base(seed));
+            assert_eq!(validate(&cave, 0), Ok(()), "\n{}", cave.pretty_print());
+            assert_eq!(&Symmetry::Quarters.apply(cave.clone()), &cave);
+            caves.push(cave);
+        }
+        caves.sort_by_key(|cave| cave.pretty_print());
+        caves.dedup();
+        assert!(caves.len() > 45, "Only {} different caves", caves.len());
+    }
+
+    #[test]
+    fn test_generate_on_every_base() {
+        let settings = GenerationSettings::DEFAULT;
+        // Seed 0 would leave the RNG stuck at 0.
+        for seed in 1..=(BASES.len() as u64 + 1) * 8 {
+            let base = pick_base(seed);
+            let map = generate(seed, base.clone(), settings);
+            assert_ne!(
+                map,
+                base,
+                "Seed {} never passed:\n{}",
+                seed,
+                base.pretty_print()
+            );
+            assert_eq!(validate(&map, settings.max_sight_gap), Ok(()));
+        }
+    }
+
+    proptest! {
+        // Each case can generate a few dozen maps.
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_generate_invariants(
+            seed in 1..=u64::MAX,
+            cave in any::<bool>(),
+            symmetry in symmetry(),
+        ) {
+            let base = if cave { cave_base(seed) } else { pick_base(seed) };
+            let settings = GenerationSettings {
+                symmetry,
+                ..GenerationSettings::DEFAULT
+            };
+            let map = generate(seed, base.clone(), settings);
+            prop_assert_eq!(&map, &generate(seed, base.clone(), settings));
+            prop_assert_eq!(validate(&map, settings.max_sight_gap), Ok(()));
+
+            // Only ever fills in the gaps in the base it was given.
+            let base = symmetry.apply(base);
+            prop_assert_eq!(map.player_spawns(), base.player_spawns());
+            for x in 0..MAP_WIDTH {
+                for y in 0..MAP_HEIGHT {
+                    let tile = map.get(x, y);
+                    prop_assert!(tile == base.get(x, y) || base.get(x, y) == MapTile::Empty);
+                }
+            }
+            for (x, y) in map.player_spawns() {
+                prop_assert!(map.get(x, y).allows_player());
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use super::*;
+
+// With an even number of tiles each way, no tile is its own image, so every
+// tile generate places has a full set of partners.
+const _: () = assert!(MAP_WIDTH % 2 == 0 && MAP_HEIGHT % 2 == 0);
+
+/// Which ways a generated map has to look the same, so no spawn gets a
+/// better arena than another.
+///
+/// Spawns are expected in the order `HONEYCOMB_BASE` has them: top left,
+/// bottom left, top right, bottom right.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Symmetry {
+    #[default]
+    None,
+    /// The right half mirrors the left.
+    LeftRight,
+    /// The bottom half mirrors the top.
+    TopBottom,
+    /// Both of the above, so every quarter mirrors the top left one.
+    Quarters,
+    /// The map looks the same after a half turn. Quarter turns would need a
+    /// square arena, which ours isn't.
+    HalfTurn,
+}
+
+impl Symmetry {
+    /// How many tiles each tile placed ends up as, itself included.
+    pub const fn group_size(self) -> u8 {
+        match self {
+            Symmetry::None => 1,
+            Symmetry::LeftRight | Symmetry::TopBottom | Symmetry::HalfTurn => 2,
+            Symmetry::Quarters => 4,
+        }
+    }
+
+    /// The other places a `tile` at `(x, y)` has to be copied to, and what it
+    /// becomes in each; only the first `group_size() - 1` are filled in.
+    pub const fn images(
+        self,
+        (x, y): (usize, usize),
+        tile: MapTile,
+    ) -> [((usize, usize), MapTile); 3] {
+        // Reflecting either way swaps which way the diagonal mirrors slant;
+        // a half turn is both reflections at once, so it leaves them be.
+        // Nothing else has a slant to swap.
+        let mirrored_x = (mirror_x((x, y)), tile.flipped());
+        let mirrored_y = (mirror_y((x, y)), tile.flipped());
+        let turned = (mirror_x(mirror_y((x, y))), tile);
+        match self {
+            Symmetry::None => [((x, y), tile); 3],
+            Symmetry::LeftRight => [mirrored_x; 3],
+            Symmetry::TopBottom => [mirrored_y; 3],
+            Symmetry::Quarters => [mirrored_x, mirrored_y, turned],
+            Symmetry::HalfTurn => [turned; 3],
+        }
+    }
+
+    /// Whether `(x, y)` is in the part of the map the rest gets copied from.
+    pub const fn is_source(self, x: usize, y: usize) -> bool {
+        let left = x < MAP_WIDTH / 2;
+        let top = y < MAP_HEIGHT / 2;
+        match self {
+            Symmetry::None => true,
+            Symmetry::LeftRight => left,
+            Symmetry::TopBottom | Symmetry::HalfTurn => top,
+            Symmetry::Quarters => left && top,
+        }
+    }
+
+    /// Copies the source part of `base` over the rest, and moves the spawns
+    /// that aren't in it to match the ones that are.
+    pub const fn apply(self, base: BaseMap) -> BaseMap {
+        let mut retvl = base;
+        let mut x = 0;
+        while x < MAP_WIDTH {
+            let mut y = 0;
+            while y < MAP_HEIGHT {
+                if self.is_source(x, y) {
+                    let images = self.images((x, y), retvl.get(x, y));
+                    let mut iidx = 0;
+                    while iidx + 1 < self.group_size() as usize {
+                        let ((ix, iy), tile) = images[iidx];
+                        retvl = retvl.with(ix, iy, tile);
+                        iidx += 1;
+                    }
+                }
+                y += 1;
+            }
+            x += 1;
+        }
+        let [top_left, bottom_left, top_right, _] = retvl.spawns;
+        retvl.spawns = match self {
+            Symmetry::None => retvl.spawns,
+            Symmetry::LeftRight => [
+                top_left,
+                bottom_left,
+                mirror_x(top_left),
+                mirror_x(bottom_left),
+            ],
+            Symmetry::TopBottom => [top_left, mirror_y(top_left), top_right, mirror_y(top_right)],
+            Symmetry::Quarters => [
+                top_left,
+                mirror_y(top_left),
+                mirror_x(top_left),
+                mirror_x(mirror_y(top_left)),
+            ],
+            Symmetry::HalfTurn => [
+                top_left,
+                bottom_left,
+                mirror_x(mirror_y(bottom_left)),
+                mirror_x(mirror_y(top_left)),
+            ],
+        };
+        retvl
+    }
+}
+
+const fn mirror_x((x, y): (usize, usize)) -> (usize, usize) {
+    (MAP_WIDTH - 1 - x, y)
+}
+
+const fn mirror_y((x, y): (usize, usize)) -> (usize, usize) {
+    (x, MAP_HEIGHT - 1 - y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    const MODES: [Symmetry; 4] = [
+        Symmetry::LeftRight,
+        Symmetry::TopBottom,
+        Symmetry::Quarters,
+        Symmetry::HalfTurn,
+    ];
+
+    fn assert_symmetric(map: &BaseMap, symmetry: Symmetry) {
+        for x in 0..MAP_WIDTH {
+            for y in 0..MAP_HEIGHT {
+                let images = symmetry.images((x, y), map.get(x, y));
+                for ((ix, iy), tile) in &images[..symmetry.group_size() as usize - 1] {
+                    assert_eq!(
+                        map.get(*ix, *iy),
+                        *tile,
+                        "{:?} broken between ({}, {}) and ({}, {}):\n{}",
+                        symmetry,
+                        x,
+                        y,
+                        ix,
+                        iy,
+                        map.pretty_print()
+                    );
+                }
+            }
+        }
+        let spawns = map.player_spawns();
+        for spawn in spawns {
+            for (image, _) in
+                &symmetry.images(spawn, MapTile::Empty)[..symmetry.group_size() as usize - 1]
+            {
+                assert!(
+                    spawns.contains(image),
+                    "{:?} spawns: {:?}",
+                    symmetry,
+                    spawns
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        // The honeycomb's already symmetric left to right, but its middle row
+        // of blocks is off by one top to bottom.
+        assert_eq!(Symmetry::LeftRight.apply(HONEYCOMB_BASE), HONEYCOMB_BASE);
+        assert_ne!(Symmetry::TopBottom.apply(HONEYCOMB_BASE), HONEYCOMB_BASE);
+        for symmetry in MODES {
+            let map = symmetry.apply(HONEYCOMB_BASE);
+            assert_symmetric(&map, symmetry);
+            assert_eq!(map.player_spawns(), HONEYCOMB_BASE.player_spawns());
+        }
+
+        let lopsided = HONEYCOMB_BASE.with(3, 3, MapTile::UpMirror);
+        let map = Symmetry::Quarters.apply(lopsided);
+        assert_eq!(map.get(MAP_WIDTH - 4, 3), MapTile::DownMirror);
+        assert_eq!(map.get(3, MAP_HEIGHT - 4), MapTile::DownMirror);
+        assert_eq!(map.get(MAP_WIDTH - 4, MAP_HEIGHT - 4), MapTile::UpMirror);
+        // Only the source part counts, so this one gets overwritten.
+        let lopsided = HONEYCOMB_BASE.with(MAP_WIDTH - 4, 3, MapTile::UpMirror);
+        assert_eq!(Symmetry::LeftRight.apply(lopsided), HONEYCOMB_BASE);
+    }
+
+    #[test]
+    fn test_generate_symmetric() {
+        for symmetry in MODES {
+            let settings = GenerationSettings {
+                symmetry,
+                ..GenerationSettings::DEFAULT
+            };
+            let mut maps = Vec::new();
+            for seed in 1..50 {
+                let map = generate(seed, HONEYCOMB_BASE, settings);
+                assert_symmetric(&map, symmetry);
+                assert_eq!(validate(&map, settings.max_sight_gap), Ok(()));
+                maps.push(map);
+            }
+            maps.dedup();
+            assert!(
+                maps.len() > 40,
+                "{:?} only made {} maps",
+                symmetry,
+                maps.len()
+            );
+        }
+    }
+}
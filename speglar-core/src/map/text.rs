@@ -0,0 +1,285 @@
+//! Reading maps back from the text `BaseMap::pretty_print` writes, so arenas
+//! can be drawn in a text editor.
+//!
+//! Each line is a row of `MAP_WIDTH` tiles, drawn as their `MapTile::repr`,
+//! and there are `MAP_HEIGHT` lines. Spawns are drawn as `1` to `4` in place
+//! of the empty tile they're on.
+use super::*;
+
+/// What `pretty_print` draws each spawn as, in `player_spawns` order.
+pub const SPAWN_MARKERS: [char; 4] = ['1', '2', '3', '4'];
+
+/// Where and why some map text couldn't be read. Lines and columns count
+/// from 1, like a text editor's.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ParseError {
+    /// There were this many lines instead of `MAP_HEIGHT`.
+    WrongHeight(usize),
+    /// This line had `width` characters instead of `MAP_WIDTH`.
+    WrongWidth { line: usize, width: usize },
+    /// A character that isn't a tile or a spawn.
+    BadCharacter {
+        line: usize,
+        column: usize,
+        found: char,
+    },
+    /// The same spawn showed up a second time.
+    DuplicateSpawn {
+        line: usize,
+        column: usize,
+        found: char,
+    },
+    /// This spawn never showed up.
+    MissingSpawn(char),
+}
+
+impl BaseMap {
+    /// Reads a map from the text `pretty_print` writes; a final newline and
+    /// Windows line endings are fine.
+    pub const fn parse(text: &str) -> Result<BaseMap, ParseError> {
+        let bytes = text.as_bytes();
+        let line_count = count_lines(bytes);
+        if line_count != MAP_HEIGHT {
+            return Err(ParseError::WrongHeight(line_count));
+        }
+
+        let mut tiles = [[MapTile::Empty; MAP_WIDTH]; MAP_HEIGHT];
+        let mut spawns = [(0, 0); 4];
+        let mut found_spawns = [false; 4];
+        let mut start = 0;
+        let mut y = 0;
+        while y < MAP_HEIGHT {
+            let end = line_end(bytes, start);
+            let width = count_chars(bytes, start, end);
+            if width != MAP_WIDTH {
+                return Err(ParseError::WrongWidth { line: y + 1, width });
+            }
+            let mut idx = start;
+            let mut x = 0;
+            while idx < end {
+                let (c, len) = decode_char(bytes, idx);
+                idx += len;
+                if let Some(tile) = MapTile::from_repr(c) {
+                    tiles[y][x] = tile;
+                } else if let Some(sidx) = spawn_index(c) {
+                    if found_spawns[sidx] {
+                        return Err(ParseError::DuplicateSpawn {
+                            line: y + 1,
+                            column: x + 1,
+                            found: c,
+                        });
+                    }
+                    found_spawns[sidx] = true;
+                    spawns[sidx] = (x, y);
+                } else {
+                    return Err(ParseError::BadCharacter {
+                        line: y + 1,
+                        column: x + 1,
+                        found: c,
+                    });
+                }
+                x += 1;
+            }
+            start = next_line(bytes, end);
+            y += 1;
+        }
+
+        let mut sidx = 0;
+        while sidx < found_spawns.len() {
+            if !found_spawns[sidx] {
+                return Err(ParseError::MissingSpawn(SPAWN_MARKERS[sidx]));
+            }
+            sidx += 1;
+        }
+        Ok(BaseMap::from_raw(tiles, spawns))
+    }
+
+    /// `parse` for use in consts, where a bad map fails the build.
+    pub const fn from_text(text: &str) -> BaseMap {
+        match BaseMap::parse(text) {
+            Ok(map) => map,
+            Err(ParseError::WrongHeight(_)) => panic!("Map text has the wrong number of lines"),
+            Err(ParseError::WrongWidth { .. }) => panic!("Map text has a line of the wrong width"),
+            Err(ParseError::BadCharacter { .. }) => {
+                panic!("Map text has a character that isn't a tile or a spawn")
+            }
+            Err(ParseError::DuplicateSpawn { .. }) => panic!("Map text has a spawn twice"),
+            Err(ParseError::MissingSpawn(_)) => panic!("Map text is missing a spawn"),
+        }
+    }
+}
+
+const fn spawn_index(c: char) -> Option<usize> {
+    let mut sidx = 0;
+    while sidx < SPAWN_MARKERS.len() {
+        if SPAWN_MARKERS[sidx] == c {
+            return Some(sidx);
+        }
+        sidx += 1;
+    }
+    None
+}
+
+/// Where the line starting at `start` ends, not counting its line ending.
+const fn line_end(bytes: &[u8], start: usize) -> usize {
+    let mut idx = start;
+    while idx < bytes.len() && bytes[idx] != b'\n' {
+        idx += 1;
+    }
+    if idx > start && bytes[idx - 1] == b'\r' {
+        idx - 1
+    } else {
+        idx
+    }
+}
+
+/// Where the line after the one ending at `end` starts.
+const fn next_line(bytes: &[u8], end: usize) -> usize {
+    let mut idx = end;
+    while idx < bytes.len() && bytes[idx] != b'\n' {
+        idx += 1;
+    }
+    idx + 1
+}
+
+const fn count_lines(bytes: &[u8]) -> usize {
+    let mut count = 0;
+    let mut start = 0;
+    while start < bytes.len() {
+        start = next_line(bytes, line_end(bytes, start));
+        count += 1;
+    }
+    count
+}
+
+/// How many characters are in `bytes[start..end]`, which is valid UTF-8.
+const fn count_chars(bytes: &[u8], start: usize, end: usize) -> usize {
+    let mut count = 0;
+    let mut idx = start;
+    while idx < end {
+        // Every character has exactly one byte that isn't 0b10xx_xxxx.
+        if bytes[idx] & 0xC0 != 0x80 {
+            count += 1;
+        }
+        idx += 1;
+    }
+    count
+}
+
+/// The character starting at `bytes[idx]`, which is valid UTF-8, and how many
+/// bytes it takes up.
+const fn decode_char(bytes: &[u8], idx: usize) -> (char, usize) {
+    let lead = bytes[idx] as u32;
+    let (len, mut value) = if lead < 0x80 {
+        (1, lead)
+    } else if lead < 0xE0 {
+        (2, lead & 0x1F)
+    } else if lead < 0xF0 {
+        (3, lead & 0x0F)
+    } else {
+        (4, lead & 0x07)
+    };
+    let mut offset = 1;
+    while offset < len {
+        value = (value << 6) | (bytes[idx + offset] as u32 & 0x3F);
+        offset += 1;
+    }
+    match char::from_u32(value) {
+        Some(c) => (c, len),
+        None => (char::REPLACEMENT_CHARACTER, len),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    #[test]
+    fn test_roundtrip() {
+        for base in BASES {
+            assert_eq!(BaseMap::parse(&base.pretty_print()), Ok(base));
+        }
+        let cave = cave_base(5);
+        assert_eq!(BaseMap::parse(&cave.pretty_print()), Ok(cave));
+        for seed in 1..200 {
+            let map = generate(seed, pick_base(seed), GenerationSettings::DEFAULT);
+            assert_eq!(
+                BaseMap::parse(&map.pretty_print()),
+                Ok(map),
+                "seed {}",
+                seed
+            );
+        }
+
+        let text = include_str!("arenas/bunkers.txt");
+        assert_eq!(BUNKERS_BASE.pretty_print(), text);
+        assert_eq!(BUNKERS_BASE.player_spawns(), HONEYCOMB_BASE.player_spawns());
+        assert_eq!(BUNKERS_BASE.get(10, 2), MapTile::UpMirror);
+        assert_eq!(BUNKERS_BASE.get(17, 2), MapTile::DownMirror);
+        assert_eq!(BUNKERS_BASE.get(4, 6), MapTile::VertPipe);
+
+        // Windows line endings and a missing final newline are both fine.
+        let windows = text.replace('\n', "\r\n");
+        assert_eq!(BaseMap::parse(&windows), Ok(BUNKERS_BASE));
+        assert_eq!(BaseMap::parse(text.trim_end()), Ok(BUNKERS_BASE));
+    }
+
+    /// The bunkers map with `line` (counting from 1) swapped out.
+    fn with_line(line: usize, replacement: &str) -> String {
+        let mut retvl = String::new();
+        for (idx, cur) in include_str!("arenas/bunkers.txt").lines().enumerate() {
+            retvl.push_str(if idx + 1 == line { replacement } else { cur });
+            retvl.push('\n');
+        }
+        retvl
+    }
+
+    #[test]
+    fn test_errors() {
+        let text = include_str!("arenas/bunkers.txt");
+        let short = &text[..text.len() - MAP_WIDTH - 1];
+        assert_eq!(BaseMap::parse(short), Err(ParseError::WrongHeight(17)));
+        let long = String::from(text) + text;
+        assert_eq!(BaseMap::parse(&long), Err(ParseError::WrongHeight(36)));
+        assert_eq!(BaseMap::parse(""), Err(ParseError::WrongHeight(0)));
+
+        let narrow = with_line(4, "x  x                    x");
+        assert_eq!(
+            BaseMap::parse(&narrow),
+            Err(ParseError::WrongWidth { line: 4, width: 25 })
+        );
+
+        // Columns count characters, not bytes.
+        let bad = with_line(3, "x  xxx    /  é   \\    xx?  x");
+        assert_eq!(
+            BaseMap::parse(&bad),
+            Err(ParseError::BadCharacter {
+                line: 3,
+                column: 14,
+                found: 'é'
+            })
+        );
+        let bad = with_line(3, "x  xxx    /      \\    xx?  x");
+        assert_eq!(
+            BaseMap::parse(&bad),
+            Err(ParseError::BadCharacter {
+                line: 3,
+                column: 25,
+                found: '?'
+            })
+        );
+
+        let twice = with_line(8, "x   \"       1          \"   x");
+        assert_eq!(
+            BaseMap::parse(&twice),
+            Err(ParseError::DuplicateSpawn {
+                line: 8,
+                column: 13,
+                found: '1'
+            })
+        );
+        let missing = with_line(17, "x                         4x");
+        assert_eq!(BaseMap::parse(&missing), Err(ParseError::MissingSpawn('2')));
+    }
+}
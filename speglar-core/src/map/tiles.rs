@@ -1,5 +1,4 @@
-use crate::graphics::tags;
-use agb::display::object::Tag;
+use crate::Direction;
 
 #[repr(u8)]
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, PartialOrd, Ord, Default)]
@@ -92,14 +91,6 @@ impl MapTile {
             VertPipe => Some(5),
         }
     }
-    pub fn tag(self) -> Option<&'static Tag> {
-        use MapTile::*;
-        match self {
-            UpMirror => Some(tags::MAP_UP_MIRROR),
-            DownMirror => Some(tags::MAP_UP_MIRROR),
-            _ => None,
-        }
-    }
     pub const fn allows_player(self) -> bool {
         use MapTile::*;
         matches!(
@@ -107,6 +98,23 @@ impl MapTile {
             Empty | UpMirror | DownMirror | HorizMirror | VertMirror
         )
     }
+    /// The way a bullet moving in `dir` leaves this tile, if it got in at all.
+    pub const fn deflected(self, dir: Direction) -> Direction {
+        use Direction::*;
+        use MapTile::*;
+        match (self, dir) {
+            (UpMirror, Right) => Up,
+            (UpMirror, Up) => Right,
+            (UpMirror, Down) => Left,
+            (UpMirror, Left) => Down,
+            (DownMirror, Right) => Down,
+            (DownMirror, Down) => Right,
+            (DownMirror, Up) => Left,
+            (DownMirror, Left) => Up,
+            (HorizMirror, Up | Down) | (VertMirror, Left | Right) => dir.flipped(),
+            _ => dir,
+        }
+    }
     pub const fn repr(self) -> char {
         use MapTile::*;
         match self {
@@ -120,4 +128,36 @@ impl MapTile {
             VertPipe => '"',
         }
     }
+    /// The tile `repr` gives `c` for, if any.
+    pub const fn from_repr(c: char) -> Option<MapTile> {
+        use MapTile::*;
+        match c {
+            ' ' => Some(Empty),
+            'x' => Some(Block),
+            '/' => Some(UpMirror),
+            '\\' => Some(DownMirror),
+            '-' => Some(HorizMirror),
+            '|' => Some(VertMirror),
+            '=' => Some(HorizPipe),
+            '"' => Some(VertPipe),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a bullet moving in `dir` makes it into `tile` rather than being
+/// destroyed by it.
+pub const fn bullet_is_passable(tile: MapTile, dir: Direction) -> bool {
+    use MapTile::*;
+
+    match tile {
+        Empty => true,
+        Block => false,
+        UpMirror => true,
+        DownMirror => true,
+        HorizMirror => dir.is_vertical(),
+        VertMirror => dir.is_horizontal(),
+        HorizPipe => dir.is_horizontal(),
+        VertPipe => dir.is_vertical(),
+    }
 }
@@ -0,0 +1,214 @@
+use crate::Direction;
+
+use super::*;
+
+/// How far along a sight line we look before calling it as long as it gets,
+/// which also stops us chasing bullets that loop forever.
+pub const MAX_SIGHT_LINE: u16 = (MAP_WIDTH + MAP_HEIGHT) as u16;
+
+/// Why a map isn't fit to play on.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MapProblem {
+    /// The spawn at this index of `BaseMap::player_spawns` isn't on a tile
+    /// players can stand on.
+    BlockedSpawn(usize),
+    /// Players at these two spawns can't walk to each other.
+    Unreachable(usize, usize),
+    /// One spawn can see much further down its sight lines than another.
+    UnfairSightLines { longest: u16, shortest: u16 },
+}
+
+/// Checks that every spawn can walk to every other, and that no spawn's
+/// sight lines are more than `max_sight_gap` tiles longer than another's.
+pub const fn validate(map: &BaseMap, max_sight_gap: u16) -> Result<(), MapProblem> {
+    let mut sidx = 0;
+    while sidx < map.spawns.len() {
+        let (x, y) = map.spawns[sidx];
+        if !map.get(x, y).allows_player() {
+            return Err(MapProblem::BlockedSpawn(sidx));
+        }
+        sidx += 1;
+    }
+
+    // Walking goes both ways, so everyone reaching the first spawn means
+    // everyone reaches each other.
+    let reachable = reachable_from(map, map.spawns[0]);
+    let mut sidx = 1;
+    while sidx < map.spawns.len() {
+        let (x, y) = map.spawns[sidx];
+        if !reachable[y][x] {
+            return Err(MapProblem::Unreachable(0, sidx));
+        }
+        sidx += 1;
+    }
+
+    let sight = sight_lines(map);
+    let mut longest = 0;
+    let mut shortest = u16::MAX;
+    let mut sidx = 0;
+    while sidx < sight.len() {
+        if sight[sidx] > longest {
+            longest = sight[sidx];
+        }
+        if sight[sidx] < shortest {
+            shortest = sight[sidx];
+        }
+        sidx += 1;
+    }
+    if longest - shortest > max_sight_gap {
+        return Err(MapProblem::UnfairSightLines { longest, shortest });
+    }
+    Ok(())
+}
+
+/// Which tiles a player could walk to from `start`.
+pub const fn reachable_from(
+    map: &BaseMap,
+    (x, y): (usize, usize),
+) -> [[bool; MAP_WIDTH]; MAP_HEIGHT] {
+    let mut seen = [[false; MAP_WIDTH]; MAP_HEIGHT];
+    if !map.get(x, y).allows_player() {
+        return seen;
+    }
+    // Every tile goes on at most once, and the coordinates all fit in a byte,
+    // which keeps this small enough for the GBA's stack.
+    let mut stack = [(0u8, 0u8); MAP_WIDTH * MAP_HEIGHT];
+    stack[0] = (x as u8, y as u8);
+    seen[y][x] = true;
+    let mut len = 1;
+    while len > 0 {
+        len -= 1;
+        let (x, y) = (stack[len].0 as usize, stack[len].1 as usize);
+        let mut didx = 0;
+        while didx < DIRECTIONS.len() {
+            didx += 1;
+            let (nx, ny) = match step((x, y), DIRECTIONS[didx - 1]) {
+                Some(next) => next,
+                None => continue,
+            };
+            if seen[ny][nx] || !map.get(nx, ny).allows_player() {
+                continue;
+            }
+            seen[ny][nx] = true;
+            stack[len] = (nx as u8, ny as u8);
+            len += 1;
+        }
+    }
+    seen
+}
+
+/// How many tiles a bullet fired from each spawn crosses before it dies,
+/// summed over all four directions and following mirrors along the way.
+pub const fn sight_lines(map: &BaseMap) -> [u16; 4] {
+    let mut retvl = [0; 4];
+    let mut sidx = 0;
+    while sidx < map.spawns.len() {
+        let mut didx = 0;
+        while didx < DIRECTIONS.len() {
+            retvl[sidx] += sight_line(map, map.spawns[sidx], DIRECTIONS[didx]);
+            didx += 1;
+        }
+        sidx += 1;
+    }
+    retvl
+}
+
+/// How many tiles a bullet fired from `start` in `dir` crosses before it
+/// dies, up to `MAX_SIGHT_LINE`.
+pub const fn sight_line(map: &BaseMap, start: (usize, usize), dir: Direction) -> u16 {
+    let mut pos = start;
+    let mut dir = dir;
+    let mut length = 0;
+    while length < MAX_SIGHT_LINE {
+        pos = match step(pos, dir) {
+            Some(next) => next,
+            None => break,
+        };
+        let tile = map.get(pos.0, pos.1);
+        if !bullet_is_passable(tile, dir) {
+            break;
+        }
+        dir = tile.deflected(dir);
+        length += 1;
+    }
+    length
+}
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+
+/// The tile next to `(x, y)` in `dir`, if it's still on the map.
+const fn step((x, y): (usize, usize), dir: Direction) -> Option<(usize, usize)> {
+    match dir {
+        Direction::Up if y > 0 => Some((x, y - 1)),
+        Direction::Down if y + 1 < MAP_HEIGHT => Some((x, y + 1)),
+        Direction::Left if x > 0 => Some((x - 1, y)),
+        Direction::Right if x + 1 < MAP_WIDTH => Some((x + 1, y)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::tests::EMPTY_MAP;
+    use Direction::*;
+
+    #[test]
+    fn test_validate_spawns() {
+        assert_eq!(validate(&HONEYCOMB_BASE, 0), Ok(()));
+
+        let blocked = HONEYCOMB_BASE.with(1, MAP_HEIGHT - 2, MapTile::Block);
+        assert_eq!(validate(&blocked, 0), Err(MapProblem::BlockedSpawn(1)));
+
+        // Players can't get through pipes, even though bullets can.
+        let walled_in = HONEYCOMB_BASE
+            .with(2, 1, MapTile::HorizPipe)
+            .with(1, 2, MapTile::VertPipe);
+        assert_eq!(validate(&walled_in, 99), Err(MapProblem::Unreachable(0, 1)));
+        assert_eq!(sight_lines(&walled_in), sight_lines(&HONEYCOMB_BASE));
+    }
+
+    #[test]
+    fn test_validate_sight_lines() {
+        let unfair = HONEYCOMB_BASE.with(2, 1, MapTile::VertMirror);
+        let sight = sight_lines(&unfair);
+        let longest = *sight.iter().max().unwrap();
+        // The mirror bounces the first spawn's shots straight back at them.
+        assert_eq!(sight.iter().min(), Some(&sight[0]));
+        assert_eq!(
+            validate(&unfair, 0),
+            Err(MapProblem::UnfairSightLines {
+                longest,
+                shortest: sight[0]
+            })
+        );
+        assert_eq!(validate(&unfair, longest - sight[0]), Ok(()));
+    }
+
+    #[test]
+    fn test_sight_line_follows_mirrors() {
+        assert_eq!(sight_line(&EMPTY_MAP, (5, 5), Right), 22);
+        let map = EMPTY_MAP.with(10, 5, MapTile::UpMirror);
+        assert_eq!(sight_line(&map, (5, 5), Right), 10);
+        let map = EMPTY_MAP.with(10, 5, MapTile::HorizMirror);
+        assert_eq!(sight_line(&map, (5, 5), Right), 4);
+        let map = EMPTY_MAP.with(10, 5, MapTile::VertMirror);
+        assert_eq!(sight_line(&map, (5, 5), Right), 15);
+        let map = EMPTY_MAP.with(10, 5, MapTile::VertPipe);
+        assert_eq!(sight_line(&map, (5, 5), Right), 4);
+        assert_eq!(sight_line(&map, (10, 0), Down), MAP_HEIGHT as u16 - 1);
+
+        // Bullets going round in circles see as far as we look.
+        let looped = EMPTY_MAP
+            .with(10, 5, MapTile::DownMirror)
+            .with(10, 8, MapTile::UpMirror)
+            .with(3, 8, MapTile::DownMirror)
+            .with(3, 5, MapTile::UpMirror);
+        assert_eq!(sight_line(&looped, (5, 5), Right), MAX_SIGHT_LINE);
+    }
+}
@@ -0,0 +1,394 @@
+use core::{fmt::Debug, hash::Hash};
+
+use crate::{
+    link::{
+        multiplayer::{MultiplayerLink, PlayerId, TransferError},
+        RegisterBackend,
+    },
+    logs::warning,
+    ControlsRepr, PlayerTag,
+};
+
+mod desync;
+mod rollback;
+mod seed;
+pub use desync::*;
+pub use rollback::*;
+pub use seed::*;
+
+/// A game that can be stepped by netplay.
+///
+/// Rolling back restores an earlier clone of the game, so cloning needs to
+/// capture all of its gameplay state; the same goes for hashing, which is how
+/// consoles check they still agree.
+pub trait NetGame: Clone + Hash + Debug {
+    fn step(&mut self, inputs: &[ControlsRepr; 4]);
+}
+
+/// How many bits of the frame number get sent along with each input.
+pub const FRAME_SEQ_BITS: u32 = 7;
+const FRAME_SEQ_MASK: u32 = (1 << FRAME_SEQ_BITS) - 1;
+
+/// A player's controls for a single frame, as sent over the link cable.
+///
+/// The frame number is truncated to `FRAME_SEQ_BITS`, which is plenty since
+/// consoles are never more than a couple of frames apart.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct InputWord {
+    pub frame_seq: u8,
+    pub controls: ControlsRepr,
+    /// A few bits of a state checksum; see `DesyncCheck`.
+    pub check: u8,
+}
+
+/*
+  Bit   Expl.
+  0-4   Controls (see `ControlsRepr::to_bits`)
+  5-7   Checksum bits (see `DesyncCheck`)
+  8-14  Low bits of the frame number
+  15    Always 0, so a word can never be mistaken for "no data" (0xFFFF)
+*/
+impl InputWord {
+    pub const fn new(frame: u32, controls: ControlsRepr) -> Self {
+        Self {
+            frame_seq: (frame & FRAME_SEQ_MASK) as u8,
+            controls,
+            check: 0,
+        }
+    }
+    pub const fn with_check(mut self, check: u8) -> Self {
+        self.check = check & 0x7;
+        self
+    }
+    pub const fn encode(self) -> u16 {
+        ((self.frame_seq as u16) << 8) | ((self.check as u16) << 5) | self.controls.to_bits() as u16
+    }
+    pub const fn decode(raw: u16) -> Option<Self> {
+        if raw & 0x8000 != 0 {
+            return None;
+        }
+        let Some(controls) = ControlsRepr::from_bits(raw as u8 & 0x1F) else {
+            return None;
+        };
+        Some(Self {
+            frame_seq: ((raw >> 8) as u8) & FRAME_SEQ_MASK as u8,
+            controls,
+            check: (raw >> 5) as u8 & 0x7,
+        })
+    }
+    pub const fn is_for(self, frame: u32) -> bool {
+        self.frame_seq as u32 == frame & FRAME_SEQ_MASK
+    }
+}
+
+/// The number of players in a session; connected GBAs always have the lowest
+/// IDs, so this also gives which `PlayerTag`s are in play.
+fn num_players(connected: &[bool; 4]) -> usize {
+    connected.iter().rposition(|c| *c).map_or(0, |idx| idx + 1)
+}
+
+/// Lockstep input exchange over the link cable.
+///
+/// Every frame, each console `submit`s its own controls and then waits until it
+/// has everyone else's controls for that same frame before stepping the game,
+/// so every console steps with identical inputs.
+pub struct Lockstep<'a, 'q, G, B> {
+    serial: &'a mut MultiplayerLink<'q, B>,
+    local_id: PlayerId,
+    connected: [bool; 4],
+    frame: u32,
+    inputs: [Option<ControlsRepr>; 4],
+    /// Our last input word, until we've seen it go out over the link.
+    unsent: Option<u16>,
+    check: DesyncCheck<G>,
+}
+
+impl<'a, 'q, G: NetGame, B: RegisterBackend> Lockstep<'a, 'q, G, B> {
+    /// Starts a lockstep session; `serial` must have already run
+    /// `initialize_id` and have its interrupt filling the transfer queues.
+    pub fn new(serial: &'a mut MultiplayerLink<'q, B>) -> Self {
+        let local_id = serial
+            .id()
+            .expect("initialize_id should be called before starting lockstep");
+        let connected = serial.connected_players();
+        serial.clear_transfers();
+        serial.mark_ready();
+        serial.reset_connection_timer();
+        Self {
+            serial,
+            local_id,
+            connected,
+            frame: 0,
+            inputs: [None; 4],
+            unsent: None,
+            // Every frame's state is final as soon as it's stepped.
+            check: DesyncCheck::new(0),
+        }
+    }
+    /// The frame we're currently collecting inputs for.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+    pub fn local_tag(&self) -> PlayerTag {
+        self.local_id.into()
+    }
+    pub fn num_players(&self) -> usize {
+        num_players(&self.connected)
+    }
+
+    /// Sets our controls for the current frame and starts sending them out.
+    pub fn submit(&mut self, controls: ControlsRepr) {
+        self.inputs[self.local_id as usize] = Some(controls);
+        let check = self.check.outgoing(self.frame);
+        let word = InputWord::new(self.frame, controls).with_check(check);
+        self.serial.write_send_reg(word.encode());
+        self.unsent = Some(word.encode());
+    }
+
+    fn all_arrived(&self) -> bool {
+        self.inputs
+            .iter()
+            .zip(self.connected)
+            .all(|(input, connected)| input.is_some() || !connected)
+    }
+
+    /// Drives the link, returning every player's controls (indexed by
+    /// `PlayerTag`) once they've all arrived for the current frame, after
+    /// which the next frame starts.
+    pub fn poll(&mut self) -> Option<[ControlsRepr; 4]> {
+        // Nothing to do until our own controls are in.
+        self.inputs[self.local_id as usize]?;
+        self.start_transfer();
+        // Only read up to the transfer that completes the frame; anything
+        // after it may already hold other players' inputs for the next one.
+        while !self.all_arrived() {
+            let transfer = self.serial.next_transfer()?;
+            self.check_sent(&transfer);
+            for id in PlayerId::ALL {
+                if id == self.local_id || !self.connected[id as usize] {
+                    continue;
+                }
+                let Some(word) = InputWord::decode(transfer[id as usize]) else {
+                    continue;
+                };
+                if word.is_for(self.frame) && self.inputs[id as usize].is_none() {
+                    self.inputs[id as usize] = Some(word.controls);
+                    self.check.receive(id.into(), self.frame, word.check);
+                }
+            }
+        }
+        let retvl = self.inputs.map(Option::unwrap_or_default);
+        self.inputs = [None; 4];
+        self.frame += 1;
+        Some(retvl)
+    }
+
+    /// Blocks until `poll` has every player's controls for the current frame,
+    /// or until someone drops out.
+    pub fn wait(&mut self) -> Result<[ControlsRepr; 4], TransferError> {
+        self.serial.reset_connection_timer();
+        loop {
+            if let Some(inputs) = self.poll() {
+                return Ok(inputs);
+            }
+            self.serial.check_connection(self.connected)?;
+        }
+    }
+
+    /// Sends `local`, waits for everyone else, then steps `game`.
+    pub fn advance(&mut self, game: &mut G, local: ControlsRepr) -> Result<(), TransferError> {
+        if let Some(checkpoint) = self.check.wants_state(self.frame) {
+            self.check.record(checkpoint, game);
+        }
+        self.submit(local);
+        let inputs = self.wait()?;
+        game.step(&inputs);
+        Ok(())
+    }
+
+    /// Drives the link without stepping, returning whether our last input has
+    /// gone out; see `NetSession::flush`.
+    pub fn flush(&mut self) -> Result<bool, TransferError> {
+        self.start_transfer();
+        while let Some(transfer) = self.serial.next_transfer() {
+            self.check_sent(&transfer);
+        }
+        self.serial.check_connection(self.connected)?;
+        Ok(self.unsent.is_none())
+    }
+
+    fn start_transfer(&mut self) {
+        if self.local_id == PlayerId::Parent && self.serial.all_ready() {
+            match self.serial.start_transfer() {
+                Ok(())
+                | Err(TransferError::AlreadyInProgress)
+                | Err(TransferError::FailedReadyCheck) => {}
+                Err(e) => {
+                    warning!("Lockstep transfer failed: {:?}", e);
+                }
+            }
+        }
+    }
+
+    fn check_sent(&mut self, transfer: &[u16; 4]) {
+        if self.unsent == Some(transfer[self.local_id as usize]) {
+            self.unsent = None;
+        }
+    }
+}
+
+/// Either kind of netplay session, picked when the match starts.
+// There's only ever the one session, so boxing the bigger one saves nothing.
+#[allow(clippy::large_enum_variant)]
+pub enum NetSession<'a, 'q, G, B> {
+    Lockstep(Lockstep<'a, 'q, G, B>),
+    Rollback(Rollback<'a, 'q, G, B>),
+}
+
+impl<'a, 'q, G: NetGame, B: RegisterBackend> NetSession<'a, 'q, G, B> {
+    pub fn local_tag(&self) -> PlayerTag {
+        match self {
+            NetSession::Lockstep(inner) => inner.local_tag(),
+            NetSession::Rollback(inner) => inner.local_tag(),
+        }
+    }
+    pub fn num_players(&self) -> usize {
+        match self {
+            NetSession::Lockstep(inner) => inner.num_players(),
+            NetSession::Rollback(inner) => inner.num_players(),
+        }
+    }
+    /// The first time the consoles were found to disagree on the game state.
+    pub fn desync(&self) -> Option<Desync> {
+        match self {
+            NetSession::Lockstep(inner) => inner.check.desync(),
+            NetSession::Rollback(inner) => inner.desync(),
+        }
+    }
+    /// Steps `game` forward with `local` as our controls for the next frame.
+    ///
+    /// Returns `Ok(false)` if the game couldn't advance this frame, in which
+    /// case `local` was dropped, or an error once another GBA drops out.
+    pub fn advance(&mut self, game: &mut G, local: ControlsRepr) -> Result<bool, TransferError> {
+        match self {
+            NetSession::Lockstep(inner) => {
+                inner.advance(game, local)?;
+                Ok(true)
+            }
+            NetSession::Rollback(inner) => inner.advance(game, local),
+        }
+    }
+    /// Drives the link without stepping the game, returning whether all our
+    /// inputs have gone out.
+    ///
+    /// This is for once the game has got somewhere no input can take it back
+    /// from: once everything we sent is out, everyone else can get there too,
+    /// so it's safe to stop sending.
+    pub fn flush(&mut self) -> Result<bool, TransferError> {
+        match self {
+            NetSession::Lockstep(inner) => inner.flush(),
+            NetSession::Rollback(inner) => inner.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::link::{
+        queues::TransferQueues,
+        tests::{finish_transfer, linked},
+        SimulatedLink,
+    };
+    use crate::Direction;
+
+    /// A game whose state is just every input it was stepped with.
+    #[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+    pub(crate) struct History(pub(crate) Vec<[ControlsRepr; 4]>);
+
+    impl NetGame for History {
+        fn step(&mut self, inputs: &[ControlsRepr; 4]) {
+            self.0.push(*inputs);
+        }
+    }
+
+    pub(crate) const FIRING: ControlsRepr = ControlsRepr {
+        dir: None,
+        fired_bullet: true,
+        fired_shield: false,
+    };
+
+    #[test]
+    fn test_words() {
+        let controls = ControlsRepr {
+            dir: Some(Direction::Left),
+            ..FIRING
+        };
+        let word = InputWord::new(0x1234, controls).with_check(0x5);
+        assert_eq!(InputWord::decode(word.encode()), Some(word));
+        assert!(word.is_for(0x1234 + (1 << FRAME_SEQ_BITS)));
+        assert!(!word.is_for(0x1235));
+        // "No data" and the handshakes' words are never inputs.
+        assert_eq!(InputWord::decode(0xFFFF), None);
+        assert_eq!(InputWord::decode(0x8123), None);
+    }
+
+    #[test]
+    fn test_lockstep() {
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let (parent_link, child_link) = links.split_at_mut(1);
+        let mut parent = Lockstep::<History, _>::new(&mut parent_link[0]);
+        let mut child = Lockstep::<History, _>::new(&mut child_link[0]);
+        assert_eq!(parent.local_tag(), PlayerTag::P1);
+        assert_eq!(child.local_tag(), PlayerTag::P2);
+        assert_eq!(parent.num_players(), 2);
+
+        for frame in 0..4 {
+            let moving = ControlsRepr {
+                dir: Some(Direction::Up),
+                ..ControlsRepr::default()
+            };
+            parent.submit(moving);
+            child.submit(FIRING);
+            // Neither side gets anywhere until a transfer goes through.
+            assert_eq!(parent.poll(), None);
+            assert_eq!(child.poll(), None);
+            finish_transfer(&link, &queues);
+            let expected = [
+                moving,
+                FIRING,
+                ControlsRepr::default(),
+                ControlsRepr::default(),
+            ];
+            assert_eq!(parent.poll(), Some(expected));
+            assert_eq!(child.poll(), Some(expected));
+            assert_eq!(parent.frame(), frame + 1);
+            assert_eq!(child.frame(), frame + 1);
+        }
+    }
+
+    #[test]
+    fn test_lockstep_flush() {
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let (parent_link, child_link) = links.split_at_mut(1);
+        let mut parent = Lockstep::<History, _>::new(&mut parent_link[0]);
+        let mut child = Lockstep::<History, _>::new(&mut child_link[0]);
+        assert_eq!(parent.flush(), Ok(true));
+
+        parent.submit(FIRING);
+        child.submit(FIRING);
+        assert_eq!(child.flush(), Ok(false));
+        assert_eq!(parent.flush(), Ok(false));
+        finish_transfer(&link, &queues);
+        assert!(parent.poll().is_some());
+        assert!(child.poll().is_some());
+        assert_eq!(parent.flush(), Ok(true));
+        assert_eq!(child.flush(), Ok(true));
+    }
+}
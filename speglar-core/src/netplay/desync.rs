@@ -0,0 +1,203 @@
+use core::{fmt::Debug, hash::Hash};
+
+use super::*;
+use crate::{checksum::checksum, logs};
+
+/// How often, in frames, the game state gets checksummed and compared.
+pub const CHECK_INTERVAL: u32 = 32;
+/// How many bits of checksum ride along with each `InputWord`.
+pub const CHECK_BITS_PER_FRAME: u32 = 3;
+/// How many frames it takes to send a whole checksum.
+const CHECK_FRAMES: u32 = 4;
+const CHECK_MASK: u16 = (1 << (CHECK_BITS_PER_FRAME * CHECK_FRAMES)) - 1;
+
+/// Two consoles disagreed about the game state.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Desync {
+    /// The frame whose starting state was checksummed.
+    pub frame: u32,
+    pub player: PlayerTag,
+    pub local: u16,
+    pub remote: u16,
+}
+
+/// Spreads a checksum of the game state at every `CHECK_INTERVAL`th frame over
+/// the spare bits of the next few `InputWord`s, and checks the ones the other
+/// players send back.
+///
+/// The checksum for checkpoint frame `C` goes out with the inputs for frames
+/// `C + delay` onwards, so a session that runs ahead of confirmed inputs can
+/// wait until the state at `C` is final before checksumming it.
+pub struct DesyncCheck<G> {
+    delay: u32,
+    /// Our checksum for the latest checkpoint, plus the state it came from so
+    /// it can be dumped if someone disagrees.
+    local: Option<(u32, u16, G)>,
+    /// The checksum each player is partway through sending.
+    partial: [(u32, u16); 4],
+    /// The last full checksum each player sent.
+    remote: [Option<(u32, u16)>; 4],
+    desync: Option<Desync>,
+}
+
+impl<G: Hash + Debug + Clone> DesyncCheck<G> {
+    pub fn new(delay: u32) -> Self {
+        Self {
+            delay,
+            local: None,
+            partial: [(0, 0); 4],
+            remote: [None; 4],
+            desync: None,
+        }
+    }
+
+    /// The checkpoint whose checksum is going out in `frame`'s input, and
+    /// which chunk of it.
+    fn window(&self, frame: u32) -> Option<(u32, u32)> {
+        let since = frame.checked_sub(self.delay)?;
+        let chunk = since % CHECK_INTERVAL;
+        (chunk < CHECK_FRAMES).then_some((since - chunk, chunk))
+    }
+
+    /// If we need the state at a checkpoint before sending `frame`'s input,
+    /// returns that checkpoint; pass its state to `record`.
+    pub fn wants_state(&self, frame: u32) -> Option<u32> {
+        match self.window(frame)? {
+            (checkpoint, 0) => Some(checkpoint),
+            _ => None,
+        }
+    }
+
+    /// Checksums `state` as the state at the start of `checkpoint`.
+    pub fn record(&mut self, checkpoint: u32, state: &G) {
+        let sum = checksum(state) as u16 & CHECK_MASK;
+        self.local = Some((checkpoint, sum, state.clone()));
+        for (pidx, remote) in self.remote.into_iter().enumerate() {
+            if let Some((frame, remote)) = remote {
+                self.compare(PlayerTag::from_u8(pidx as u8), frame, remote);
+            }
+        }
+    }
+
+    /// The checksum bits to send with our input for `frame`.
+    pub fn outgoing(&self, frame: u32) -> u8 {
+        let Some((checkpoint, chunk)) = self.window(frame) else {
+            return 0;
+        };
+        match &self.local {
+            Some((local_frame, sum, _)) if *local_frame == checkpoint => {
+                let bits = sum >> (chunk * CHECK_BITS_PER_FRAME);
+                (bits as u8) & ((1 << CHECK_BITS_PER_FRAME) - 1)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Handles the checksum bits from `player`'s input for `frame`; inputs
+    /// need to be passed in order for each player.
+    pub fn receive(&mut self, player: PlayerTag, frame: u32, bits: u8) {
+        let Some((checkpoint, chunk)) = self.window(frame) else {
+            return;
+        };
+        let pidx = player as usize;
+        if chunk == 0 {
+            self.partial[pidx] = (checkpoint, 0);
+        }
+        let (partial_frame, partial) = &mut self.partial[pidx];
+        if *partial_frame != checkpoint {
+            return;
+        }
+        *partial |= (bits as u16) << (chunk * CHECK_BITS_PER_FRAME);
+        if chunk == CHECK_FRAMES - 1 {
+            let remote = *partial;
+            self.remote[pidx] = Some((checkpoint, remote));
+            self.compare(player, checkpoint, remote);
+        }
+    }
+
+    fn compare(&mut self, player: PlayerTag, checkpoint: u32, remote: u16) {
+        let Some((local_frame, local, state)) = &self.local else {
+            return;
+        };
+        if *local_frame != checkpoint || *local == remote || self.desync.is_some() {
+            return;
+        }
+        let desync = Desync {
+            frame: checkpoint,
+            player,
+            local: *local,
+            remote,
+        };
+        warning!(
+            "Desync at frame {}: our checksum was {:03x}, {:?} sent {:03x}",
+            checkpoint,
+            local,
+            player,
+            remote
+        );
+        // The other console dumps its own state when it notices too, so
+        // between the two logs we have both sides.
+        logs::dump("Our state", state);
+        self.desync = Some(desync);
+    }
+
+    /// The first desync found, if any.
+    pub fn desync(&self) -> Option<Desync> {
+        self.desync
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `a` and `b` through the first checkpoint with each other's
+    /// checksums, where their games are at `state_a` and `state_b`.
+    fn exchange(a: &mut DesyncCheck<u32>, b: &mut DesyncCheck<u32>, state_a: u32, state_b: u32) {
+        for frame in 0..CHECK_FRAMES {
+            if let Some(checkpoint) = a.wants_state(frame) {
+                a.record(checkpoint, &state_a);
+            }
+            if let Some(checkpoint) = b.wants_state(frame) {
+                b.record(checkpoint, &state_b);
+            }
+            let (from_a, from_b) = (a.outgoing(frame), b.outgoing(frame));
+            a.receive(PlayerTag::P2, frame, from_b);
+            b.receive(PlayerTag::P1, frame, from_a);
+        }
+    }
+
+    #[test]
+    fn test_matching_states() {
+        let mut a = DesyncCheck::new(0);
+        let mut b = DesyncCheck::new(0);
+        exchange(&mut a, &mut b, 7, 7);
+        assert_eq!(a.desync(), None);
+        assert_eq!(b.desync(), None);
+    }
+
+    #[test]
+    fn test_different_states() {
+        let mut a = DesyncCheck::new(0);
+        let mut b = DesyncCheck::new(0);
+        exchange(&mut a, &mut b, 7, 8);
+        assert!(matches!(
+            a.desync(),
+            Some(Desync {
+                frame: 0,
+                player: PlayerTag::P2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            b.desync(),
+            Some(Desync {
+                frame: 0,
+                player: PlayerTag::P1,
+                ..
+            })
+        ));
+        let (a, b) = (a.desync().unwrap(), b.desync().unwrap());
+        assert_eq!((a.local, a.remote), (b.remote, b.local));
+    }
+}
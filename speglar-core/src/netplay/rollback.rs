@@ -0,0 +1,512 @@
+use alloc::{collections::VecDeque, vec::Vec};
+
+use super::*;
+
+/// How many frames we'll simulate past the last frame where we have every
+/// player's real controls before stalling.
+pub const MAX_ROLLBACK: u32 = 8;
+/// Other consoles can be up to `MAX_ROLLBACK` frames ahead of us as well as
+/// behind, so we need to hold onto inputs in both directions.
+const HISTORY_LEN: usize = 2 * MAX_ROLLBACK as usize + 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+struct FrameInputs {
+    frame: u32,
+    controls: [ControlsRepr; 4],
+    confirmed: [bool; 4],
+}
+
+impl FrameInputs {
+    const fn new(frame: u32) -> Self {
+        Self {
+            frame,
+            controls: [ControlsRepr {
+                dir: None,
+                fired_bullet: false,
+                fired_shield: false,
+            }; 4],
+            confirmed: [false; 4],
+        }
+    }
+}
+
+/// Rollback input exchange over the link cable.
+///
+/// Instead of waiting for everyone's controls like `Lockstep`, we guess that
+/// other players are still holding whatever they last sent and keep going.
+/// When their real controls arrive and don't match the guess, we restore the
+/// snapshot from that frame and re-simulate up to the present.
+pub struct Rollback<'a, 'q, G, B> {
+    serial: &'a mut MultiplayerLink<'q, B>,
+    local_id: PlayerId,
+    connected: [bool; 4],
+    /// The next frame to simulate.
+    frame: u32,
+    /// The next frame we expect each player's controls for.
+    next_remote: [u32; 4],
+    last_remote: [ControlsRepr; 4],
+    inputs: [FrameInputs; HISTORY_LEN],
+    /// The state at the start of each frame, before it was stepped.
+    snapshots: Vec<Option<(u32, G)>>,
+    /// Encoded `InputWord`s we haven't seen go out over the link yet.
+    outgoing: VecDeque<u16>,
+    resimulate_from: Option<u32>,
+    check: DesyncCheck<G>,
+}
+
+impl<'a, 'q, G: NetGame, B: RegisterBackend> Rollback<'a, 'q, G, B> {
+    /// Starts a rollback session; `serial` must have already run
+    /// `initialize_id` and have its interrupt filling the transfer queues.
+    pub fn new(serial: &'a mut MultiplayerLink<'q, B>) -> Self {
+        let local_id = serial
+            .id()
+            .expect("initialize_id should be called before starting rollback");
+        let connected = serial.connected_players();
+        serial.clear_transfers();
+        serial.mark_ready();
+        serial.reset_connection_timer();
+        Self {
+            serial,
+            local_id,
+            connected,
+            frame: 0,
+            next_remote: [0; 4],
+            last_remote: [ControlsRepr::default(); 4],
+            inputs: [FrameInputs::new(0); HISTORY_LEN],
+            snapshots: (0..HISTORY_LEN).map(|_| None).collect(),
+            outgoing: VecDeque::with_capacity(HISTORY_LEN),
+            resimulate_from: None,
+            // Wait until the checkpoint can't be rolled back any more.
+            check: DesyncCheck::new(MAX_ROLLBACK),
+        }
+    }
+    /// The next frame to be simulated.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+    pub fn local_tag(&self) -> PlayerTag {
+        self.local_id.into()
+    }
+    pub fn num_players(&self) -> usize {
+        num_players(&self.connected)
+    }
+
+    fn remotes(&self) -> impl Iterator<Item = PlayerId> {
+        let local_id = self.local_id;
+        let connected = self.connected;
+        PlayerId::ALL
+            .into_iter()
+            .filter(move |id| *id != local_id && connected[*id as usize])
+    }
+
+    /// The first time the consoles were found to disagree on the game state.
+    pub fn desync(&self) -> Option<Desync> {
+        self.check.desync()
+    }
+
+    /// The first frame we're still missing someone's real controls for.
+    pub fn confirmed_until(&self) -> u32 {
+        self.remotes()
+            .map(|id| self.next_remote[id as usize])
+            .min()
+            .unwrap_or(self.frame)
+    }
+
+    fn inputs_mut(&mut self, frame: u32) -> &mut FrameInputs {
+        let slot = &mut self.inputs[frame as usize % HISTORY_LEN];
+        if slot.frame != frame {
+            *slot = FrameInputs::new(frame);
+        }
+        slot
+    }
+
+    fn receive(&mut self, id: PlayerId, word: InputWord) {
+        let pidx = id as usize;
+        let frame = self.next_remote[pidx];
+        if !word.is_for(frame) {
+            return;
+        }
+        self.next_remote[pidx] += 1;
+        self.last_remote[pidx] = word.controls;
+        self.check.receive(id.into(), frame, word.check);
+        let slot = self.inputs_mut(frame);
+        let mispredicted = slot.controls[pidx] != word.controls;
+        slot.controls[pidx] = word.controls;
+        slot.confirmed[pidx] = true;
+        if frame >= self.frame {
+            return;
+        }
+        if mispredicted {
+            self.mark_resimulate(frame);
+        }
+        // Frames we've already guessed past this one repeated the old input;
+        // they need to repeat this one instead.
+        for later in frame + 1..self.frame {
+            let slot = self.inputs_mut(later);
+            if slot.controls[pidx] != word.controls {
+                slot.controls[pidx] = word.controls;
+                self.mark_resimulate(later);
+            }
+        }
+    }
+
+    fn mark_resimulate(&mut self, frame: u32) {
+        let from = self.resimulate_from.map_or(frame, |prev| prev.min(frame));
+        self.resimulate_from = Some(from);
+    }
+
+    /// Drives the link and handles every transfer since the last call.
+    fn pump(&mut self) {
+        if self.local_id == PlayerId::Parent && self.serial.all_ready() {
+            match self.serial.start_transfer() {
+                Ok(())
+                | Err(TransferError::AlreadyInProgress)
+                | Err(TransferError::FailedReadyCheck) => {}
+                Err(e) => {
+                    warning!("Rollback transfer failed: {:?}", e);
+                }
+            }
+        }
+        while let Some(transfer) = self.serial.next_transfer() {
+            if self.outgoing.front() == Some(&transfer[self.local_id as usize]) {
+                self.outgoing.pop_front();
+            }
+            for id in self.remotes() {
+                if let Some(word) = InputWord::decode(transfer[id as usize]) {
+                    self.receive(id, word);
+                }
+            }
+        }
+        if let Some(next) = self.outgoing.front() {
+            self.serial.write_send_reg(*next);
+        }
+    }
+
+    /// Saves `game` as the state at the start of `frame`.
+    fn save_snapshot(&mut self, frame: u32, game: &G) {
+        self.snapshots[frame as usize % HISTORY_LEN] = Some((frame, game.clone()));
+    }
+
+    fn resimulate(&mut self, game: &mut G) {
+        let Some(from) = self.resimulate_from.take() else {
+            return;
+        };
+        match &self.snapshots[from as usize % HISTORY_LEN] {
+            Some((frame, snapshot)) if *frame == from => game.clone_from(snapshot),
+            _ => {
+                warning!("Missing snapshot for frame {}; can't roll back", from);
+                return;
+            }
+        }
+        for frame in from..self.frame {
+            if frame != from {
+                self.save_snapshot(frame, game);
+            }
+            let inputs = self.inputs[frame as usize % HISTORY_LEN].controls;
+            game.step(&inputs);
+        }
+    }
+
+    /// Steps `game` forward with `local` as our controls for the next frame,
+    /// rolling back first if any earlier guesses turned out wrong.
+    ///
+    /// Returns `Ok(false)` without stepping if we're too far ahead of the
+    /// other players and need to wait for them, or an error once one of them
+    /// drops out.
+    pub fn advance(&mut self, game: &mut G, local: ControlsRepr) -> Result<bool, TransferError> {
+        self.pump();
+        self.serial.check_connection(self.connected)?;
+        self.resimulate(game);
+        if self.frame >= self.confirmed_until() + MAX_ROLLBACK {
+            return Ok(false);
+        }
+        let frame = self.frame;
+        let local_idx = self.local_id as usize;
+        let last_remote = self.last_remote;
+        let slot = self.inputs_mut(frame);
+        slot.controls[local_idx] = local;
+        slot.confirmed[local_idx] = true;
+        for (pidx, last) in last_remote.into_iter().enumerate() {
+            if !slot.confirmed[pidx] {
+                slot.controls[pidx] = last;
+            }
+        }
+        let inputs = slot.controls;
+
+        if let Some(checkpoint) = self.check.wants_state(frame) {
+            match &self.snapshots[checkpoint as usize % HISTORY_LEN] {
+                Some((saved, state)) if *saved == checkpoint => {
+                    self.check.record(checkpoint, state);
+                }
+                _ => {
+                    warning!("Missing snapshot for checkpoint {}", checkpoint);
+                }
+            }
+        }
+        let word = InputWord::new(frame, local).with_check(self.check.outgoing(frame));
+        self.outgoing.push_back(word.encode());
+        if self.outgoing.len() == 1 {
+            self.serial.write_send_reg(word.encode());
+        }
+        self.save_snapshot(frame, game);
+        game.step(&inputs);
+        self.frame += 1;
+        Ok(true)
+    }
+
+    /// Drives the link without stepping, returning whether every input we've
+    /// sent has gone out; see `NetSession::flush`.
+    pub fn flush(&mut self) -> Result<bool, TransferError> {
+        self.pump();
+        self.serial.check_connection(self.connected)?;
+        Ok(self.outgoing.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::{
+        clock::millis_to_ticks,
+        multiplayer::DEFAULT_TIMEOUT_MILLIS,
+        queues::{TransferQueues, DROP_TRANSFERS},
+        tests::{finish_transfer, linked},
+        SimulatedLink,
+    };
+    use crate::netplay::tests::{History, FIRING};
+    use crate::Direction;
+
+    const MOVING: ControlsRepr = ControlsRepr {
+        dir: Some(Direction::Left),
+        fired_bullet: false,
+        fired_shield: false,
+    };
+
+    /// What the parent holds on each frame.
+    fn parent_controls(frame: u32) -> ControlsRepr {
+        if frame % 2 == 0 {
+            MOVING
+        } else {
+            ControlsRepr::default()
+        }
+    }
+    /// What the child holds on each frame; never what the parent would guess
+    /// from the frame before.
+    fn child_controls(frame: u32) -> ControlsRepr {
+        if frame % 3 == 1 {
+            FIRING
+        } else {
+            ControlsRepr::default()
+        }
+    }
+
+    /// The game `Lockstep` ends up with after `frames` frames of the
+    /// controls above.
+    fn lockstep_history(frames: usize) -> History {
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let (parent_link, child_link) = links.split_at_mut(1);
+        let mut parent = Lockstep::<History, _>::new(&mut parent_link[0]);
+        let mut child = Lockstep::<History, _>::new(&mut child_link[0]);
+        let mut game = History::default();
+        while game.0.len() < frames {
+            parent.submit(parent_controls(parent.frame()));
+            child.submit(child_controls(child.frame()));
+            // Polling is what gets the parent to start the transfer.
+            assert_eq!(parent.poll(), None);
+            assert_eq!(child.poll(), None);
+            finish_transfer(&link, &queues);
+            let inputs = parent.poll().unwrap();
+            assert_eq!(child.poll(), Some(inputs));
+            game.step(&inputs);
+        }
+        game
+    }
+
+    #[test]
+    fn test_resimulate() {
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let (parent_link, child_link) = links.split_at_mut(1);
+        let mut parent = Rollback::<History, _>::new(&mut parent_link[0]);
+        let mut child = Rollback::<History, _>::new(&mut child_link[0]);
+        let mut parent_game = History::default();
+        let mut child_game = History::default();
+
+        // Both run ahead before hearing anything, the parent guessing that
+        // the child is idle when it's really firing.
+        for _ in 0..3 {
+            assert_eq!(
+                parent.advance(&mut parent_game, ControlsRepr::default()),
+                Ok(true)
+            );
+            assert_eq!(child.advance(&mut child_game, FIRING), Ok(true));
+        }
+        assert_eq!(parent.confirmed_until(), 0);
+        assert!(!parent_game.0[0][1].fired_bullet);
+
+        for _ in 0..12 {
+            finish_transfer(&link, &queues);
+            assert_eq!(
+                parent.advance(&mut parent_game, ControlsRepr::default()),
+                Ok(true)
+            );
+            assert_eq!(child.advance(&mut child_game, FIRING), Ok(true));
+        }
+        let confirmed = parent.confirmed_until().min(child.confirmed_until()) as usize;
+        assert!(confirmed > 3, "Only {} frames were confirmed", confirmed);
+        // The parent rolled back and replayed the frames it guessed wrong.
+        assert_eq!(parent_game.0[..confirmed], child_game.0[..confirmed]);
+        assert!(parent_game.0[..confirmed]
+            .iter()
+            .all(|inputs| inputs[1] == FIRING));
+        assert_eq!(parent.desync(), None);
+        assert_eq!(child.desync(), None);
+    }
+
+    #[test]
+    fn test_late_inputs_match_lockstep() {
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let (parent_link, child_link) = links.split_at_mut(1);
+        let mut parent = Rollback::<History, _>::new(&mut parent_link[0]);
+        let mut child = Rollback::<History, _>::new(&mut child_link[0]);
+        let mut parent_game = History::default();
+        let mut child_game = History::default();
+        let mut advance = |parent: &mut Rollback<History, _>,
+                           child: &mut Rollback<History, _>,
+                           parent_game: &mut History| {
+            let frame = parent.frame();
+            assert_eq!(
+                parent.advance(parent_game, parent_controls(frame)),
+                Ok(true)
+            );
+            let frame = child.frame();
+            assert_eq!(
+                child.advance(&mut child_game, child_controls(frame)),
+                Ok(true)
+            );
+        };
+
+        // Nothing gets through for the first few frames, so the parent
+        // guesses the child is idle when it fired on frame 1.
+        for _ in 0..4 {
+            advance(&mut parent, &mut child, &mut parent_game);
+        }
+        assert_eq!(parent_game.0[1][1], ControlsRepr::default());
+        for _ in 0..24 {
+            finish_transfer(&link, &queues);
+            advance(&mut parent, &mut child, &mut parent_game);
+        }
+        let confirmed = parent.confirmed_until().min(child.confirmed_until()) as usize;
+        assert!(confirmed > 16, "Only {} frames were confirmed", confirmed);
+        let expected = lockstep_history(confirmed);
+        assert_eq!(parent_game.0[..confirmed], expected.0[..]);
+        assert_eq!(child_game.0[..confirmed], expected.0[..]);
+        assert_eq!(parent.desync(), None);
+        assert_eq!(child.desync(), None);
+    }
+
+    #[test]
+    fn test_stall_at_max_rollback() {
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let (parent_link, child_link) = links.split_at_mut(1);
+        let mut parent = Rollback::<History, _>::new(&mut parent_link[0]);
+        let mut child = Rollback::<History, _>::new(&mut child_link[0]);
+        let mut parent_game = History::default();
+        let mut child_game = History::default();
+
+        // With nothing heard from the child, the parent can only guess so far.
+        for _ in 0..MAX_ROLLBACK {
+            assert_eq!(parent.advance(&mut parent_game, MOVING), Ok(true));
+        }
+        for _ in 0..3 {
+            assert_eq!(parent.advance(&mut parent_game, MOVING), Ok(false));
+        }
+        assert_eq!(parent.frame(), MAX_ROLLBACK);
+        assert_eq!(parent_game.0.len(), MAX_ROLLBACK as usize);
+
+        // One frame of the child's controls is enough to go one further.
+        assert_eq!(child.advance(&mut child_game, FIRING), Ok(true));
+        finish_transfer(&link, &queues);
+        assert_eq!(parent.confirmed_until(), 0);
+        finish_transfer(&link, &queues);
+        assert_eq!(parent.advance(&mut parent_game, MOVING), Ok(true));
+        assert_eq!(parent.confirmed_until(), 1);
+        assert_eq!(parent_game.0[0][1], FIRING);
+        assert_eq!(parent.advance(&mut parent_game, MOVING), Ok(false));
+    }
+
+    #[test]
+    fn test_flush() {
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let (parent_link, child_link) = links.split_at_mut(1);
+        let mut parent = Rollback::<History, _>::new(&mut parent_link[0]);
+        let mut child = Rollback::<History, _>::new(&mut child_link[0]);
+        let mut parent_game = History::default();
+        let mut child_game = History::default();
+        assert_eq!(parent.flush(), Ok(true));
+
+        // Three frames of the child's inputs going out one per transfer.
+        for _ in 0..3 {
+            assert_eq!(child.advance(&mut child_game, FIRING), Ok(true));
+        }
+        for _ in 0..3 {
+            assert_eq!(child.flush(), Ok(false));
+            assert_eq!(parent.flush(), Ok(true));
+            finish_transfer(&link, &queues);
+        }
+        assert_eq!(child.flush(), Ok(true));
+        assert_eq!(child.frame(), 3);
+        for _ in 0..3 {
+            assert_eq!(parent.advance(&mut parent_game, MOVING), Ok(true));
+        }
+        assert_eq!(parent.confirmed_until(), 3);
+        assert!(parent_game.0.iter().all(|inputs| inputs[1] == FIRING));
+    }
+
+    #[test]
+    fn test_dropped_peer() {
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let (parent_link, child_link) = links.split_at_mut(1);
+        let mut parent = Rollback::<History, _>::new(&mut parent_link[0]);
+        let mut child = Rollback::<History, _>::new(&mut child_link[0]);
+        let mut parent_game = History::default();
+        let mut child_game = History::default();
+        for _ in 0..4 {
+            assert_eq!(parent.advance(&mut parent_game, MOVING), Ok(true));
+            assert_eq!(child.advance(&mut child_game, FIRING), Ok(true));
+            finish_transfer(&link, &queues);
+        }
+
+        // The parent notices the child's gone from the transfers, stalled or
+        // not.
+        link.unplug(PlayerId::P1);
+        let mut result = Ok(true);
+        for _ in 0..=DROP_TRANSFERS {
+            result = parent.advance(&mut parent_game, MOVING);
+            if result.is_err() {
+                break;
+            }
+            finish_transfer(&link, &queues);
+        }
+        assert_eq!(result, Err(TransferError::Disconnected(PlayerId::P1)));
+
+        // The child hears nothing more, so only finds out from the clock.
+        assert_eq!(child.advance(&mut child_game, FIRING), Ok(true));
+        link.advance_clock(millis_to_ticks(DEFAULT_TIMEOUT_MILLIS) + 1);
+        assert_eq!(
+            child.advance(&mut child_game, FIRING),
+            Err(TransferError::Timeout)
+        );
+    }
+}
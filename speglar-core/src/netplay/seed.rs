@@ -0,0 +1,244 @@
+use super::*;
+use crate::logs::println;
+
+/// The number of words it takes to send a seed, one byte at a time.
+const SEED_WORDS: u16 = 8;
+const DONE_INDEX: u16 = 0xF;
+const WORD_TAG: u16 = 0xE000;
+const TAG_MASK: u16 = 0xF000;
+
+/*
+  Bit   Expl.
+  0-7   One byte of the seed, or 0 for the final "done" word
+  8-11  Which byte of the seed (0-7), or 15 for "done"
+  12-15 Always 0xE, so these can't be mistaken for an `InputWord` (bit 15
+        clear), a lobby word (0x8-0xB) or "no data" (0xFFFF)
+*/
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum SeedWord {
+    Byte { index: u16, value: u8 },
+    Done,
+}
+
+impl SeedWord {
+    const fn encode(self) -> u16 {
+        match self {
+            SeedWord::Byte { index, value } => WORD_TAG | (index << 8) | value as u16,
+            SeedWord::Done => WORD_TAG | (DONE_INDEX << 8),
+        }
+    }
+    const fn decode(raw: u16) -> Option<Self> {
+        if raw & TAG_MASK != WORD_TAG {
+            return None;
+        }
+        let index = (raw >> 8) & 0xF;
+        if index == DONE_INDEX {
+            Some(SeedWord::Done)
+        } else if index < SEED_WORDS {
+            Some(SeedWord::Byte {
+                index,
+                value: raw as u8,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Gets every connected GBA onto the same match seed.
+///
+/// The parent sends its seed a byte at a time, waiting for every child to
+/// echo each byte back before moving on; the children ignore the seed they
+/// were given. Nobody finishes until every child has confirmed the whole
+/// seed, so it's safe to generate the arena afterwards.
+pub struct SeedShare<'a, 'q, B> {
+    serial: &'a mut MultiplayerLink<'q, B>,
+    local_id: PlayerId,
+    connected: [bool; 4],
+    bytes: [u8; SEED_WORDS as usize],
+    /// The parent's next word to get echoed, or which bytes a child has
+    /// heard as a bitmask.
+    progress: u16,
+}
+
+impl<'a, 'q, B: RegisterBackend> SeedShare<'a, 'q, B> {
+    /// Starts sharing `seed` over `serial`, which must have already run
+    /// `initialize_id` and have its interrupt filling the transfer queues.
+    ///
+    /// Children keep sending whatever they were until they hear the first
+    /// byte, so a lobby's start echo still reaches a parent that hasn't
+    /// caught up yet.
+    pub fn new(serial: &'a mut MultiplayerLink<'q, B>, seed: u64) -> Self {
+        let local_id = serial
+            .id()
+            .expect("initialize_id should be called before sharing the seed");
+        let connected = serial.connected_players();
+        serial.clear_transfers();
+        serial.mark_ready();
+        serial.reset_connection_timer();
+        Self {
+            serial,
+            local_id,
+            connected,
+            bytes: seed.to_le_bytes(),
+            progress: 0,
+        }
+    }
+
+    /// Drives the link, returning the agreed seed once we're done.
+    pub fn poll(&mut self) -> Option<u64> {
+        let retvl = if self.local_id == PlayerId::Parent {
+            self.poll_send()
+        } else {
+            self.poll_receive()
+        };
+        if let Some(seed) = retvl {
+            println!("Agreed on seed {:016x}", seed);
+        }
+        retvl
+    }
+
+    /// Blocks until `poll` has the seed, or until someone drops out.
+    pub fn wait(&mut self) -> Result<u64, TransferError> {
+        loop {
+            self.serial.check_connection(self.connected)?;
+            if let Some(seed) = self.poll() {
+                return Ok(seed);
+            }
+        }
+    }
+
+    fn parent_word(&self) -> SeedWord {
+        if self.progress < SEED_WORDS {
+            SeedWord::Byte {
+                index: self.progress,
+                value: self.bytes[self.progress as usize],
+            }
+        } else {
+            SeedWord::Done
+        }
+    }
+
+    fn poll_send(&mut self) -> Option<u64> {
+        while let Some(transfer) = self.serial.next_transfer() {
+            let word = self.parent_word();
+            let echoed = PlayerId::ALL
+                .into_iter()
+                .filter(|id| *id != PlayerId::Parent && self.connected[*id as usize])
+                .all(|id| {
+                    let raw = transfer[id as usize];
+                    // A child that's already started sending inputs has
+                    // clearly seen the "done".
+                    raw == word.encode() || (word == SeedWord::Done && raw & 0x8000 == 0)
+                });
+            if !echoed {
+                continue;
+            }
+            if word == SeedWord::Done {
+                return Some(u64::from_le_bytes(self.bytes));
+            }
+            self.progress += 1;
+        }
+        self.serial.write_send_reg(self.parent_word().encode());
+        if self.serial.all_ready() {
+            match self.serial.start_transfer() {
+                Ok(())
+                | Err(TransferError::AlreadyInProgress)
+                | Err(TransferError::FailedReadyCheck) => {}
+                Err(e) => {
+                    warning!("Seed transfer failed: {:?}", e);
+                }
+            }
+        }
+        None
+    }
+
+    fn poll_receive(&mut self) -> Option<u64> {
+        while let Some(transfer) = self.serial.next_transfer() {
+            let raw = transfer[PlayerId::Parent as usize];
+            match SeedWord::decode(raw) {
+                Some(SeedWord::Byte { index, value }) => {
+                    self.bytes[index as usize] = value;
+                    self.progress |= 1 << index;
+                    self.serial.write_send_reg(raw);
+                }
+                // The parent only sends "done" after we've echoed every byte.
+                Some(SeedWord::Done) if self.progress == (1 << SEED_WORDS) - 1 => {
+                    self.serial.write_send_reg(raw);
+                    return Some(u64::from_le_bytes(self.bytes));
+                }
+                Some(SeedWord::Done) | None => {}
+            }
+        }
+        None
+    }
+}
+
+/// Gets every connected GBA onto the parent's `seed`, blocking until they
+/// all have it; see `SeedShare`. Fails if anyone drops out partway through.
+pub fn share_seed<B: RegisterBackend>(
+    serial: &mut MultiplayerLink<'_, B>,
+    seed: u64,
+) -> Result<u64, TransferError> {
+    SeedShare::new(serial, seed).wait()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::link::{
+        queues::TransferQueues,
+        tests::{finish_transfer, linked},
+        SimulatedLink,
+    };
+
+    #[test]
+    fn test_words() {
+        for word in [
+            SeedWord::Byte {
+                index: 7,
+                value: 0xFF,
+            },
+            SeedWord::Done,
+        ] {
+            assert_eq!(SeedWord::decode(word.encode()), Some(word));
+        }
+        assert_eq!(SeedWord::decode(0xE800), None);
+        // Lobby words and inputs are something else entirely.
+        assert_eq!(SeedWord::decode(0xA123), None);
+        assert_eq!(SeedWord::decode(0x0123), None);
+    }
+
+    #[test]
+    fn test_share_seed() {
+        let link = SimulatedLink::new(3);
+        let queues = [
+            TransferQueues::new(),
+            TransferQueues::new(),
+            TransferQueues::new(),
+        ];
+        let mut links = linked(&link, &queues);
+        let seed = 0x0123_4567_89AB_CDEF;
+        let mut shares: Vec<_> = links
+            .iter_mut()
+            .enumerate()
+            .map(|(idx, serial)| SeedShare::new(serial, if idx == 0 { seed } else { 0 }))
+            .collect();
+        let mut agreed = [None; 3];
+        for _ in 0..64 {
+            for (share, agreed) in shares.iter_mut().zip(agreed.iter_mut()) {
+                if agreed.is_none() {
+                    *agreed = share.poll();
+                }
+            }
+            // The parent waits on every child's confirmation.
+            if agreed[0].is_some() {
+                break;
+            }
+            finish_transfer(&link, &queues);
+        }
+        assert_eq!(agreed, [Some(seed); 3]);
+    }
+}
@@ -0,0 +1,924 @@
+//! Framing for sending byte packets over a stream of 16-bit words.
+//!
+//! Words from `0xFFF0` up are reserved for control words, and any data word in
+//! that range gets escaped; in particular `0xFFFF`, which the link cable uses
+//! for "no data", is never sent. A frame looks like:
+//!
+//! ```text
+//!   SOF, header, payload words..., checksum
+//! ```
+//!
+//! Acks can be slipped in between any two words of a frame, as `ACK` followed
+//! by an ack word, so a console can ack what it's receiving while it's still in
+//! the middle of sending its own frame.
+//!
+//! `PacketLink` sends packets as frames over the multiplayer link, broadcasting
+//! each one to every other GBA and resending it until everyone connected has
+//! acked it.
+use alloc::{collections::VecDeque, vec::Vec};
+use core::hash::Hasher;
+
+use crate::{
+    checksum::Fnv1a,
+    link::{
+        multiplayer::{MultiplayerLink, PlayerId, TransferError},
+        queues::SEND_QUEUE_LEN,
+        RegisterBackend,
+    },
+    logs::warning,
+};
+
+/// Followed by the low nibble of an escaped data word.
+pub const ESCAPE: u16 = 0xFFF0;
+/// Starts a new frame, abandoning any half-received one.
+pub const START: u16 = 0xFFF1;
+/// Followed by an ack word.
+pub const ACK: u16 = 0xFFF2;
+/// Filler for when there's nothing to send; ignored anywhere.
+pub const IDLE: u16 = 0xFFF3;
+const FIRST_CONTROL: u16 = ESCAPE;
+
+/// The most payload a single frame can carry; longer packets get split up.
+pub const MAX_FRAME_BYTES: usize = 32;
+/// Sequence numbers wrap around at this.
+pub const SEQ_MODULUS: u8 = 16;
+
+/*
+  Header word:
+  Bit   Expl.
+  0-3   Sequence number
+  4-9   Payload length in bytes (0-32)
+  10    More frames follow for the same packet
+  11-15 Not used (should be 0)
+
+  Ack word:
+  Bit   Expl.
+  0-3   Sequence number being acked
+  4-5   Which player sent the frame being acked
+  6-15  Not used (should be 0)
+*/
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FrameHeader {
+    pub seq: u8,
+    pub len: u8,
+    pub more: bool,
+}
+
+impl FrameHeader {
+    pub const fn encode(self) -> u16 {
+        (self.seq as u16 & 0xF) | ((self.len as u16 & 0x3F) << 4) | ((self.more as u16) << 10)
+    }
+    pub const fn decode(raw: u16) -> Option<Self> {
+        let len = ((raw >> 4) & 0x3F) as u8;
+        if raw & 0xF800 != 0 || len as usize > MAX_FRAME_BYTES {
+            return None;
+        }
+        Some(Self {
+            seq: (raw & 0xF) as u8,
+            len,
+            more: raw & (1 << 10) != 0,
+        })
+    }
+    /// How many words the payload takes up.
+    const fn payload_words(self) -> usize {
+        (self.len as usize + 1) / 2
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum FrameEvent {
+    Frame {
+        header: FrameHeader,
+        payload: Vec<u8>,
+    },
+    /// Another console got frame `seq` from `sender`.
+    Ack { sender: u8, seq: u8 },
+}
+
+fn frame_checksum(words: &[u16]) -> u16 {
+    let mut hasher = Fnv1a::new();
+    for word in words {
+        hasher.write(&word.to_le_bytes());
+    }
+    let hash = hasher.finish() as u32;
+    (hash ^ (hash >> 16)) as u16
+}
+
+fn push_escaped(out: &mut Vec<u16>, word: u16) {
+    if word >= FIRST_CONTROL {
+        out.push(ESCAPE);
+        out.push(word & 0xF);
+    } else {
+        out.push(word);
+    }
+}
+
+/// Appends the words for a frame carrying `payload` onto `out`.
+pub fn encode_frame(seq: u8, payload: &[u8], more: bool, out: &mut Vec<u16>) {
+    debug_assert!(payload.len() <= MAX_FRAME_BYTES);
+    let header = FrameHeader {
+        seq: seq % SEQ_MODULUS,
+        len: payload.len() as u8,
+        more,
+    };
+    let mut words = Vec::with_capacity(header.payload_words() + 1);
+    words.push(header.encode());
+    for pair in payload.chunks(2) {
+        let lo = pair[0] as u16;
+        let hi = pair.get(1).copied().unwrap_or(0) as u16;
+        words.push(lo | (hi << 8));
+    }
+    let checksum = frame_checksum(&words);
+    out.push(START);
+    for word in words {
+        push_escaped(out, word);
+    }
+    push_escaped(out, checksum);
+}
+
+/// Appends the words acking frame `seq` from `sender` onto `out`.
+pub fn encode_ack(sender: u8, seq: u8, out: &mut Vec<u16>) {
+    out.push(ACK);
+    out.push(((sender as u16 & 0x3) << 4) | (seq % SEQ_MODULUS) as u16);
+}
+
+/// Splits a packet into the payloads of the frames it's sent as, along with
+/// whether more frames follow each one. Empty packets still take one frame.
+pub fn fragments(packet: &[u8]) -> impl Iterator<Item = (&[u8], bool)> {
+    let count = packet.len().div_ceil(MAX_FRAME_BYTES).max(1);
+    (0..count).map(move |idx| {
+        let start = idx * MAX_FRAME_BYTES;
+        let end = (start + MAX_FRAME_BYTES).min(packet.len());
+        (&packet[start..end], idx + 1 < count)
+    })
+}
+
+/// Picks frames and acks back out of one console's stream of words.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct FrameDecoder {
+    /// The unescaped words of the frame being received, if we're in one.
+    frame: Option<Vec<u16>>,
+    escaped: bool,
+    in_ack: bool,
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            frame: None,
+            escaped: false,
+            in_ack: false,
+        }
+    }
+
+    /// Feeds in the next word, returning anything it completes.
+    ///
+    /// Frames that fail their checksum or are otherwise malformed are silently
+    /// dropped; the sender will retransmit them.
+    pub fn push(&mut self, word: u16) -> Option<FrameEvent> {
+        if self.in_ack {
+            self.in_ack = false;
+            if word < FIRST_CONTROL {
+                return Some(FrameEvent::Ack {
+                    sender: ((word >> 4) & 0x3) as u8,
+                    seq: (word & 0xF) as u8,
+                });
+            }
+        }
+        if self.escaped {
+            self.escaped = false;
+            if word > 0xF {
+                self.frame = None;
+                return None;
+            }
+            return self.push_data(FIRST_CONTROL | word);
+        }
+        match word {
+            START => {
+                self.frame = Some(Vec::new());
+                None
+            }
+            ESCAPE => {
+                self.escaped = true;
+                None
+            }
+            ACK => {
+                self.in_ack = true;
+                None
+            }
+            // Includes IDLE, along with anything unassigned and the "no data"
+            // we see from disconnected consoles.
+            w if w >= FIRST_CONTROL => None,
+            w => self.push_data(w),
+        }
+    }
+
+    fn push_data(&mut self, word: u16) -> Option<FrameEvent> {
+        let words = self.frame.as_mut()?;
+        words.push(word);
+        let Some(header) = FrameHeader::decode(words[0]) else {
+            self.frame = None;
+            return None;
+        };
+        // Header, payload, checksum.
+        if words.len() < header.payload_words() + 2 {
+            return None;
+        }
+        let words = self.frame.take()?;
+        let (checksum, body) = words.split_last()?;
+        if frame_checksum(body) != *checksum {
+            return None;
+        }
+        let mut payload = Vec::with_capacity(header.len as usize);
+        for word in &body[1..] {
+            payload.extend_from_slice(&word.to_le_bytes());
+        }
+        payload.truncate(header.len as usize);
+        Some(FrameEvent::Frame { header, payload })
+    }
+}
+
+/// How many transfers to wait for acks after a frame has gone out before
+/// sending it again.
+const ACK_TIMEOUT: u32 = 32;
+
+struct InFlight {
+    seq: u8,
+    words: Vec<u16>,
+    acked: [bool; 4],
+    /// The transfer count after which we give up waiting and resend.
+    deadline: u32,
+}
+
+/// Reliable byte packets over a `MultiplayerLink`.
+///
+/// Packets get split into frames which are broadcast to every other GBA, one
+/// at a time; each frame is resent until everyone connected has acked it.
+pub struct PacketLink<'a, 'q, B> {
+    serial: &'a mut MultiplayerLink<'q, B>,
+    local_id: PlayerId,
+    connected: [bool; 4],
+    /// How many transfers we've seen, used as the clock for resending.
+    transfers: u32,
+
+    /// Frame payloads waiting their turn, with whether more frames follow.
+    outgoing: VecDeque<(Vec<u8>, bool)>,
+    next_seq: u8,
+    in_flight: Option<InFlight>,
+    /// Frame words waiting for room in the serial send queue.
+    words: VecDeque<u16>,
+    /// Ack words, which jump ahead of `words`.
+    acks: VecDeque<u16>,
+
+    decoders: [FrameDecoder; 4],
+    expected_seq: [u8; 4],
+    /// The frames of each player's packet that's still coming in.
+    partial: [Vec<u8>; 4],
+    inbox: [VecDeque<Vec<u8>>; 4],
+}
+
+impl<'a, 'q, B: RegisterBackend> PacketLink<'a, 'q, B> {
+    /// Starts a link over `serial`, which must have already run
+    /// `initialize_id` and have its interrupt filling the transfer queues.
+    /// Every connected GBA needs to start its link at the same point, since
+    /// sequence numbers start from scratch.
+    pub fn new(serial: &'a mut MultiplayerLink<'q, B>) -> Self {
+        let local_id = serial
+            .id()
+            .expect("initialize_id should be called before starting a packet link");
+        serial.clear_transfers();
+        serial.mark_ready();
+        serial.reset_connection_timer();
+        let mut connected = serial.connected_players();
+        connected[local_id as usize] = false;
+        Self {
+            serial,
+            local_id,
+            connected,
+            transfers: 0,
+            outgoing: VecDeque::new(),
+            next_seq: 0,
+            in_flight: None,
+            words: VecDeque::new(),
+            acks: VecDeque::new(),
+            decoders: Default::default(),
+            expected_seq: [0; 4],
+            partial: Default::default(),
+            inbox: Default::default(),
+        }
+    }
+
+    pub fn local_id(&self) -> PlayerId {
+        self.local_id
+    }
+
+    /// Queues `data` to be sent to every other connected GBA.
+    pub fn send(&mut self, data: &[u8]) {
+        self.outgoing
+            .extend(fragments(data).map(|(payload, more)| (payload.to_vec(), more)));
+    }
+
+    /// Pops the oldest packet received from `from`.
+    pub fn recv(&mut self, from: PlayerId) -> Option<Vec<u8>> {
+        self.inbox[from as usize].pop_front()
+    }
+
+    /// Whether everything passed to `send` has been acked by everyone.
+    pub fn is_flushed(&self) -> bool {
+        self.outgoing.is_empty() && self.in_flight.is_none()
+    }
+
+    /// Moves everything along; needs to be called regularly, ideally at least
+    /// once per frame. Fails once another GBA drops out.
+    pub fn poll(&mut self) -> Result<(), TransferError> {
+        if self.local_id == PlayerId::Parent && self.serial.all_ready() {
+            match self.serial.start_transfer() {
+                Ok(())
+                | Err(TransferError::AlreadyInProgress)
+                | Err(TransferError::FailedReadyCheck) => {}
+                Err(e) => {
+                    warning!("Packet transfer failed: {:?}", e);
+                }
+            }
+        }
+        while let Some(transfer) = self.serial.next_transfer() {
+            self.transfers = self.transfers.wrapping_add(1);
+            self.receive(transfer);
+        }
+        self.update_in_flight();
+        self.fill_send_queue();
+        self.serial.check_connection(self.connected)
+    }
+
+    fn receive(&mut self, transfer: [u16; 4]) {
+        for id in PlayerId::ALL {
+            let pidx = id as usize;
+            if !self.connected[pidx] {
+                continue;
+            }
+            match self.decoders[pidx].push(transfer[pidx]) {
+                Some(FrameEvent::Frame { header, payload }) => {
+                    // Ack duplicates too, since it's probably our last ack
+                    // that got lost.
+                    let mut ack = Vec::with_capacity(2);
+                    encode_ack(id as u8, header.seq, &mut ack);
+                    self.acks.extend(ack);
+                    if header.seq != self.expected_seq[pidx] {
+                        continue;
+                    }
+                    self.expected_seq[pidx] = (header.seq + 1) % SEQ_MODULUS;
+                    self.partial[pidx].extend_from_slice(&payload);
+                    if !header.more {
+                        let packet = core::mem::take(&mut self.partial[pidx]);
+                        self.inbox[pidx].push_back(packet);
+                    }
+                }
+                Some(FrameEvent::Ack { sender, seq }) if sender == self.local_id as u8 => {
+                    if let Some(in_flight) = &mut self.in_flight {
+                        if in_flight.seq == seq {
+                            in_flight.acked[pidx] = true;
+                        }
+                    }
+                }
+                Some(FrameEvent::Ack { .. }) | None => {}
+            }
+        }
+    }
+
+    fn update_in_flight(&mut self) {
+        if let Some(in_flight) = &self.in_flight {
+            let done = PlayerId::ALL
+                .into_iter()
+                .all(|id| !self.connected[id as usize] || in_flight.acked[id as usize]);
+            if done {
+                self.in_flight = None;
+            } else if self.transfers.wrapping_sub(in_flight.deadline) as i32 > 0
+                && self.words.is_empty()
+            {
+                let (seq, words) = (in_flight.seq, in_flight.words.clone());
+                self.words.extend(words);
+                self.reset_deadline();
+                warning!("Resending frame {}", seq);
+            }
+        }
+        if self.in_flight.is_some() {
+            return;
+        }
+        let Some((payload, more)) = self.outgoing.pop_front() else {
+            return;
+        };
+        let seq = self.next_seq;
+        self.next_seq = (seq + 1) % SEQ_MODULUS;
+        let mut words = Vec::new();
+        encode_frame(seq, &payload, more, &mut words);
+        // So the word that keeps getting resent once we run out isn't part of
+        // the frame.
+        words.push(IDLE);
+        self.words.extend(words.iter().copied());
+        self.in_flight = Some(InFlight {
+            seq,
+            words,
+            acked: [false; 4],
+            deadline: 0,
+        });
+        self.reset_deadline();
+    }
+
+    fn reset_deadline(&mut self) {
+        let backlog = (self.words.len() + self.acks.len() + self.serial.queued_sends()) as u32;
+        let deadline = self.transfers.wrapping_add(backlog + ACK_TIMEOUT);
+        if let Some(in_flight) = &mut self.in_flight {
+            in_flight.deadline = deadline;
+        }
+    }
+
+    fn fill_send_queue(&mut self) {
+        // Acks and escapes are two-word units, so nothing gets queued between
+        // the halves.
+        loop {
+            let source = if self.acks.is_empty() {
+                &mut self.words
+            } else {
+                &mut self.acks
+            };
+            let Some(&first) = source.front() else {
+                return;
+            };
+            let len = if first == ESCAPE || first == ACK {
+                2
+            } else {
+                1
+            };
+            if self.serial.queued_sends() + len > SEND_QUEUE_LEN {
+                return;
+            }
+            for word in source.drain(..len) {
+                self.serial.queue_send(word);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::VecDeque;
+
+    use super::*;
+    use crate::link::{
+        multiplayer::MultiplayerCommReg,
+        queues::{TransferQueues, DROP_TRANSFERS},
+        tests::{finish_transfer, linked},
+        SimulatedGba, SimulatedLink, SIOMLT_SEND,
+    };
+
+    fn decode_all(words: &[u16]) -> Vec<FrameEvent> {
+        let mut decoder = FrameDecoder::new();
+        words.iter().filter_map(|w| decoder.push(*w)).collect()
+    }
+
+    fn frame(seq: u8, payload: &[u8], more: bool) -> FrameEvent {
+        FrameEvent::Frame {
+            header: FrameHeader {
+                seq,
+                len: payload.len() as u8,
+                more,
+            },
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let payloads: [&[u8]; 5] = [
+            &[],
+            &[1],
+            &[0xFF, 0xFF, 0xF0, 0xFF, 0xFF],
+            &[0xAB; MAX_FRAME_BYTES],
+            b"hello",
+        ];
+        for (seq, payload) in payloads.into_iter().enumerate() {
+            let mut words = Vec::new();
+            encode_frame(seq as u8, payload, seq % 2 == 0, &mut words);
+            assert!(!words.contains(&0xFFFF));
+            assert_eq!(
+                decode_all(&words),
+                [frame(seq as u8, payload, seq % 2 == 0)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_acks_between_frame_words() {
+        let mut words = Vec::new();
+        encode_frame(3, &[0xFF; 9], false, &mut words);
+        let mut ack = Vec::new();
+        encode_ack(2, 7, &mut ack);
+        // Anywhere but between an escape and what it escapes.
+        for idx in 1..words.len() {
+            if words[idx - 1] == ESCAPE {
+                continue;
+            }
+            let mut mixed = words.clone();
+            mixed.splice(idx..idx, ack.iter().copied());
+            mixed.insert(idx, IDLE);
+            assert_eq!(
+                decode_all(&mixed),
+                [
+                    FrameEvent::Ack { sender: 2, seq: 7 },
+                    frame(3, &[0xFF; 9], false)
+                ],
+                "Ack at {}",
+                idx
+            );
+        }
+    }
+
+    #[test]
+    fn test_corruption_drops_frame() {
+        let mut words = Vec::new();
+        encode_frame(1, b"some data", false, &mut words);
+        for idx in 1..words.len() {
+            for bit in 0..16 {
+                let mut corrupted = words.clone();
+                corrupted[idx] ^= 1 << bit;
+                let events = decode_all(&corrupted);
+                assert!(
+                    events
+                        .iter()
+                        .all(|e| !matches!(e, FrameEvent::Frame { .. })),
+                    "Flipping bit {} of word {} got through: {:?}",
+                    bit,
+                    idx,
+                    events
+                );
+            }
+        }
+        // Dropping a word entirely.
+        for idx in 1..words.len() {
+            let mut dropped = words.clone();
+            dropped.remove(idx);
+            assert_eq!(decode_all(&dropped), []);
+        }
+    }
+
+    #[test]
+    fn test_start_resyncs() {
+        let mut words = Vec::new();
+        encode_frame(1, b"lost", false, &mut words);
+        words.truncate(3);
+        encode_frame(2, b"kept", false, &mut words);
+        assert_eq!(decode_all(&words), [frame(2, b"kept", false)]);
+    }
+
+    #[test]
+    fn test_fragments() {
+        let packet = [7u8; MAX_FRAME_BYTES * 2 + 3];
+        let parts = fragments(&packet).collect::<Vec<_>>();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0].0.len(), MAX_FRAME_BYTES);
+        assert_eq!(parts[2].0.len(), 3);
+        assert_eq!(
+            parts.iter().map(|(_, more)| *more).collect::<Vec<_>>(),
+            [true, true, false]
+        );
+        assert_eq!(fragments(&[]).collect::<Vec<_>>(), [(&[][..], false)]);
+        assert_eq!(fragments(&packet[..MAX_FRAME_BYTES]).count(), 1);
+    }
+
+    /// Runs the transfer the parent last started, with `tamper` getting to
+    /// change the word each GBA puts on the wire, and returns what everyone
+    /// got.
+    fn transfer(
+        link: &SimulatedLink,
+        queues: &[TransferQueues],
+        mut tamper: impl FnMut(PlayerId, u16) -> u16,
+    ) -> [u16; 4] {
+        let sending = PlayerId::ALL.map(|id| link.gba(id).read(SIOMLT_SEND));
+        for id in PlayerId::ALL {
+            link.gba(id)
+                .write(SIOMLT_SEND, tamper(id, sending[id as usize]));
+        }
+        assert!(link.complete_transfer(), "No transfer was started");
+        // Only this transfer gets tampered with, not what's sent after it.
+        for id in PlayerId::ALL {
+            link.gba(id).write(SIOMLT_SEND, sending[id as usize]);
+        }
+        link.run_interrupts(queues);
+        PlayerId::ALL
+            .map(|id| MultiplayerCommReg::with_backend(id, link.gba(PlayerId::Parent)).raw_read())
+    }
+
+    /// Polls every link and runs a transfer, `count` times over, returning
+    /// what was on the wire each time.
+    fn run(
+        link: &SimulatedLink,
+        queues: &[TransferQueues],
+        packets: &mut [PacketLink<'_, '_, SimulatedGba<'_>>],
+        count: usize,
+        mut tamper: impl FnMut(PlayerId, u16) -> u16,
+    ) -> Vec<[u16; 4]> {
+        (0..count)
+            .map(|_| {
+                for packet in packets.iter_mut() {
+                    packet.poll().unwrap();
+                }
+                transfer(link, queues, &mut tamper)
+            })
+            .collect()
+    }
+
+    /// Has the parent send one frame to a child with `tamper` changing the
+    /// words of its first copy, by their index in the frame, and checks that
+    /// it's resent once `ACK_TIMEOUT` is up and then delivered once.
+    fn check_resent(tamper: impl Fn(usize, u16) -> u16) {
+        const PACKET: &[u8] = b"hello there";
+        let mut words = Vec::new();
+        encode_frame(0, PACKET, false, &mut words);
+
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let mut packets: Vec<_> = links.iter_mut().map(PacketLink::new).collect();
+        packets[0].send(PACKET);
+        let mut sent = 0;
+        let mut first_copy = None;
+        let wire = run(&link, &queues, &mut packets, 200, |id, word| {
+            if id != PlayerId::Parent {
+                return word;
+            }
+            let idx = sent;
+            sent += 1;
+            if word == START && first_copy.is_none() {
+                first_copy = Some(idx);
+            }
+            match first_copy {
+                Some(start) if idx - start < words.len() => tamper(idx - start, word),
+                _ => word,
+            }
+        });
+
+        let first_copy = first_copy.expect("The frame was never sent");
+        let resent = (first_copy + 1..wire.len())
+            .find(|&idx| wire[idx][0] == START)
+            .expect("The frame was never resent");
+        assert!(
+            resent > first_copy + ACK_TIMEOUT as usize,
+            "Resent after {} transfers",
+            resent - first_copy
+        );
+        assert_eq!(packets[1].recv(PlayerId::Parent), Some(PACKET.to_vec()));
+        assert_eq!(packets[1].recv(PlayerId::Parent), None);
+        assert!(packets[0].is_flushed());
+    }
+
+    #[test]
+    fn test_dropped_frame_resent() {
+        check_resent(|_, _| IDLE);
+    }
+
+    #[test]
+    fn test_corrupted_frame_resent() {
+        // The first payload word.
+        check_resent(|idx, word| if idx == 2 { word ^ 1 } else { word });
+    }
+
+    #[test]
+    fn test_waits_for_every_ack() {
+        let link = SimulatedLink::new(3);
+        let queues = [
+            TransferQueues::new(),
+            TransferQueues::new(),
+            TransferQueues::new(),
+        ];
+        let mut links = linked(&link, &queues);
+        let mut packets: Vec<_> = links.iter_mut().map(PacketLink::new).collect();
+        packets[0].send(b"everyone?");
+        // P2's first ack never makes it, while P1's does.
+        let mut lost_ack = false;
+        let wire = run(
+            &link,
+            &queues,
+            &mut packets,
+            ACK_TIMEOUT as usize * 3,
+            |id, word| {
+                if id == PlayerId::P2 && word == ACK && !lost_ack {
+                    lost_ack = true;
+                    return IDLE;
+                }
+                word
+            },
+        );
+        assert!(lost_ack);
+        let starts = wire.iter().filter(|words| words[0] == START).count();
+        assert_eq!(starts, 2);
+        assert!(packets[0].is_flushed());
+        for id in [PlayerId::P1, PlayerId::P2] {
+            let receiver = &mut packets[id as usize];
+            assert_eq!(receiver.recv(PlayerId::Parent), Some(b"everyone?".to_vec()));
+            assert_eq!(receiver.recv(PlayerId::Parent), None);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_frame() {
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let (parent_link, child) = links.split_at_mut(1);
+        let mut parent = PacketLink::new(&mut parent_link[0]);
+        let child = &mut child[0];
+        child.mark_ready();
+        // As if the parent's ack of the first copy got lost.
+        let mut words = Vec::new();
+        encode_frame(0, b"twice", false, &mut words);
+        encode_frame(0, b"twice", false, &mut words);
+        words.push(IDLE);
+        for word in words {
+            assert!(child.queue_send(word));
+        }
+
+        let mut decoder = FrameDecoder::new();
+        let mut acks = 0;
+        for _ in 0..40 {
+            parent.poll().unwrap();
+            let wire = transfer(&link, &queues, |_, word| word);
+            if let Some(event) = decoder.push(wire[0]) {
+                assert_eq!(event, FrameEvent::Ack { sender: 1, seq: 0 });
+                acks += 1;
+            }
+        }
+        assert_eq!(acks, 2);
+        assert_eq!(parent.recv(PlayerId::P1), Some(b"twice".to_vec()));
+        assert_eq!(parent.recv(PlayerId::P1), None);
+    }
+
+    #[test]
+    fn test_interleaved_fragments() {
+        // Half escaped words, so there are plenty of two-word units for acks
+        // to get stuck between.
+        let packet = |id: usize, idx: u8| -> Vec<u8> {
+            (0..MAX_FRAME_BYTES as u8 * 2 + 7)
+                .map(|byte| {
+                    if byte & 2 == 0 {
+                        0xFF
+                    } else {
+                        byte ^ (id as u8 * 64 + idx)
+                    }
+                })
+                .collect()
+        };
+        let link = SimulatedLink::new(3);
+        let queues = [
+            TransferQueues::new(),
+            TransferQueues::new(),
+            TransferQueues::new(),
+        ];
+        let mut links = linked(&link, &queues);
+        let mut packets: Vec<_> = links.iter_mut().map(PacketLink::new).collect();
+        // Everyone sends at once, so each transfer carries a word of
+        // everyone's frames.
+        for (id, sender) in packets.iter_mut().enumerate() {
+            sender.send(&packet(id, 0));
+            sender.send(&packet(id, 1));
+        }
+        let mut wire = Vec::new();
+        for _ in 0..50 {
+            wire.extend(run(&link, &queues, &mut packets, 20, |_, word| word));
+            if packets.iter().all(PacketLink::is_flushed) {
+                break;
+            }
+        }
+        assert!(packets.iter().all(PacketLink::is_flushed));
+        // Nothing was lost, so nothing should have been resent either.
+        for id in 0..3 {
+            let starts = wire.iter().filter(|words| words[id] == START).count();
+            assert_eq!(starts, 6, "Frames sent by {}", id);
+        }
+        for (id, receiver) in packets.iter_mut().enumerate() {
+            for from in PlayerId::ALL[..3].iter().copied() {
+                if from as usize == id {
+                    continue;
+                }
+                assert_eq!(receiver.recv(from), Some(packet(from as usize, 0)));
+                assert_eq!(receiver.recv(from), Some(packet(from as usize, 1)));
+                assert_eq!(receiver.recv(from), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ack_mid_frame() {
+        let payload = [0x42; MAX_FRAME_BYTES];
+        let link = SimulatedLink::new(2);
+        let queues = [TransferQueues::new(), TransferQueues::new()];
+        let mut links = linked(&link, &queues);
+        let (parent_link, child) = links.split_at_mut(1);
+        let mut parent = PacketLink::new(&mut parent_link[0]);
+        let child = &mut child[0];
+        child.mark_ready();
+        parent.send(b"hi");
+        let mut words = Vec::new();
+        encode_frame(0, &payload, false, &mut words);
+        let frame_len = words.len();
+        words.push(IDLE);
+        let mut words = VecDeque::from(words);
+
+        // The child sends a word at a time, so it can ack the parent's frame
+        // as soon as it's in, halfway through its own.
+        let mut decoder = FrameDecoder::new();
+        let mut parent_stream = Vec::new();
+        let mut child_stream = Vec::new();
+        for _ in 0..ACK_TIMEOUT * 3 {
+            parent.poll().unwrap();
+            if child.queued_sends() == 0 {
+                if let Some(word) = words.pop_front() {
+                    child.queue_send(word);
+                }
+            }
+            let wire = transfer(&link, &queues, |_, word| word);
+            parent_stream.push(wire[0]);
+            child_stream.push(wire[1]);
+            if let Some(FrameEvent::Frame { header, .. }) = decoder.push(wire[0]) {
+                let mut ack = Vec::new();
+                encode_ack(0, header.seq, &mut ack);
+                for word in ack.into_iter().rev() {
+                    words.push_front(word);
+                }
+            }
+        }
+
+        let start = child_stream.iter().position(|&w| w == START).unwrap();
+        let ack = child_stream.iter().position(|&w| w == ACK).unwrap();
+        assert!(start < ack && ack < start + frame_len);
+        assert_eq!(parent.recv(PlayerId::P1), Some(payload.to_vec()));
+        assert!(parent.is_flushed());
+        // Acked the first time round, so never resent.
+        assert_eq!(parent_stream.iter().filter(|&&w| w == START).count(), 1);
+    }
+
+    #[test]
+    fn test_acks_keep_escapes_whole() {
+        // All escaped, and with the send queue mostly taken already, so the
+        // frame trickles out a word per transfer.
+        let payload = [0xFF; MAX_FRAME_BYTES];
+        for delay in 0..16 {
+            let link = SimulatedLink::new(2);
+            let queues = [TransferQueues::new(), TransferQueues::new()];
+            let mut links = linked(&link, &queues);
+            let (parent_link, child) = links.split_at_mut(1);
+            for _ in 0..SEND_QUEUE_LEN - 12 {
+                assert!(parent_link[0].queue_send(IDLE));
+            }
+            let mut parent = PacketLink::new(&mut parent_link[0]);
+            let child = &mut child[0];
+            child.mark_ready();
+            parent.send(&payload);
+            // The child's frame lands `delay` transfers in, needing an ack.
+            let mut words = vec![IDLE; delay];
+            encode_frame(0, b"ping", false, &mut words);
+            words.push(IDLE);
+            for word in words {
+                assert!(child.queue_send(word));
+            }
+
+            // Stopping short of when a resend could have made it through.
+            let mut decoder = FrameDecoder::new();
+            let mut events = Vec::new();
+            for _ in 0..ACK_TIMEOUT * 3 {
+                parent.poll().unwrap();
+                let wire = transfer(&link, &queues, |_, word| word);
+                events.extend(decoder.push(wire[0]));
+            }
+            assert_eq!(events.len(), 2, "Ack after {} transfers", delay);
+            assert!(events.contains(&FrameEvent::Ack { sender: 1, seq: 0 }));
+            assert!(events.contains(&frame(0, &payload, false)));
+        }
+    }
+
+    #[test]
+    fn test_peer_drops_out() {
+        let link = SimulatedLink::new(3);
+        let queues = [
+            TransferQueues::new(),
+            TransferQueues::new(),
+            TransferQueues::new(),
+        ];
+        let mut links = linked(&link, &queues);
+        let mut packets: Vec<_> = links.iter_mut().map(PacketLink::new).collect();
+        packets[0].send(b"anyone there?");
+        run(&link, &queues, &mut packets, 4, |_, word| word);
+
+        link.unplug(PlayerId::P2);
+        let mut errors = [None, None];
+        for _ in 0..DROP_TRANSFERS * 2 {
+            for (packet, error) in packets[..2].iter_mut().zip(&mut errors) {
+                if let Err(e) = packet.poll() {
+                    error.get_or_insert(e);
+                }
+            }
+            finish_transfer(&link, &queues);
+        }
+        let dropped = Some(TransferError::Disconnected(PlayerId::P2));
+        assert_eq!(errors, [dropped.clone(), dropped]);
+    }
+}
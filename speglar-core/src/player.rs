@@ -0,0 +1,304 @@
+use agb_fixnum::num;
+use alloc::vec::Vec;
+
+use crate::{
+    map::BaseMap, n_from_parts, AlignedVec, Bullet, BulletTag, BulletType, Direction, Hitbox,
+    RectExt, VectType, MAX_FRAC_PORTION, N,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Player {
+    pub pos: VectType,
+    pub dir: Direction,
+    pub vel: AlignedVec,
+    pub charge: u8,
+    pub cooldown: u8,
+    pub alive: bool,
+    pub tag: PlayerTag,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, PartialOrd, Ord, Default)]
+#[repr(u8)]
+pub enum PlayerTag {
+    #[default]
+    P1 = 0,
+    P2 = 1,
+    P3 = 2,
+    P4 = 3,
+}
+
+impl PlayerTag {
+    pub const fn from_u8(n: u8) -> Self {
+        unsafe { core::mem::transmute(n & 0x3) }
+    }
+    pub const fn bullet_tag(self) -> BulletTag {
+        match self {
+            PlayerTag::P1 => BulletTag::Player1,
+            PlayerTag::P2 => BulletTag::Player2,
+            PlayerTag::P3 => BulletTag::Player3,
+            PlayerTag::P4 => BulletTag::Player4,
+        }
+    }
+}
+
+impl Hitbox for Player {
+    fn pos(&self) -> VectType {
+        self.pos
+    }
+    fn size(&self) -> VectType {
+        VectType::new(num!(7.5), num!(7.5))
+    }
+}
+
+impl Player {
+    pub const SPEED: N = n_from_parts(1, 0);
+    pub const FRICTION: N = n_from_parts(0, MAX_FRAC_PORTION / 3);
+    pub const OVERBOOST_FRICTION: N = n_from_parts(0, MAX_FRAC_PORTION / 2);
+    pub const ACCEL: N = n_from_parts(0, MAX_FRAC_PORTION / 2);
+    /// The speed a player is launched at when hit by their own bullet; anything
+    /// above `SPEED` is slowed by `OVERBOOST_FRICTION` instead of `FRICTION`.
+    pub const PUSH_SPEED: N = n_from_parts(3, 0);
+    pub const MAX_CHARGE: u8 = 3;
+
+    /// Frames to wait after firing a bullet before firing again.
+    pub const BULLET_COOLDOWN: u8 = 20;
+    /// Frames to wait after firing a reflector before firing again.
+    pub const REFLECTOR_COOLDOWN: u8 = 45;
+    /// Max number of a player's bullets that can be on screen at once.
+    pub const MAX_BULLETS: usize = 3;
+    /// Max number of a player's reflectors that can be on screen at once.
+    pub const MAX_REFLECTORS: usize = 1;
+
+    const fn speed_for(dir: Direction) -> AlignedVec {
+        AlignedVec::new_unchecked(Self::SPEED, dir)
+    }
+    pub fn new(pos: VectType, tag: PlayerTag) -> Self {
+        let dir = match tag {
+            PlayerTag::P1 | PlayerTag::P3 => Direction::Right,
+            _ => Direction::Left,
+        };
+
+        Self {
+            pos,
+            dir,
+            vel: AlignedVec::zero(dir),
+            charge: 0,
+            cooldown: 0,
+            alive: true,
+            tag,
+        }
+    }
+
+    pub const fn vflip(&self) -> bool {
+        matches!(self.dir, Direction::Down)
+    }
+    pub const fn hflip(&self) -> bool {
+        matches!(self.dir, Direction::Left)
+    }
+    fn step_vel(&mut self, controls: ControlsRepr) {
+        let is_overboost = self.vel.magnitude() > Self::SPEED;
+        if is_overboost {
+            self.vel = self.vel.step_to(Self::OVERBOOST_FRICTION, num!(0.0));
+            self.dir = controls.dir.unwrap_or(self.dir);
+        } else {
+            match controls.dir {
+                None => {
+                    self.vel = self.vel.step_to(Self::FRICTION, num!(0.0));
+                }
+                Some(ndir) => {
+                    self.dir = ndir;
+                    self.vel = self.vel.step_to_dir(Self::ACCEL, Self::speed_for(self.dir));
+                }
+            }
+        }
+    }
+    pub fn update(
+        &mut self,
+        map: &BaseMap,
+        players_1: &[Player],
+        players_2: &[Player],
+        bullets: &[Bullet],
+        controls: ControlsRepr,
+    ) -> Option<PlayerEvent> {
+        if !self.alive {
+            return None;
+        }
+        self.step_vel(controls);
+
+        let next_pos_raw = self.pos + self.vel;
+        let next_pos = {
+            let remapped = map.index_to_pixel(map.pixel_to_index(next_pos_raw).unwrap());
+            // Snap along the direction we're actually moving, which isn't
+            // necessarily the direction we're facing while overboosted.
+            if self.vel.direction().is_horizontal() {
+                VectType::new(next_pos_raw.x, remapped.y)
+            } else {
+                VectType::new(remapped.x, next_pos_raw.y)
+            }
+        };
+        let next_hitbox = self.next_hitbox(next_pos);
+        let mut collides = false;
+        'outer: {
+            let next_tiles = map.tiles_intersecting(next_hitbox).collect::<Vec<_>>();
+            for next_tile in next_tiles {
+                if !next_tile.allows_player() {
+                    collides = true;
+                    break 'outer;
+                }
+            }
+            for other in players_1.iter().chain(players_2.iter()) {
+                if other.alive && next_hitbox.collides(other) {
+                    collides = true;
+                    break 'outer;
+                }
+            }
+        }
+        if collides {
+            self.vel = AlignedVec::zero(self.dir);
+        } else {
+            self.pos = next_pos;
+        }
+
+        self.cooldown = self.cooldown.saturating_sub(1);
+        let kind = if controls.fired_shield {
+            BulletType::Reflector
+        } else if controls.fired_bullet {
+            BulletType::Bullet
+        } else {
+            return None;
+        };
+        if !self.can_fire(kind, bullets) {
+            return None;
+        }
+        self.cooldown = match kind {
+            BulletType::Bullet => Self::BULLET_COOLDOWN,
+            BulletType::Reflector => Self::REFLECTOR_COOLDOWN,
+        };
+        Some(PlayerEvent::Fire(kind))
+    }
+    /// Puts the player into the death state; they stop being drawn, stop
+    /// colliding with anything, and ignore all further inputs.
+    pub fn kill(&mut self) {
+        self.alive = false;
+        self.vel = AlignedVec::zero(self.dir);
+    }
+    /// Handles the player getting hit by their own bullet, launching them in the
+    /// bullet's direction and building up charge for their next shot.
+    pub fn push_charge(&mut self, dir: Direction) {
+        self.vel = AlignedVec::new_unchecked(Self::PUSH_SPEED, dir);
+        self.charge = (self.charge + 1).min(Self::MAX_CHARGE);
+    }
+    fn can_fire(&self, kind: BulletType, bullets: &[Bullet]) -> bool {
+        if self.cooldown > 0 {
+            return false;
+        }
+        let limit = match kind {
+            BulletType::Bullet => Self::MAX_BULLETS,
+            BulletType::Reflector => Self::MAX_REFLECTORS,
+        };
+        let bullet_tag = self.tag.bullet_tag();
+        let on_screen = bullets
+            .iter()
+            .filter(|b| b.tag == bullet_tag && b.kind == kind)
+            .count();
+        on_screen < limit
+    }
+    /// Creates a new bullet just in front of the player, far enough away that
+    /// it doesn't immediately hit them.
+    ///
+    /// Any built-up charge is spent on the bullet.
+    pub fn spawn_bullet(&mut self, kind: BulletType) -> Bullet {
+        let charge = match kind {
+            BulletType::Bullet => core::mem::take(&mut self.charge),
+            BulletType::Reflector => 0,
+        };
+        let bullet_size = VectType::new(Bullet::SIZE, Bullet::SIZE);
+        let offset = (self.size().x + Bullet::SIZE) / 2 + 1;
+        let center = self.hitbox().center() + self.dir.scaled_vec(offset);
+        Bullet::new(
+            center - bullet_size / 2,
+            self.dir,
+            self.tag.bullet_tag(),
+            kind,
+        )
+        .with_charge(charge)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PlayerEvent {
+    Fire(BulletType),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct ControlsRepr {
+    pub dir: Option<Direction>,
+    pub fired_bullet: bool,
+    pub fired_shield: bool,
+}
+
+impl ControlsRepr {
+    /// Packs the controls into the low 5 bits of a byte: 3 bits of direction
+    /// (0 for none) followed by the bullet and shield flags.
+    pub const fn to_bits(self) -> u8 {
+        let dir = match self.dir {
+            None => 0,
+            Some(Direction::Up) => 1,
+            Some(Direction::Down) => 2,
+            Some(Direction::Left) => 3,
+            Some(Direction::Right) => 4,
+        };
+        dir | ((self.fired_bullet as u8) << 3) | ((self.fired_shield as u8) << 4)
+    }
+    pub const fn from_bits(bits: u8) -> Option<Self> {
+        if bits & !0x1F != 0 {
+            return None;
+        }
+        let dir = match bits & 0x7 {
+            0 => None,
+            1 => Some(Direction::Up),
+            2 => Some(Direction::Down),
+            3 => Some(Direction::Left),
+            4 => Some(Direction::Right),
+            _ => {
+                return None;
+            }
+        };
+        Some(Self {
+            dir,
+            fired_bullet: bits & (1 << 3) != 0,
+            fired_shield: bits & (1 << 4) != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_controls_bits_roundtrip() {
+        for bits in 0..=u8::MAX {
+            if let Some(controls) = ControlsRepr::from_bits(bits) {
+                assert_eq!(controls.to_bits(), bits);
+            }
+        }
+        let dirs = [
+            None,
+            Some(Direction::Up),
+            Some(Direction::Down),
+            Some(Direction::Left),
+            Some(Direction::Right),
+        ];
+        for dir in dirs {
+            for (fired_bullet, fired_shield) in [(false, false), (true, false), (false, true)] {
+                let controls = ControlsRepr {
+                    dir,
+                    fired_bullet,
+                    fired_shield,
+                };
+                assert_eq!(ControlsRepr::from_bits(controls.to_bits()), Some(controls));
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+pub struct Rng {
+    cur_state: u64,
+}
+
+impl Rng {
+    pub const fn with_seed(seed: u64) -> Self {
+        Self { cur_state: seed }
+    }
+    pub const fn bool_const(self) -> (Self, bool) {
+        let next_state = step(self.cur_state);
+        (Self::with_seed(next_state), next_state % 2 == 1)
+    }
+    /// Returns the next raw 64-bit value; never 0 unless the seed was.
+    pub const fn next_u64_const(self) -> (Self, u64) {
+        let next_state = step(self.cur_state);
+        (Self::with_seed(next_state), next_state)
+    }
+    pub const fn u8_const(self, min: u8, max: u8) -> (Self, u8) {
+        let (next, base) = self.u64_const(min as u64, max as u64);
+        (next, base as u8)
+    }
+    pub const fn usize_const(self, min: usize, max: usize) -> (Self, usize) {
+        let (next, base) = self.u64_const(min as u64, max as u64);
+        (next, base as usize)
+    }
+    pub const fn u64_const(self, min: u64, max: u64) -> (Self, u64) {
+        let next_state = step(self.cur_state);
+        let range = max - min + 1;
+        (Self::with_seed(next_state), min + next_state % range)
+    }
+    pub const fn i32_const(self, min: i32, max: i32) -> (Self, i32) {
+        let next_state = step(self.cur_state);
+        let n = (next_state & ((1 << 31) - 1)) as i32;
+        let range = max - min + 1;
+        (Self::with_seed(next_state), min + n % range)
+    }
+}
+
+const fn step(cur: u64) -> u64 {
+    let mut retvl = cur;
+    retvl ^= retvl << 13;
+    retvl ^= retvl >> 7;
+    retvl ^= retvl << 17;
+    retvl
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::{any, prop_assert, prop_assert_ne, proptest, Strategy};
+
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Rng::with_seed(0xdeadbeef);
+        let mut b = Rng::with_seed(0xdeadbeef);
+        for _ in 0..1000 {
+            let (na, va) = a.next_u64_const();
+            let (nb, vb) = b.next_u64_const();
+            assert_eq!(va, vb);
+            (a, b) = (na, nb);
+        }
+    }
+
+    #[test]
+    fn test_ranges_are_inclusive() {
+        let mut rng = Rng::with_seed(12345);
+        let mut seen = [false; 5];
+        for _ in 0..1000 {
+            let (next, n) = rng.usize_const(3, 7);
+            rng = next;
+            assert!((3..=7).contains(&n), "{} out of range", n);
+            seen[n - 3] = true;
+        }
+        assert!(seen.iter().all(|s| *s), "Missed a value: {:?}", seen);
+
+        for _ in 0..1000 {
+            let (next, n) = rng.i32_const(-4, 4);
+            rng = next;
+            assert!((-4..=4).contains(&n), "{} out of range", n);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_nonzero_seeds_never_reach_zero(seed in 1..=u64::MAX) {
+            let mut rng = Rng::with_seed(seed);
+            for _ in 0..64 {
+                let (next, value) = rng.next_u64_const();
+                prop_assert_ne!(value, 0);
+                rng = next;
+            }
+        }
+
+        #[test]
+        fn test_values_stay_in_range(
+            seed in 1..=u64::MAX,
+            (min, max) in (any::<u32>(), any::<u32>()).prop_map(|(a, b)| (a.min(b), a.max(b))),
+            (imin, imax) in (-(1i32 << 20)..(1 << 20), 0i32..(1 << 20)).prop_map(|(a, len)| (a, a + len)),
+        ) {
+            let rng = Rng::with_seed(seed);
+            let (rng, n) = rng.u64_const(min as u64, max as u64);
+            prop_assert!((min as u64..=max as u64).contains(&n));
+            let (_, n) = rng.i32_const(imin, imax);
+            prop_assert!((imin..=imax).contains(&n));
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use alloc::vec::Vec;
+
+use crate::{
+    map::BaseMap, split_mut_at, Bullet, BulletEvent, ControlsRepr, Player, PlayerEvent, PlayerTag,
+};
+
+/// All of the gameplay state for a single round.
+///
+/// This is plain data with no display handles, so it can be cloned, hashed and
+/// stepped without a screen; see `render::Renderer` for drawing it.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Simulation {
+    pub map: BaseMap,
+    pub players: Vec<Player>,
+    pub bullets: Vec<Bullet>,
+}
+
+impl Simulation {
+    pub fn new(map: BaseMap, num_players: usize) -> Self {
+        let mut players = Vec::with_capacity(4);
+        for (pidx, spawn) in map.player_spawns().iter().enumerate().take(num_players) {
+            let ptag = PlayerTag::from_u8(pidx as u8);
+            let player = Player::new(map.index_to_pixel(*spawn), ptag);
+            players.push(player);
+        }
+        Self {
+            map,
+            players,
+            bullets: Vec::new(),
+        }
+    }
+    /// Steps the game by one frame, with `inputs` indexed by `PlayerTag`.
+    pub fn step(&mut self, inputs: &[ControlsRepr; 4]) {
+        for idx in 0..self.players.len() {
+            let Some((pa, cur, pb)) = split_mut_at(&mut self.players, idx) else {
+                continue;
+            };
+            let controls = inputs[cur.tag as usize];
+
+            let evt = cur.update(&self.map, pa, pb, &self.bullets, controls);
+            if let Some(PlayerEvent::Fire(kind)) = evt {
+                self.bullets.push(cur.spawn_bullet(kind));
+            }
+        }
+        // Entities are only removed after every update has run, since the
+        // `split_mut_at` loops above depend on indices staying stable; kills
+        // are tracked by tag for the same reason.
+        let mut players_to_kill = Vec::new();
+        let mut players_to_push = Vec::new();
+        let bullet_n = self.bullets.len();
+        for idx in 0..bullet_n {
+            let Some((ba, cur, bb)) = split_mut_at(&mut self.bullets, idx) else {
+                continue;
+            };
+            if let Some(evt) = cur.update(&self.map, &self.players, ba, bb) {
+                match evt {
+                    BulletEvent::KillPlayer(tag) => {
+                        players_to_kill.push(tag);
+                    }
+                    BulletEvent::PushChargePlayer(tag, dir) => {
+                        players_to_push.push((tag, dir));
+                    }
+                }
+            }
+        }
+        for (tag, dir) in players_to_push {
+            if let Some(player) = self.players.iter_mut().find(|p| p.tag == tag) {
+                player.push_charge(dir);
+            }
+        }
+        for tag in players_to_kill {
+            if let Some(player) = self.players.iter_mut().find(|p| p.tag == tag) {
+                player.kill();
+            }
+        }
+        self.bullets.retain(|bullet| !bullet.should_die);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        map::{self, tests::EMPTY_MAP, MapTile},
+        rng::Rng,
+        Direction,
+    };
+
+    fn fire_right() -> ControlsRepr {
+        ControlsRepr {
+            dir: None,
+            fired_bullet: true,
+            fired_shield: false,
+        }
+    }
+
+    #[test]
+    fn test_bullet_kills_other_player() {
+        let mut sim = Simulation::new(EMPTY_MAP, 2);
+        sim.players[0].pos = sim.map.index_to_pixel((2, 5));
+        sim.players[1].pos = sim.map.index_to_pixel((6, 5));
+        let mut inputs = [ControlsRepr::default(); 4];
+        inputs[0] = fire_right();
+        sim.step(&inputs);
+        assert_eq!(sim.bullets.len(), 1);
+
+        let inputs = [ControlsRepr::default(); 4];
+        for _ in 0..2000 {
+            sim.step(&inputs);
+            if !sim.players[1].alive {
+                break;
+            }
+        }
+        assert!(!sim.players[1].alive);
+        assert!(sim.players[0].alive);
+        assert!(sim.bullets.is_empty());
+    }
+
+    #[test]
+    fn test_own_bullet_pushes_and_charges() {
+        // Bounce a bullet straight back off a vertical mirror.
+        let map = EMPTY_MAP.with(6, 5, MapTile::VertMirror);
+        let mut sim = Simulation::new(map, 1);
+        sim.players[0].pos = sim.map.index_to_pixel((2, 5));
+        let mut inputs = [ControlsRepr::default(); 4];
+        inputs[0] = fire_right();
+        sim.step(&inputs);
+
+        let inputs = [ControlsRepr::default(); 4];
+        for _ in 0..4000 {
+            sim.step(&inputs);
+            if sim.bullets.is_empty() {
+                break;
+            }
+        }
+        let player = &sim.players[0];
+        assert!(player.alive);
+        assert_eq!(player.charge, 1);
+        assert_eq!(player.vel.direction(), Direction::Left);
+    }
+
+    /// Two copies of the same game fed the same inputs never drift apart, which
+    /// netplay depends on.
+    #[test]
+    fn test_step_is_deterministic() {
+        for seed in 1..8 {
+            let map = map::generate(seed, map::HONEYCOMB_BASE, Default::default());
+            let mut a = Simulation::new(map, 4);
+            let mut b = a.clone();
+            let mut rng = Rng::with_seed(seed);
+            for frame in 0..1200 {
+                let mut inputs = [ControlsRepr::default(); 4];
+                for input in inputs.iter_mut() {
+                    let (next, bits) = rng.u8_const(0, 0x1F);
+                    rng = next;
+                    *input = ControlsRepr::from_bits(bits).unwrap_or_default();
+                }
+                a.step(&inputs);
+                b.step(&inputs);
+                assert_eq!(a, b, "Seed {} diverged on frame {}", seed, frame);
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use core::ops::{Add, AddAssign};
 
-use agb::fixnum::{num, Num, Rect, Vector2D};
+use agb_fixnum::{num, Num, Rect, Vector2D};
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Direction {
@@ -165,6 +165,9 @@ impl AlignedVec {
     pub fn magnitude(&self) -> N {
         self.mag
     }
+    pub const fn direction(&self) -> Direction {
+        self.dir
+    }
 
     pub fn x(&self) -> N {
         use Direction::*;
@@ -235,12 +238,27 @@ pub const fn write_bit_u8(v: u8, n: u8, bit: bool) -> u8 {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use proptest::prelude::{prop_assert, prop_assert_eq, prop_oneof, proptest, Just, Strategy};
+
     use super::*;
-    use agb::Gba;
 
-    #[test_case]
-    fn test_n_stepper(_gba: &mut Gba) {
+    pub(crate) fn direction() -> impl Strategy<Value = Direction> {
+        prop_oneof![
+            Just(Direction::Up),
+            Just(Direction::Down),
+            Just(Direction::Left),
+            Just(Direction::Right),
+        ]
+    }
+
+    /// Numbers well clear of overflowing when added together.
+    fn num() -> impl Strategy<Value = N> {
+        (-(1i32 << 24)..(1 << 24)).prop_map(N::from_raw)
+    }
+
+    #[test]
+    fn test_n_stepper() {
         let tests = [
             // Test basic
             (0.0, 0.2, 1.0, 0.2),
@@ -264,4 +282,78 @@ mod tests {
             assert_eq!(expected, actual, "Error in test: {}", idx);
         }
     }
+
+    #[test]
+    fn test_aligned_vec_step_to_dir() {
+        let speed = n_from_parts(1, 0);
+        let step = n_from_parts(0, MAX_FRAC_PORTION / 2);
+        let right = AlignedVec::new_unchecked(speed, Direction::Right);
+        let left = AlignedVec::new_unchecked(speed, Direction::Left);
+
+        // Turning around slows down through 0 rather than flipping instantly.
+        let mut vel = right;
+        let mut frames = 0;
+        while vel != left {
+            vel = vel.step_to_dir(step, left);
+            assert!(vel.magnitude() <= speed);
+            frames += 1;
+            assert!(frames < 100, "Never turned around: {:?}", vel);
+        }
+        assert!(frames > 2);
+
+        // Turning sideways stops dead, then speeds up in the new direction.
+        let up = AlignedVec::new_unchecked(speed, Direction::Up);
+        let turned = right.step_to_dir(step, up);
+        assert_eq!(turned.direction(), Direction::Up);
+        assert!(turned.magnitude() < speed);
+
+        assert_eq!(
+            AlignedVec::new(-speed, Direction::Up),
+            AlignedVec::new_unchecked(speed, Direction::Down)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_step_to_never_overshoots(cur in num(), step in num(), goal in num()) {
+            let next = step_to(cur, step, goal);
+            prop_assert!((cur.min(goal)..=cur.max(goal)).contains(&next));
+            prop_assert!((next - cur).abs() <= step.abs());
+            if (goal - cur).abs() <= step.abs() {
+                prop_assert_eq!(next, goal);
+            }
+        }
+
+        #[test]
+        fn test_aligned_vec_matches_scaled_vec(n in num(), dir in direction()) {
+            let vec = AlignedVec::new(n, dir);
+            prop_assert!(vec.magnitude() >= num!(0.0));
+            prop_assert_eq!(vec.magnitude(), n.abs());
+            prop_assert_eq!(VectType::new(vec.x(), vec.y()), dir.scaled_vec(n));
+        }
+
+        #[test]
+        fn test_step_to_dir_reaches_goal(
+            start in num(),
+            start_dir in direction(),
+            goal in num(),
+            goal_dir in direction(),
+            step in (1i32 << 12..1 << 20).prop_map(N::from_raw),
+        ) {
+            let goal = AlignedVec::new(goal, goal_dir);
+            let mut vel = AlignedVec::new(start, start_dir);
+            let limit = vel.magnitude().max(goal.magnitude());
+            // Slowing to a stop and speeding back up again is the long way
+            // round.
+            let max_steps = (vel.magnitude() + goal.magnitude()).to_raw() / step.to_raw() + 2;
+            for _ in 0..max_steps {
+                vel = vel.step_to_dir(step, goal);
+                prop_assert!(vel.magnitude() <= limit);
+            }
+            prop_assert_eq!(vel.magnitude(), goal.magnitude());
+            if goal.magnitude() != num!(0.0) {
+                prop_assert_eq!(vel.direction(), goal.direction());
+            }
+        }
+    }
 }
@@ -1,13 +1,163 @@
-use agb::display::object::{Graphics, Tag};
+use agb::display::object::{DynamicSprite, Graphics, PaletteVram, Size, SpriteVram, Tag};
 use agb::display::palette16::Palette16;
 use agb::display::tile_data::TileData;
+use agb::display::tiled::{MapLoan, RegularMap, TileSetting, VRamManager};
 use agb::{include_aseprite, include_background_gfx};
 
+use crate::{BulletTag, BulletType};
+
 include_background_gfx!(bg, "0a0b0c", DATA => "assets/sprites/background.png");
 pub static TILEDATA: &TileData = &bg::DATA;
 pub static PALETTES: &[Palette16] = bg::PALETTES;
 pub static SPRITES: &Graphics = include_aseprite!("assets/sprites/sprites.aseprite");
 
+/// Colours for the generated bullet sprites, indexed by `BulletTag as usize + 1`.
+pub static BULLET_PALETTE: Palette16 = Palette16::new([
+    0x0000, 0x6318, 0x001F, 0x7C00, 0x03E0, 0x03FF, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
+    0x0000, 0x0000, 0x0000, 0x0000,
+]);
+
+/// The bullet sprites, generated at runtime since the aseprite file doesn't
+/// have any.
+///
+/// Bullets are a filled 4x4 square and reflectors are a hollow one, coloured
+/// based on the player that fired them.
+pub struct BulletSprites {
+    sprites: [[SpriteVram; 2]; 5],
+}
+
+impl Default for BulletSprites {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BulletSprites {
+    pub fn new() -> Self {
+        let palette = PaletteVram::new(&BULLET_PALETTE).expect("Should have a free palette");
+        let tags = [
+            BulletTag::NoPlayer,
+            BulletTag::Player1,
+            BulletTag::Player2,
+            BulletTag::Player3,
+            BulletTag::Player4,
+        ];
+        let sprites = tags.map(|tag| {
+            [BulletType::Bullet, BulletType::Reflector]
+                .map(|kind| Self::make_sprite(tag, kind).to_vram(palette.clone()))
+        });
+        Self { sprites }
+    }
+    fn make_sprite(tag: BulletTag, kind: BulletType) -> DynamicSprite {
+        let colour = tag as usize + 1;
+        let mut sprite = DynamicSprite::new(Size::S8x8);
+        for x in 0..4 {
+            for y in 0..4 {
+                let is_edge = x == 0 || x == 3 || y == 0 || y == 3;
+                if kind == BulletType::Bullet || is_edge {
+                    sprite.set_pixel(x, y, colour);
+                }
+            }
+        }
+        sprite
+    }
+    pub fn get(&self, tag: BulletTag, kind: BulletType) -> SpriteVram {
+        self.sprites[tag as usize][kind as usize].clone()
+    }
+}
+
+/// A 3x5 pixel font for the digits 0-9; each row is 3 bits, with the high bit
+/// on the left.
+const DIGIT_FONT: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b011, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+pub const DIGIT_WIDTH: u16 = 3;
+pub const DIGIT_HEIGHT: u16 = 5;
+
+/// Draws `n` onto `bg` using block tiles as pixels, with the top left of the
+/// first digit at tile `(x, y)`.
+///
+/// Returns the width of the drawn number in tiles.
+pub fn draw_number(
+    bg: &mut MapLoan<'_, RegularMap>,
+    vram: &mut VRamManager,
+    (x, y): (u16, u16),
+    n: u32,
+) -> u16 {
+    let mut digits = [0u8; 10];
+    let mut len = 0;
+    let mut rest = n;
+    loop {
+        digits[len] = (rest % 10) as u8;
+        len += 1;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    for (idx, digit) in digits[..len].iter().rev().enumerate() {
+        let digit_x = x + idx as u16 * (DIGIT_WIDTH + 1);
+        draw_glyph(
+            bg,
+            vram,
+            (digit_x, y),
+            &DIGIT_FONT[*digit as usize],
+            DIGIT_WIDTH,
+        );
+    }
+    len as u16 * (DIGIT_WIDTH + 1) - 1
+}
+
+/// Draws a picture `width` tiles wide onto `bg` using block tiles as pixels,
+/// with each of `rows` laid out like `DIGIT_FONT`'s.
+pub fn draw_glyph(
+    bg: &mut MapLoan<'_, RegularMap>,
+    vram: &mut VRamManager,
+    (x, y): (u16, u16),
+    rows: &[u8],
+    width: u16,
+) {
+    for (row_idx, row) in rows.iter().enumerate() {
+        for col in 0..width {
+            if row & (1 << (width - 1 - col)) == 0 {
+                continue;
+            }
+            bg.set_tile(
+                vram,
+                (x + col, y + row_idx as u16),
+                &TILEDATA.tiles,
+                TileSetting::new(0, false, false, 0),
+            );
+        }
+    }
+}
+
+/// Fills `width` tiles starting at `(x, y)`, for underlines and the like.
+pub fn fill_row(
+    overlay: &mut MapLoan<'_, RegularMap>,
+    vram: &mut VRamManager,
+    (x, y): (u16, u16),
+    width: u16,
+) {
+    for col in x..x + width {
+        overlay.set_tile(
+            vram,
+            (col, y),
+            &TILEDATA.tiles,
+            TileSetting::new(0, false, false, 0),
+        );
+    }
+}
+
 #[allow(dead_code)]
 pub mod tags {
 
@@ -0,0 +1,142 @@
+//! Drawing the lobby, and feeding it the GBA's buttons; the lobby itself is
+//! `speglar_core::lobby`.
+use agb::display::{
+    object::{OamManaged, Object},
+    tiled::{MapLoan, RegularMap, TiledMap, VRamManager},
+};
+use agb::input::{Button, ButtonController};
+use alloc::vec::Vec;
+
+pub use speglar_core::lobby::*;
+
+use crate::{
+    graphics::{draw_number, fill_row, DIGIT_HEIGHT, DIGIT_WIDTH},
+    rounds::MatchRules,
+    serial::{multiplayer::PlayerId, Hardware},
+    sprite_tag,
+};
+
+/// The buttons the lobby cares about that were just pressed.
+pub fn lobby_input(btns: &ButtonController) -> LobbyInput {
+    LobbyInput {
+        start: btns.is_just_pressed(Button::START),
+        a: btns.is_just_pressed(Button::A),
+        left: btns.is_just_pressed(Button::LEFT),
+        right: btns.is_just_pressed(Button::RIGHT),
+        up: btns.is_just_pressed(Button::UP),
+        down: btns.is_just_pressed(Button::DOWN),
+        l: btns.is_just_pressed(Button::L),
+        r: btns.is_just_pressed(Button::R),
+    }
+}
+
+/// The rules for a match between the first `num_players` players of
+/// `outcome`.
+pub fn match_rules(outcome: &LobbyOutcome, num_players: u8) -> MatchRules {
+    let defaults = MatchRules::default();
+    let mut characters = defaults.characters;
+    for (character, slot) in characters.iter_mut().zip(outcome.slots) {
+        if let Some(slot) = slot {
+            *character = slot.character;
+        }
+    }
+    let time_limit = outcome.settings.time_limit_secs();
+    MatchRules {
+        rounds_to_win: outcome.settings.rounds_to_win,
+        time_limit: (time_limit != 0).then_some(time_limit as u32 * 60),
+        num_players,
+        characters,
+        ..defaults
+    }
+}
+
+/// What the lobby screen is showing, so it only gets redrawn when that
+/// changes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+struct Shown {
+    slots: [Option<Slot>; 4],
+    settings: LobbySettings,
+    selected: Option<Setting>,
+}
+
+/// Draws a `Lobby`: a column for each player with their number, character and
+/// whether they're ready, and the match settings underneath.
+#[derive(Default)]
+pub struct LobbyRenderer<'a> {
+    characters: Vec<Object<'a>>,
+    shown: Option<Shown>,
+}
+
+/// How many tiles wide each player's column is.
+const COLUMN_WIDTH: u16 = 7;
+/// The tile rows the characters and ready bars sit on.
+const CHARACTER_ROW: u16 = DIGIT_HEIGHT + 2;
+const READY_ROW: u16 = CHARACTER_ROW + 2;
+const SETTINGS_ROW: u16 = READY_ROW + 4;
+
+impl<'a> LobbyRenderer<'a> {
+    pub fn new() -> Self {
+        Self {
+            characters: Vec::new(),
+            shown: None,
+        }
+    }
+
+    pub fn update_display(
+        &mut self,
+        lobby: &Lobby<'_, '_, Hardware>,
+        gfx: &'a OamManaged,
+        overlay: &mut MapLoan<'_, RegularMap>,
+        vram: &mut VRamManager,
+    ) {
+        let next = Shown {
+            slots: lobby.slots(),
+            settings: lobby.settings(),
+            selected: lobby.selected(),
+        };
+        if self.shown == Some(next) {
+            return;
+        }
+        self.shown = Some(next);
+        self.characters.clear();
+        overlay.clear(vram);
+        for id in PlayerId::ALL {
+            let Some(slot) = next.slots[id as usize] else {
+                continue;
+            };
+            let x = id as u16 * COLUMN_WIDTH + 1;
+            let digit_x = x + (COLUMN_WIDTH - 1 - DIGIT_WIDTH) / 2;
+            draw_number(overlay, vram, (digit_x, 0), id as u32 + 1);
+            if id == lobby.local_id() {
+                fill_row(overlay, vram, (digit_x, DIGIT_HEIGHT), DIGIT_WIDTH);
+            }
+            let mut obj = gfx.object_sprite(sprite_tag(slot.character).sprite(0));
+            let pos = ((digit_x as i32 + 1) * 8, CHARACTER_ROW as i32 * 8);
+            obj.set_position(pos).show();
+            self.characters.push(obj);
+            if slot.ready {
+                fill_row(overlay, vram, (x, READY_ROW), COLUMN_WIDTH - 1);
+            }
+        }
+        let settings = next.settings;
+        // Like the time limit, a 0 map (`RANDOM_MAP`) shows as just that.
+        let columns = [
+            (Setting::RoundsToWin, 1, settings.rounds_to_win as u32),
+            (Setting::TimeLimit, 6, settings.time_limit_secs() as u32),
+            (Setting::Map, 18, settings.map as u32),
+        ];
+        for (setting, x, value) in columns {
+            let width = draw_number(overlay, vram, (x, SETTINGS_ROW), value);
+            if next.selected == Some(setting) {
+                fill_row(overlay, vram, (x, SETTINGS_ROW + DIGIT_HEIGHT + 1), width);
+            }
+        }
+        overlay.commit(vram);
+    }
+
+    /// Drops every object being displayed, freeing up their OAM slots.
+    pub fn clear(&mut self) {
+        self.characters.clear();
+        self.shown = None;
+    }
+}
@@ -2,6 +2,7 @@ use core::fmt;
 
 use agb::external::portable_atomic::{AtomicU16, Ordering};
 use agb::mgba::{self, DebugLevel, Mgba};
+use speglar_core::logs::{self as core_logs, LogLevel};
 
 pub struct Logger {
     framecounter: AtomicU16,
@@ -22,6 +23,18 @@ impl Logger {
             mgba.set_level(level);
         }
     }
+    /// Has everything `speglar_core` logs come out here too.
+    pub fn forward_core_logs(&self) {
+        core_logs::set_sink(|level, msg| {
+            let level = match level {
+                LogLevel::Error => DebugLevel::Error,
+                LogLevel::Warning => DebugLevel::Warning,
+                LogLevel::Info => DebugLevel::Info,
+                LogLevel::Debug => DebugLevel::Debug,
+            };
+            let _ = Logger::get().log(level, msg);
+        });
+    }
     pub fn id_from_framecount(&self) -> Result<(), u16> {
         self.set_id(self.framecounter.load(Ordering::Relaxed))
     }
@@ -19,27 +19,28 @@ extern crate alloc;
 
 use agb::{
     display::{
-        object::OamManaged,
         tiled::{MapLoan, RegularBackgroundSize, RegularMap, TiledMap, VRamManager},
         Priority,
     },
-    external::portable_atomic::Ordering,
     input::{Button, ButtonController},
     interrupt::{add_interrupt_handler, Interrupt},
     mgba::DebugLevel,
     Gba,
 };
 
-mod bullet;
+mod lobby;
 mod map;
-mod rng;
+mod menu;
+mod render;
+mod rounds;
 mod serial;
-use alloc::{format, vec::Vec};
-use bullet::*;
+use alloc::format;
 use core::fmt::Write;
-mod utils;
-use map::GameMap;
-pub use utils::*;
+use lobby::{lobby_input, match_rules, Lobby, LobbyRenderer};
+use menu::{title_menu, MenuEntry};
+use netplay::{share_seed, Lockstep, NetSession, Rollback};
+use rounds::{Match, MatchRenderer, MatchRules};
+pub use speglar_core::{bullet::*, netplay, rng, sim, utils, utils::*};
 mod player;
 pub use player::*;
 mod graphics;
@@ -51,122 +52,294 @@ use logs::{println, warning, Logger};
 // and interrupt handlers correctly. It will also handle creating the `Gba` struct for you.
 #[agb::entry]
 fn main(mut gba: agb::Gba) -> ! {
-    multiplayer_test_main(gba)
+    netplay_main(gba)
 }
 
 #[allow(dead_code)]
 fn main_inner(mut gba: Gba) -> ! {
     let vblank = agb::interrupt::VBlank::get();
     Logger::get().set_level(DebugLevel::Debug);
-    let test_map = map::generate(0xdeadbeef, map::HONEYCOMB_BASE, 16, 32);
     let gfx = gba.display.object.get_managed();
-    let test_map = GameMap::new_undisplayed(test_map);
-    let mut game = GameState::new(test_map, PlayerTag::P1);
+    let mut btns = ButtonController::new();
+    let mut game = Match::new(MatchRules::default(), 0xdeadbeef);
+    let mut renderer = MatchRenderer::new();
     let (tiled, mut vram) = gba.display.video.tiled0();
     let mut bg = tiled.background(
         Priority::P0,
         RegularBackgroundSize::Background32x32,
         graphics::TILEDATA.tiles.format(),
     );
-    game.init_display(&gfx, &mut bg, &mut vram);
-    bg.commit(&mut vram);
+    let mut overlay = tiled.background(
+        Priority::P0,
+        RegularBackgroundSize::Background32x32,
+        graphics::TILEDATA.tiles.format(),
+    );
+    renderer.init_display(&game, &gfx, &mut bg, &mut overlay, &mut vram);
     bg.set_visible(true);
+    overlay.set_visible(true);
     loop {
-        game.update_logic();
+        btns.update();
+        let mut inputs = [ControlsRepr::default(); 4];
+        inputs[PlayerTag::P1 as usize] = read_controls(&btns);
+        game.step(&inputs);
         vblank.wait_for_vblank();
-        game.update_display(&gfx);
+        renderer.update_display(&game, &gfx, &mut bg, &mut overlay, &mut vram);
         gfx.commit();
         Logger::get().tick();
     }
-    drop(bg);
-    drop(test_map);
 }
 
-pub struct GameState<'a> {
-    pub map: GameMap<'a>,
-    pub players: Vec<Player<'a>>,
-    pub bullets: Vec<Bullet<'a>>,
-    pub local_player: PlayerTag,
-    pub button_controller: ButtonController,
-}
+/// Whether netplay predicts other players' inputs and rolls back, rather than
+/// waiting on them every frame.
+const USE_ROLLBACK: bool = true;
 
-impl<'a> GameState<'a> {
-    pub fn new(map: GameMap<'a>, local_player: PlayerTag) -> Self {
-        let mut players = Vec::with_capacity(4);
-        for (pidx, spawn) in map.player_spawns().iter().enumerate() {
-            let ptag = PlayerTag::from_u8(pidx as u8);
-            let player = Player::new(map.data.index_to_pixel(*spawn), ptag);
-            players.push(player);
-        }
-        Self {
-            map,
-            players,
-            bullets: Vec::new(),
-            local_player,
-            button_controller: ButtonController::new(),
-        }
+/// Runs matches over the link cable, with every GBA stepping the game in
+/// sync, going back to the lobby after each one.
+fn netplay_main(mut gba: Gba) -> ! {
+    let vblank = agb::interrupt::VBlank::get();
+    Logger::get().set_level(DebugLevel::Debug);
+    Logger::get().forward_core_logs();
+    let mut btns = ButtonController::new();
+    let gfx = gba.display.object.get_managed();
+    let (tiled, mut vram) = gba.display.video.tiled0();
+    let mut bg = tiled.background(
+        Priority::P0,
+        RegularBackgroundSize::Background32x32,
+        graphics::TILEDATA.tiles.format(),
+    );
+    let mut overlay = tiled.background(
+        Priority::P0,
+        RegularBackgroundSize::Background32x32,
+        graphics::TILEDATA.tiles.format(),
+    );
+    // Download play is only on offer when this build has a client to send.
+    let entries: &[MenuEntry] = if DOWNLOAD_PLAY_CLIENT.is_some() {
+        &[MenuEntry::LinkMatch, MenuEntry::HostDownloadPlay]
+    } else {
+        &[MenuEntry::LinkMatch]
+    };
+    overlay.set_visible(true);
+    let (entry, entropy) = title_menu(entries, &mut btns, &mut overlay, &mut vram);
+    Logger::get().id_from_framecount().unwrap();
+    let mut serial = Serial::new();
+    if let (MenuEntry::HostDownloadPlay, Some(client)) = (entry, DOWNLOAD_PLAY_CLIENT) {
+        host_download_play(&mut serial, &mut btns, &client.0);
     }
-    pub fn init_display(
-        &mut self,
-        gfx: &'a OamManaged,
-        bg: &mut MapLoan<'_, RegularMap>,
-        vram: &mut VRamManager,
-    ) {
-        self.map.init_display(gfx, bg, vram);
-        for player in &mut self.players {
-            player.init_display(gfx);
-        }
+    let mut multiplayer_handle = MultiplayerSerial::new(&mut serial, BaudRate::B9600).unwrap();
+    multiplayer_handle.enable_buffer_interrupt();
+    // The others might not have pressed A yet, so keep waiting for them.
+    while let Err(e) = multiplayer_handle.initialize_id() {
+        warning!("Couldn't get our ID: {:?}", e);
     }
-    pub fn update_logic(&mut self) {
-        self.button_controller.update();
-        for idx in 0..self.players.len() {
-            let Some((pa, cur, pb)) = split_mut_at(&mut self.players, idx) else {
-                continue;
-            };
-            let controls = if cur.tag == self.local_player {
-                ControlsRepr::from(&self.button_controller)
-            } else {
-                ControlsRepr::default()
-            };
+    // Each match gets its own random arena.
+    let mut rng = rng::Rng::with_seed(entropy | 1);
+    loop {
+        let mut lobby = Lobby::new(&mut *multiplayer_handle);
+        let mut lobby_renderer = LobbyRenderer::new();
+        let lobby = loop {
+            btns.update();
+            if let Some(outcome) = lobby.update(lobby_input(&btns)) {
+                break outcome;
+            }
+            vblank.wait_for_vblank();
+            lobby_renderer.update_display(&lobby, &gfx, &mut overlay, &mut vram);
+            gfx.commit();
+            Logger::get().tick();
+        };
+        lobby_renderer.clear();
+        gfx.commit();
+        // A picked arena number is already everyone's seed; for random arenas
+        // the children only get theirs here, from the parent's title screen
+        // timing.
+        let entropy;
+        (rng, entropy) = rng.next_u64_const();
+        let seed = match share_seed(&mut *multiplayer_handle, lobby.seed().unwrap_or(entropy)) {
+            Ok(seed) => seed,
+            Err(e) => show_link_error(e, &mut bg, &mut overlay, &mut vram),
+        };
+        let mut session = if USE_ROLLBACK {
+            NetSession::Rollback(Rollback::new(&mut *multiplayer_handle))
+        } else {
+            NetSession::Lockstep(Lockstep::new(&mut *multiplayer_handle))
+        };
+        println!(
+            "We are {:?} of {} players",
+            session.local_tag(),
+            session.num_players()
+        );
 
-            cur.update(&self.map.data, pa, pb, &self.bullets, controls);
-        }
-        let mut players_to_remove = Vec::new();
-        let mut bullets_to_remove = Vec::new();
-        let bullet_n = self.bullets.len();
-        for idx in 0..bullet_n {
-            let Some((ba, cur, bb)) = split_mut_at(&mut self.bullets, idx) else {
-                continue;
+        let rules = match_rules(&lobby, session.num_players() as u8);
+        let mut game = Match::new(rules, seed);
+        let mut renderer = MatchRenderer::new();
+        renderer.init_display(&game, &gfx, &mut bg, &mut overlay, &mut vram);
+        bg.set_visible(true);
+        overlay.set_visible(true);
+        loop {
+            btns.update();
+            // Once the match is finished, nobody's inputs matter any more;
+            // we just have to make sure everyone has ours before heading back
+            // to the lobby.
+            let flushed = if game.is_finished() {
+                session.flush()
+            } else {
+                session
+                    .advance(&mut game, read_controls(&btns))
+                    .map(|_| false)
             };
-            if let Some(evt) = cur.update(&self.map.data, &self.players, ba, bb) {
-                match evt {
-                    BulletEvent::KillPlayer(tag) => {
-                        if let Ok(pidx) = self.players.binary_search_by_key(&tag, |p| p.tag) {
-                            players_to_remove.push(pidx);
-                        }
-                    }
-                    other => {
-                        println!("TODO: Handle event {:?}", other);
-                    }
+            match flushed {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(e) => {
+                    renderer.clear();
+                    gfx.commit();
+                    show_link_error(e, &mut bg, &mut overlay, &mut vram);
                 }
             }
-            if cur.should_die {
-                bullets_to_remove.push(idx);
+            // Anyone back in the lobby already sends words that could pass
+            // for inputs, so only the match itself gets checked.
+            if let Some(desync) = session.desync().filter(|_| !game.is_finished()) {
+                renderer.clear();
+                gfx.commit();
+                show_stopped(
+                    Stopped::Desync(desync.frame),
+                    &mut bg,
+                    &mut overlay,
+                    &mut vram,
+                );
             }
+            vblank.wait_for_vblank();
+            renderer.update_display(&game, &gfx, &mut bg, &mut overlay, &mut vram);
+            gfx.commit();
+            Logger::get().tick();
         }
+        println!("Back to the lobby");
+        renderer.clear();
+        gfx.commit();
     }
-    pub fn update_display(&mut self, gfx: &'a OamManaged) {
-        self.map.update_display(gfx);
-        for plr in self.players.iter_mut() {
-            plr.update_display(gfx);
+}
+
+/// Stops the game after losing the link, showing which player dropped (or
+/// nothing, if the link just went quiet).
+fn show_link_error(
+    error: TransferError,
+    bg: &mut MapLoan<'_, RegularMap>,
+    overlay: &mut MapLoan<'_, RegularMap>,
+    vram: &mut VRamManager,
+) -> ! {
+    warning!("Lost the link: {:?}", error);
+    match error {
+        TransferError::Disconnected(id) => {
+            show_stopped(Stopped::Disconnected(id), bg, overlay, vram)
+        }
+        _ => {
+            bg.clear(vram);
+            bg.commit(vram);
+            overlay.clear(vram);
+            overlay.commit(vram);
+            halt()
         }
     }
 }
+
+/// Why `show_stopped` stopped the game.
+enum Stopped {
+    /// This player dropped off the link.
+    Disconnected(PlayerId),
+    /// The consoles disagreed about the state at the start of this frame.
+    Desync(u32),
+}
+
+/// Replaces the game with the number that goes with `reason`, and stops
+/// there; the details are in the log.
+///
+/// A desync's frame goes between two bars right across the screen, so it
+/// can't be mistaken for the player number of a disconnect.
+fn show_stopped(
+    reason: Stopped,
+    bg: &mut MapLoan<'_, RegularMap>,
+    overlay: &mut MapLoan<'_, RegularMap>,
+    vram: &mut VRamManager,
+) -> ! {
+    bg.clear(vram);
+    bg.commit(vram);
+    overlay.clear(vram);
+    let (number, barred) = match reason {
+        Stopped::Disconnected(id) => (id as u32, false),
+        Stopped::Desync(frame) => (frame, true),
+    };
+    let digits = number.checked_ilog10().unwrap_or(0) as u16 + 1;
+    let width = digits * (graphics::DIGIT_WIDTH + 1) - 1;
+    let (x, y) = ((30 - width) / 2, (20 - graphics::DIGIT_HEIGHT) / 2);
+    graphics::draw_number(overlay, vram, (x, y), number);
+    if barred {
+        graphics::fill_row(overlay, vram, (0, y - 2), 30);
+        graphics::fill_row(overlay, vram, (0, y + graphics::DIGIT_HEIGHT + 1), 30);
+    }
+    overlay.commit(vram);
+    halt()
+}
+
+fn halt() -> ! {
+    let vblank = agb::interrupt::VBlank::get();
+    loop {
+        vblank.wait_for_vblank();
+        Logger::get().tick();
+    }
+}
+
 use serial::{
-    multiplayer::{MultiplayerSerial, PlayerId, TransferError, MULTIPLAYER_COUNTER},
+    multiboot::send_multiboot,
+    multiplayer::{MultiplayerSerial, PlayerId, TransferError},
     BaudRate, Serial,
 };
 
+/// The download play client from `scripts/build-client`, in builds with the
+/// `download-play` feature.
+#[cfg(feature = "download-play")]
+static DOWNLOAD_PLAY_CLIENT: Option<&Aligned<[u8]>> = Some(CLIENT_IMAGE);
+#[cfg(feature = "download-play")]
+const CLIENT_IMAGE: &Aligned<[u8]> = &Aligned(*include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/target/download-play/client.gba"
+)));
+#[cfg(not(feature = "download-play"))]
+static DOWNLOAD_PLAY_CLIENT: Option<&Aligned<[u8]>> = None;
+
+#[cfg(all(feature = "download-play", feature = "multiboot"))]
+compile_error!("The download play client can't host a download play itself");
+
+/// The BIOS copies the client out a word at a time.
+#[repr(C, align(4))]
+struct Aligned<T: ?Sized>(T);
+
+/// Hosts a download play: sends `client` to every linked GBA with no
+/// cartridge in, trying again each time A is pressed until someone gets it.
+///
+/// The children boot into the title screen, so we carry on to the lobby
+/// with them afterwards.
+fn host_download_play(serial: &mut Serial, btns: &mut ButtonController, client: &'static [u8]) {
+    let mut multiplayer_handle = MultiplayerSerial::new(serial, BaudRate::B115200).unwrap();
+    loop {
+        match send_multiboot(&mut multiplayer_handle, client) {
+            Ok(children) => {
+                println!("Sent the game to {:?}", children);
+                return;
+            }
+            Err(e) => warning!("Couldn't send the game: {:?}", e),
+        }
+        println!("Press A to try again.");
+        let vblank = agb::interrupt::VBlank::get();
+        loop {
+            vblank.wait_for_vblank();
+            btns.update();
+            Logger::get().tick();
+            if btns.is_just_pressed(Button::A) {
+                break;
+            }
+        }
+    }
+}
+
 #[allow(dead_code)]
 fn multiplayer_test_main(mut _gba: Gba) -> ! {
     agb::mgba::Mgba::new().expect("Should be in mgba");
@@ -193,7 +366,9 @@ fn multiplayer_test_main(mut _gba: Gba) -> ! {
     let mut multiplayer_handle = MultiplayerSerial::new(&mut serial, BaudRate::B9600).unwrap();
     multiplayer_handle.enable_buffer_interrupt();
     println!("Entered multiplayer mode");
-    multiplayer_handle.initialize_id().unwrap();
+    while let Err(e) = multiplayer_handle.initialize_id() {
+        warning!("Couldn't get our ID: {:?}", e);
+    }
     println!("We are {:?}", multiplayer_handle.id().unwrap());
 
     let _vblank_handle =
@@ -211,7 +386,9 @@ fn multiplayer_test_main(mut _gba: Gba) -> ! {
         multiplayer_handle.write_send_reg(n);
         multiplayer_handle.mark_ready();
 
-        while !multiplayer_handle.all_ready() {}
+        if let Err(e) = multiplayer_handle.wait_all_ready() {
+            panic!("{:?}", e);
+        }
         match multiplayer_handle.start_transfer() {
             Ok(()) => {}
             Err(TransferError::AlreadyInProgress) => {
@@ -224,7 +401,7 @@ fn multiplayer_test_main(mut _gba: Gba) -> ! {
         let mut msg = format!(
             "Current loop: {:03} (Counter: {:?})\n",
             loopcnt,
-            MULTIPLAYER_COUNTER.load(Ordering::Acquire)
+            multiplayer_handle.transfer_count()
         );
         for pid in PlayerId::ALL {
             write!(&mut msg, "  -  Player {}", pid as u8).ok();
@@ -0,0 +1,106 @@
+//! The title screen, where each player picks how they're getting into the
+//! lobby.
+use agb::display::tiled::{MapLoan, RegularMap, TiledMap, VRamManager};
+use agb::input::{Button, ButtonController};
+use agb::interrupt::VBlank;
+
+use crate::{
+    graphics::{draw_glyph, draw_number, fill_row, DIGIT_HEIGHT, DIGIT_WIDTH},
+    logs::{println, Logger},
+    serial::clock,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MenuEntry {
+    /// Straight to the lobby, with everyone playing off their own cartridge.
+    LinkMatch,
+    /// Sends the game to every linked GBA with no cartridge in first, then
+    /// joins them in the lobby.
+    HostDownloadPlay,
+}
+
+impl MenuEntry {
+    pub const fn label(self) -> &'static str {
+        match self {
+            MenuEntry::LinkMatch => "link match",
+            MenuEntry::HostDownloadPlay => "host a download play",
+        }
+    }
+    /// The picture drawn next to the entry's number, `ICON_WIDTH` tiles wide
+    /// and laid out like the digits.
+    const fn icon(self) -> [u8; DIGIT_HEIGHT as usize] {
+        match self {
+            // Two consoles and the cable between them.
+            MenuEntry::LinkMatch => [0b1100011, 0b1100011, 0b1111111, 0b1100011, 0b1100011],
+            // An arrow pointing down, for sending the game out.
+            MenuEntry::HostDownloadPlay => [0b0001000, 0b0001000, 0b0111110, 0b0011100, 0b0001000],
+        }
+    }
+}
+
+const ICON_WIDTH: u16 = 7;
+/// The width of an entry: its number, a gap, then its icon.
+const ENTRY_WIDTH: u16 = DIGIT_WIDTH + 2 + ICON_WIDTH;
+/// The tile column the entries start in.
+const ENTRY_COLUMN: u16 = (30 - ENTRY_WIDTH) / 2;
+
+/// Shows `entries` as a column of numbers and icons, with the selected one
+/// underlined, until A is pressed on one.
+///
+/// Also returns the clock's time when A went down, since how long it takes
+/// someone to choose is about as random as we can get; the clock's ticks are
+/// far finer than frames, so this isn't just a few hundred likely values.
+pub fn title_menu(
+    entries: &[MenuEntry],
+    btns: &mut ButtonController,
+    overlay: &mut MapLoan<'_, RegularMap>,
+    vram: &mut VRamManager,
+) -> (MenuEntry, u64) {
+    let vblank = VBlank::get();
+    clock::start();
+    let mut selected = 0usize;
+    let mut shown = None;
+    loop {
+        vblank.wait_for_vblank();
+        Logger::get().tick();
+        btns.update();
+        if btns.is_just_pressed(Button::A) {
+            break;
+        }
+        if btns.is_just_pressed(Button::UP) {
+            selected = selected.saturating_sub(1);
+        }
+        if btns.is_just_pressed(Button::DOWN) {
+            selected = (selected + 1).min(entries.len() - 1);
+        }
+        if shown == Some(selected) {
+            continue;
+        }
+        shown = Some(selected);
+        println!(
+            "Press A to choose {}: {}",
+            selected + 1,
+            entries[selected].label()
+        );
+        overlay.clear(vram);
+        for (idx, entry) in entries.iter().enumerate() {
+            let y = 2 + idx as u16 * (DIGIT_HEIGHT + 3);
+            draw_number(overlay, vram, (ENTRY_COLUMN, y), idx as u32 + 1);
+            let icon_x = ENTRY_COLUMN + ENTRY_WIDTH - ICON_WIDTH;
+            draw_glyph(overlay, vram, (icon_x, y), &entry.icon(), ICON_WIDTH);
+            if idx == selected {
+                fill_row(
+                    overlay,
+                    vram,
+                    (ENTRY_COLUMN, y + DIGIT_HEIGHT + 1),
+                    ENTRY_WIDTH,
+                );
+            }
+        }
+        overlay.commit(vram);
+    }
+    let entropy = clock::now() as u64;
+    overlay.clear(vram);
+    overlay.commit(vram);
+    (entries[selected], entropy)
+}
@@ -0,0 +1,107 @@
+use agb::display::{
+    object::{OamManaged, Object},
+    tiled::{MapLoan, RegularMap, VRamManager},
+};
+use alloc::vec::Vec;
+
+use crate::{
+    graphics::BulletSprites, map::MapRenderer, player_sprite, sim::Simulation, BulletTag, Hitbox,
+    PlayerTag,
+};
+
+/// Keeps the OAM objects on screen in sync with a `Simulation`.
+///
+/// Players and bullets are matched up with their objects by index, so objects
+/// get reused as bullets come and go rather than being tied to any one bullet.
+pub struct Renderer<'a> {
+    map: MapRenderer<'a>,
+    players: Vec<Option<Object<'a>>>,
+    bullets: Vec<Object<'a>>,
+    bullet_sprites: Option<BulletSprites>,
+    /// Whose sprite each player is drawn with, indexed by `PlayerTag`.
+    characters: [PlayerTag; 4],
+}
+
+impl Default for Renderer<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new() -> Self {
+        Self {
+            map: MapRenderer::new(),
+            players: Vec::new(),
+            bullets: Vec::new(),
+            bullet_sprites: None,
+            characters: [PlayerTag::P1, PlayerTag::P2, PlayerTag::P3, PlayerTag::P4],
+        }
+    }
+    /// Draws each player (and their bullets) as the player in `characters`,
+    /// indexed by `PlayerTag`.
+    pub fn set_characters(&mut self, characters: [PlayerTag; 4]) {
+        self.characters = characters;
+    }
+    /// Draws the arena onto `bg` and sets up objects for everything in `sim`.
+    pub fn init_display(
+        &mut self,
+        sim: &Simulation,
+        gfx: &'a OamManaged,
+        bg: &mut MapLoan<'_, RegularMap>,
+        vram: &mut VRamManager,
+    ) {
+        self.map.init_display(&sim.map, gfx, bg, vram);
+        self.players.clear();
+        self.bullets.clear();
+        self.update_display(sim, gfx);
+    }
+    pub fn update_display(&mut self, sim: &Simulation, gfx: &'a OamManaged) {
+        self.map.update_display(&sim.map, gfx);
+
+        self.players.resize_with(sim.players.len(), || None);
+        for (player, slot) in sim.players.iter().zip(self.players.iter_mut()) {
+            if !player.alive {
+                if let Some(obj) = slot.as_mut() {
+                    obj.hide();
+                }
+                continue;
+            }
+            let sprite = player_sprite(player, self.characters[player.tag as usize]);
+            let obj = slot.get_or_insert_with(|| gfx.object_sprite(sprite));
+            obj.set_sprite(gfx.sprite(sprite));
+            obj.set_position(player.pos().trunc())
+                .set_hflip(player.hflip())
+                .set_vflip(player.vflip())
+                .show();
+        }
+
+        let sprites = self.bullet_sprites.get_or_insert_with(BulletSprites::new);
+        // Dropping the extra objects frees their OAM slots.
+        self.bullets.truncate(sim.bullets.len());
+        for (idx, bullet) in sim.bullets.iter().enumerate() {
+            let tag = match bullet.tag {
+                BulletTag::NoPlayer => BulletTag::NoPlayer,
+                tag => self.characters[tag as usize - 1].bullet_tag(),
+            };
+            let sprite = sprites.get(tag, bullet.kind);
+            let obj = match self.bullets.get_mut(idx) {
+                Some(obj) => {
+                    obj.set_sprite(sprite);
+                    obj
+                }
+                None => {
+                    self.bullets.push(gfx.object(sprite));
+                    &mut self.bullets[idx]
+                }
+            };
+            obj.set_position(bullet.pos.trunc()).show();
+        }
+    }
+    /// Drops every object being displayed, freeing up their OAM slots.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.players.clear();
+        self.bullets.clear();
+    }
+}
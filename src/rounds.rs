@@ -0,0 +1,416 @@
+use agb::display::{
+    object::{OamManaged, Object},
+    tiled::{MapLoan, RegularMap, TiledMap, VRamManager},
+};
+use alloc::vec::Vec;
+
+use crate::{
+    graphics::{draw_number, DIGIT_HEIGHT, DIGIT_WIDTH},
+    lobby::DEFAULT_ROUNDS_TO_WIN,
+    logs::println,
+    map::{self, BaseMap, GenerationSettings},
+    netplay::{NetGame, MAX_ROLLBACK},
+    render::Renderer,
+    rng::Rng,
+    sim::Simulation,
+    sprite_tag, ControlsRepr, PlayerTag,
+};
+
+/// The settings for a match, fixed when the match is created.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct MatchRules {
+    /// The number of round wins a player needs to win the match.
+    pub rounds_to_win: u8,
+    /// How many frames a round can last before it ends in a draw.
+    pub time_limit: Option<u32>,
+    /// The team of each player, indexed by `PlayerTag`. A round ends once all
+    /// the players left standing are on the same team.
+    pub teams: [u8; 4],
+    /// How many players are in the match, starting from `PlayerTag::P1`.
+    pub num_players: u8,
+    /// Whose sprite and colours each player is drawn with, indexed by
+    /// `PlayerTag`; this doesn't affect the game itself.
+    pub characters: [PlayerTag; 4],
+    /// What `map::generate` puts in each round's arena.
+    pub generation: GenerationSettings,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        Self {
+            rounds_to_win: DEFAULT_ROUNDS_TO_WIN,
+            time_limit: Some(90 * 60),
+            teams: [0, 1, 2, 3],
+            num_players: 4,
+            characters: [PlayerTag::P1, PlayerTag::P2, PlayerTag::P3, PlayerTag::P4],
+            generation: GenerationSettings::DEFAULT,
+        }
+    }
+}
+
+impl MatchRules {
+    pub const fn team_of(&self, player: PlayerTag) -> u8 {
+        self.teams[player as usize]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum RoundResult {
+    /// Everyone left standing was on this team.
+    Winner(u8),
+    /// Either nobody was left or the time limit ran out.
+    Draw,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MatchPhase {
+    /// Players are frozen while the countdown runs.
+    Countdown {
+        frames_left: u16,
+    },
+    Playing {
+        frames: u32,
+    },
+    /// The round is decided; wait a bit before moving on so players can see
+    /// what happened.
+    RoundOver {
+        result: RoundResult,
+        frames_left: u16,
+    },
+    /// The match is over and the scores are on screen; once `frames_left` runs
+    /// out, anyone pressing A moves on.
+    Results {
+        frames_left: u16,
+    },
+    /// Someone pressed A on the results, which stay up while `frames_left`
+    /// runs out.
+    Leaving {
+        frames_left: u16,
+    },
+    /// Done with; whoever's running the match should move on.
+    Finished,
+}
+
+/// A full match: a series of rounds, each on a freshly generated arena, played
+/// until someone reaches `MatchRules::rounds_to_win`.
+///
+/// Like `Simulation`, this is plain data; `MatchRenderer` draws it.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Match {
+    pub rules: MatchRules,
+    pub sim: Simulation,
+    pub scores: [u8; 4],
+    pub round: u8,
+    pub phase: MatchPhase,
+    seed: u64,
+}
+
+impl Match {
+    /// Frames the pre-round countdown lasts for; 1 second per number.
+    pub const COUNTDOWN_FRAMES: u16 = 3 * 60;
+    /// Frames to linger on a finished round before starting the next one.
+    pub const ROUND_OVER_FRAMES: u16 = 2 * 60;
+    /// Frames the results stay up before they can be skipped, so nobody
+    /// still mashing A from the last round skips them by accident.
+    pub const RESULTS_FRAMES: u16 = 60;
+    /// Frames between someone pressing A on the results and the match
+    /// finishing. Netplay can guess at inputs up to `MAX_ROLLBACK` frames
+    /// ahead, so this has to be longer for the press to be certain by then.
+    pub const LEAVING_FRAMES: u16 = 30;
+
+    pub fn new(rules: MatchRules, seed: u64) -> Self {
+        let map = Self::round_map(&rules, seed, 0);
+        let sim = Simulation::new(map, rules.num_players as usize);
+        Self {
+            rules,
+            sim,
+            scores: [0; 4],
+            round: 0,
+            phase: MatchPhase::Countdown {
+                frames_left: Self::COUNTDOWN_FRAMES,
+            },
+            seed,
+        }
+    }
+
+    fn round_map(rules: &MatchRules, seed: u64, round: u8) -> BaseMap {
+        // Step the match seed once per round so every round gets a different
+        // arena; `| 1` keeps the xorshift state from getting stuck at 0.
+        let mut rng = Rng::with_seed(seed | 1);
+        let mut round_seed = 0;
+        for _ in 0..=round {
+            (rng, round_seed) = rng.next_u64_const();
+        }
+        map::generate(round_seed, map::pick_base(round_seed), rules.generation)
+    }
+
+    pub fn is_over(&self) -> bool {
+        matches!(
+            self.phase,
+            MatchPhase::Results { .. } | MatchPhase::Leaving { .. } | MatchPhase::Finished
+        )
+    }
+
+    /// Whether someone's moved on from the results.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.phase, MatchPhase::Finished)
+    }
+
+    fn round_result(&self) -> Option<RoundResult> {
+        let mut alive_team = None;
+        for player in self.sim.players.iter().filter(|p| p.alive) {
+            let team = self.rules.team_of(player.tag);
+            match alive_team {
+                None => alive_team = Some(team),
+                Some(other) if other != team => return None,
+                Some(_) => {}
+            }
+        }
+        Some(alive_team.map_or(RoundResult::Draw, RoundResult::Winner))
+    }
+
+    fn finish_round(&mut self, result: RoundResult) {
+        println!("Round {} over: {:?}", self.round, result);
+        if let RoundResult::Winner(team) = result {
+            for player in self.sim.players.iter() {
+                if self.rules.team_of(player.tag) == team {
+                    self.scores[player.tag as usize] += 1;
+                }
+            }
+        }
+        self.phase = MatchPhase::RoundOver {
+            result,
+            frames_left: Self::ROUND_OVER_FRAMES,
+        };
+    }
+
+    fn next_round(&mut self) {
+        let match_over = self
+            .scores
+            .iter()
+            .any(|score| *score >= self.rules.rounds_to_win);
+        if match_over {
+            println!("Match over: {:?}", self.scores);
+            self.phase = MatchPhase::Results {
+                frames_left: Self::RESULTS_FRAMES,
+            };
+        } else {
+            self.round += 1;
+            let map = Self::round_map(&self.rules, self.seed, self.round);
+            self.sim = Simulation::new(map, self.rules.num_players as usize);
+            self.phase = MatchPhase::Countdown {
+                frames_left: Self::COUNTDOWN_FRAMES,
+            };
+        }
+    }
+
+    /// Steps the match by one frame, with `inputs` indexed by `PlayerTag`.
+    pub fn step(&mut self, inputs: &[ControlsRepr; 4]) {
+        match self.phase {
+            MatchPhase::Countdown { frames_left } => {
+                let frames_left = frames_left.saturating_sub(1);
+                self.phase = if frames_left == 0 {
+                    MatchPhase::Playing { frames: 0 }
+                } else {
+                    MatchPhase::Countdown { frames_left }
+                };
+            }
+            MatchPhase::Playing { frames } => {
+                self.sim.step(inputs);
+                let frames = frames + 1;
+                self.phase = MatchPhase::Playing { frames };
+                if let Some(result) = self.round_result() {
+                    self.finish_round(result);
+                } else if self.rules.time_limit.is_some_and(|limit| frames >= limit) {
+                    self.finish_round(RoundResult::Draw);
+                }
+            }
+            MatchPhase::RoundOver {
+                result,
+                frames_left,
+            } => {
+                let frames_left = frames_left.saturating_sub(1);
+                if frames_left == 0 {
+                    self.next_round();
+                } else {
+                    self.phase = MatchPhase::RoundOver {
+                        result,
+                        frames_left,
+                    };
+                }
+            }
+            MatchPhase::Results { frames_left } => {
+                let pressed = inputs
+                    .iter()
+                    .any(|input| input.fired_bullet || input.fired_shield);
+                self.phase = if frames_left > 0 {
+                    MatchPhase::Results {
+                        frames_left: frames_left - 1,
+                    }
+                } else if pressed {
+                    MatchPhase::Leaving {
+                        frames_left: Self::LEAVING_FRAMES,
+                    }
+                } else {
+                    MatchPhase::Results { frames_left }
+                };
+            }
+            MatchPhase::Leaving { frames_left } => {
+                let frames_left = frames_left.saturating_sub(1);
+                self.phase = if frames_left == 0 {
+                    MatchPhase::Finished
+                } else {
+                    MatchPhase::Leaving { frames_left }
+                };
+            }
+            MatchPhase::Finished => {}
+        }
+    }
+}
+
+const _: () = assert!(Match::LEAVING_FRAMES as u32 > MAX_ROLLBACK);
+
+/// What the overlay is showing, so it only gets redrawn when that changes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Overlay {
+    Countdown(u32),
+    Blank,
+    Results,
+}
+
+impl Overlay {
+    const fn of(phase: MatchPhase) -> Self {
+        match phase {
+            MatchPhase::Countdown { frames_left } => {
+                Overlay::Countdown((frames_left as u32 + 59) / 60)
+            }
+            MatchPhase::Playing { .. } | MatchPhase::RoundOver { .. } => Overlay::Blank,
+            MatchPhase::Results { .. } | MatchPhase::Leaving { .. } | MatchPhase::Finished => {
+                Overlay::Results
+            }
+        }
+    }
+}
+
+/// Draws a `Match`: the current round's arena and objects, plus an overlay
+/// with the countdown and final scores.
+///
+/// Since the match itself doesn't track what's changed, this remembers what it
+/// last drew and compares against that; that way it also copes with the match
+/// jumping around, like when netplay rolls back.
+#[derive(Default)]
+pub struct MatchRenderer<'a> {
+    sim: Renderer<'a>,
+    results: Vec<Object<'a>>,
+    /// The round and overlay last drawn, if anything has been.
+    shown: Option<(u8, Overlay)>,
+}
+
+impl<'a> MatchRenderer<'a> {
+    pub fn new() -> Self {
+        Self {
+            sim: Renderer::new(),
+            results: Vec::new(),
+            shown: None,
+        }
+    }
+
+    pub fn init_display(
+        &mut self,
+        game: &Match,
+        gfx: &'a OamManaged,
+        bg: &mut MapLoan<'_, RegularMap>,
+        overlay: &mut MapLoan<'_, RegularMap>,
+        vram: &mut VRamManager,
+    ) {
+        self.shown = None;
+        self.update_display(game, gfx, bg, overlay, vram);
+    }
+
+    /// Syncs the display with the match; `bg` holds the arena and `overlay`
+    /// holds the countdown and scores on top of it.
+    pub fn update_display(
+        &mut self,
+        game: &Match,
+        gfx: &'a OamManaged,
+        bg: &mut MapLoan<'_, RegularMap>,
+        overlay: &mut MapLoan<'_, RegularMap>,
+        vram: &mut VRamManager,
+    ) {
+        self.sim.set_characters(game.rules.characters);
+        let next = (game.round, Overlay::of(game.phase));
+        if self.shown == Some(next) {
+            if !game.is_over() {
+                self.sim.update_display(&game.sim, gfx);
+            }
+            return;
+        }
+        // The results screen clears the arena, so it needs a full redraw
+        // after that too.
+        let arena_dirty = self.shown.map_or(true, |(round, shown)| {
+            round != game.round || shown == Overlay::Results
+        });
+        self.shown = Some(next);
+        overlay.clear(vram);
+        match next.1 {
+            Overlay::Results => {
+                self.sim.clear();
+                bg.clear(vram);
+                bg.commit(vram);
+                self.draw_results(game, gfx, overlay, vram);
+            }
+            Overlay::Countdown(_) | Overlay::Blank => {
+                self.results.clear();
+                if arena_dirty {
+                    bg.clear(vram);
+                    self.sim.init_display(&game.sim, gfx, bg, vram);
+                    bg.commit(vram);
+                }
+                if let Overlay::Countdown(seconds_left) = next.1 {
+                    let x = (30 - DIGIT_WIDTH) / 2;
+                    let y = (20 - DIGIT_HEIGHT) / 2;
+                    draw_number(overlay, vram, (x, y), seconds_left);
+                }
+                self.sim.update_display(&game.sim, gfx);
+            }
+        }
+        overlay.commit(vram);
+    }
+
+    /// Drops every object being displayed, freeing up their OAM slots.
+    pub fn clear(&mut self) {
+        self.sim.clear();
+        self.results.clear();
+        self.shown = None;
+    }
+
+    /// Draws each player's sprite next to their final score.
+    fn draw_results(
+        &mut self,
+        game: &Match,
+        gfx: &'a OamManaged,
+        overlay: &mut MapLoan<'_, RegularMap>,
+        vram: &mut VRamManager,
+    ) {
+        self.results.clear();
+        for (idx, player) in game.sim.players.iter().enumerate() {
+            let row_y = idx as u16 * DIGIT_HEIGHT;
+            let character = game.rules.characters[player.tag as usize];
+            let mut obj = gfx.object_sprite(sprite_tag(character).sprite(1));
+            obj.set_position((8 * 8, (row_y as i32 + 1) * 8)).show();
+            self.results.push(obj);
+            draw_number(
+                overlay,
+                vram,
+                (12, row_y),
+                game.scores[player.tag as usize] as u32,
+            );
+        }
+    }
+}
+
+impl NetGame for Match {
+    fn step(&mut self, inputs: &[ControlsRepr; 4]) {
+        Match::step(self, inputs)
+    }
+}
@@ -0,0 +1,320 @@
+//! I2C and SPI masters bit-banged over the link port in GPIO mode, for
+//! talking to small peripherals wired straight to the cable.
+//!
+//! Timing comes from `clock`, so the fastest either can go is half its tick
+//! rate; in practice register access keeps things slower than that.
+use super::clock::{self, Deadline, TICKS_PER_SECOND};
+use super::generalpurpose::{GeneralPurpose, GpioConfig, GpioDirection, PinState};
+use super::multiplayer::DEFAULT_TIMEOUT_MILLIS;
+use super::{Pin, RcntWrapper};
+
+pub use speglar_core::link::bitbang::SpiMode;
+
+/// The fastest clock rate either master accepts.
+pub const MAX_RATE_HZ: u32 = TICKS_PER_SECOND / 2;
+
+/// How many ticks each half of a clock cycle at `hz` lasts, rounded up so we
+/// never run faster than asked.
+fn half_period(hz: u32) -> Option<u32> {
+    if hz == 0 || hz > MAX_RATE_HZ {
+        return None;
+    }
+    Some(TICKS_PER_SECOND.div_ceil(hz * 2))
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum I2cError {
+    /// The clock rate was zero or above `MAX_RATE_HZ`.
+    UnsupportedRate,
+    /// Something was holding SDA low when we went to start.
+    BusBusy,
+    /// Nobody acked the address.
+    AddressNack,
+    /// The device stopped acking partway through a write, after this many
+    /// bytes.
+    DataNack(usize),
+    /// A device held SCL low for longer than the timeout.
+    Timeout,
+}
+
+/// An I2C master, with SC as SCL and SD as SDA.
+///
+/// Both lines are driven open-drain by switching them between a low output and
+/// an input, so they need pull-up resistors. Devices may stretch the clock for
+/// up to `timeout_millis`.
+pub struct I2cMaster<'a> {
+    gpio: GeneralPurpose<'a>,
+    half_period: u32,
+    timeout_millis: u32,
+}
+
+const SCL: Pin = Pin::SC;
+const SDA: Pin = Pin::SD;
+
+impl<'a> I2cMaster<'a> {
+    pub fn new(gpio: GeneralPurpose<'a>, rate_hz: u32) -> Result<Self, I2cError> {
+        let half_period = half_period(rate_hz).ok_or(I2cError::UnsupportedRate)?;
+        clock::start();
+        // Both released.
+        gpio.set_gpio_config(GpioConfig::default());
+        Ok(Self {
+            gpio,
+            half_period,
+            timeout_millis: DEFAULT_TIMEOUT_MILLIS,
+        })
+    }
+    pub fn set_rate(&mut self, rate_hz: u32) -> Result<(), I2cError> {
+        self.half_period = half_period(rate_hz).ok_or(I2cError::UnsupportedRate)?;
+        Ok(())
+    }
+    /// Sets how long a device can stretch the clock before we give up.
+    pub fn set_timeout_millis(&mut self, millis: u32) {
+        self.timeout_millis = millis;
+    }
+    pub fn into_inner(self) -> GeneralPurpose<'a> {
+        self.gpio
+    }
+
+    /// Writes all of `data` to the device at `address`.
+    pub fn write(&mut self, address: u8, data: &[u8]) -> Result<(), I2cError> {
+        self.transaction(|bus| {
+            bus.send_address(address, false)?;
+            bus.write_bytes(data)
+        })
+    }
+    /// Fills `buf` from the device at `address`.
+    pub fn read(&mut self, address: u8, buf: &mut [u8]) -> Result<(), I2cError> {
+        self.transaction(|bus| {
+            bus.send_address(address, true)?;
+            bus.read_bytes(buf)
+        })
+    }
+    /// Writes `data` and then reads into `buf` with a repeated start in
+    /// between, the usual way to read a device's registers.
+    pub fn write_read(&mut self, address: u8, data: &[u8], buf: &mut [u8]) -> Result<(), I2cError> {
+        self.transaction(|bus| {
+            bus.send_address(address, false)?;
+            bus.write_bytes(data)?;
+            bus.start()?;
+            bus.send_address(address, true)?;
+            bus.read_bytes(buf)
+        })
+    }
+
+    /// Sends a start and runs `f`, then sends a stop whether it worked or not.
+    ///
+    /// If the start fails we never had the bus, so there's nothing to stop.
+    fn transaction(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<(), I2cError>,
+    ) -> Result<(), I2cError> {
+        self.start()?;
+        let retvl = f(self);
+        let stopped = self.stop();
+        retvl.and(stopped)
+    }
+
+    fn delay(&self) {
+        clock::wait_ticks(self.half_period);
+    }
+    fn pull_low(&self, pin: Pin) {
+        // Reading a released line gives its level, which any read-modify-write
+        // of RCNT would latch as the pin's output; so clear the output and make
+        // it an output in one go.
+        let rcnt = RcntWrapper::get();
+        let old = rcnt.read();
+        let bit = pin as u16;
+        rcnt.write((old & !(1 << bit)) | (1 << (bit + 4)));
+    }
+    fn release(&self, pin: Pin) {
+        self.gpio.set_direction(pin, GpioDirection::Input);
+    }
+    /// Lets SCL go high, waiting out any clock stretching.
+    fn release_scl(&self) -> Result<(), I2cError> {
+        self.release(SCL);
+        let deadline = Deadline::after_millis(self.timeout_millis);
+        while !self.gpio.read_pin(SCL) {
+            if deadline.expired() {
+                return Err(I2cError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a start, or a repeated start if we're mid-transaction.
+    fn start(&mut self) -> Result<(), I2cError> {
+        self.release(SDA);
+        self.release_scl()?;
+        self.delay();
+        if !self.gpio.read_pin(SDA) {
+            return Err(I2cError::BusBusy);
+        }
+        self.pull_low(SDA);
+        self.delay();
+        self.pull_low(SCL);
+        Ok(())
+    }
+    fn stop(&mut self) -> Result<(), I2cError> {
+        self.pull_low(SDA);
+        self.delay();
+        let released = self.release_scl();
+        self.delay();
+        self.release(SDA);
+        self.delay();
+        released
+    }
+
+    fn write_bit(&mut self, bit: bool) -> Result<(), I2cError> {
+        if bit {
+            self.release(SDA);
+        } else {
+            self.pull_low(SDA);
+        }
+        self.delay();
+        self.release_scl()?;
+        self.delay();
+        self.pull_low(SCL);
+        Ok(())
+    }
+    fn read_bit(&mut self) -> Result<bool, I2cError> {
+        self.release(SDA);
+        self.delay();
+        self.release_scl()?;
+        self.delay();
+        let bit = self.gpio.read_pin(SDA);
+        self.pull_low(SCL);
+        Ok(bit)
+    }
+    /// Sends `byte`, returning whether it was acked.
+    fn write_byte(&mut self, byte: u8) -> Result<bool, I2cError> {
+        for idx in (0..8).rev() {
+            self.write_bit(byte & (1 << idx) != 0)?;
+        }
+        Ok(!self.read_bit()?)
+    }
+    fn read_byte(&mut self, ack: bool) -> Result<u8, I2cError> {
+        let mut byte = 0;
+        for _ in 0..8 {
+            byte = (byte << 1) | self.read_bit()? as u8;
+        }
+        self.write_bit(!ack)?;
+        Ok(byte)
+    }
+
+    fn send_address(&mut self, address: u8, read: bool) -> Result<(), I2cError> {
+        if self.write_byte((address << 1) | read as u8)? {
+            Ok(())
+        } else {
+            Err(I2cError::AddressNack)
+        }
+    }
+    fn write_bytes(&mut self, data: &[u8]) -> Result<(), I2cError> {
+        for (idx, byte) in data.iter().enumerate() {
+            if !self.write_byte(*byte)? {
+                return Err(I2cError::DataNack(idx));
+            }
+        }
+        Ok(())
+    }
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), I2cError> {
+        let len = buf.len();
+        for (idx, slot) in buf.iter_mut().enumerate() {
+            // Nack the last byte to tell the device we're done.
+            *slot = self.read_byte(idx + 1 < len)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SpiError {
+    /// The clock rate was zero or above `MAX_RATE_HZ`.
+    UnsupportedRate,
+}
+
+/// An SPI master sending MSB first, with SC as the clock, SO as MOSI, SI as
+/// MISO and SD as an active-low chip select.
+pub struct SpiMaster<'a> {
+    gpio: GeneralPurpose<'a>,
+    half_period: u32,
+    mode: SpiMode,
+}
+
+const SCK: Pin = Pin::SC;
+const MOSI: Pin = Pin::SO;
+const MISO: Pin = Pin::SI;
+const CS: Pin = Pin::SD;
+
+impl<'a> SpiMaster<'a> {
+    pub fn new(gpio: GeneralPurpose<'a>, rate_hz: u32, mode: SpiMode) -> Result<Self, SpiError> {
+        let half_period = half_period(rate_hz).ok_or(SpiError::UnsupportedRate)?;
+        clock::start();
+        gpio.set_gpio_config(GpioConfig {
+            sc: GpioDirection::Output,
+            sd: GpioDirection::Output,
+            si: GpioDirection::Input,
+            so: GpioDirection::Output,
+        });
+        gpio.write_pins(PinState::default().with_sc(mode.idles_high()).with_sd(true));
+        Ok(Self {
+            gpio,
+            half_period,
+            mode,
+        })
+    }
+    pub fn set_rate(&mut self, rate_hz: u32) -> Result<(), SpiError> {
+        self.half_period = half_period(rate_hz).ok_or(SpiError::UnsupportedRate)?;
+        Ok(())
+    }
+    pub fn into_inner(self) -> GeneralPurpose<'a> {
+        self.gpio
+    }
+
+    /// Pulls chip select low, so the device starts listening.
+    pub fn select(&mut self) {
+        self.gpio.write_pin(CS, false);
+        clock::wait_ticks(self.half_period);
+    }
+    pub fn deselect(&mut self) {
+        clock::wait_ticks(self.half_period);
+        self.gpio.write_pin(CS, true);
+    }
+
+    /// Shifts `byte` out while shifting the device's reply in.
+    pub fn transfer_byte(&mut self, byte: u8) -> u8 {
+        let idle = self.mode.idles_high();
+        let mut received = 0;
+        for idx in (0..8).rev() {
+            let out = byte & (1 << idx) != 0;
+            // The first edge of each cycle takes the clock away from idle; in
+            // the late-sampling modes that's when the next bit goes out.
+            if !self.mode.samples_late() {
+                self.gpio.write_pin(MOSI, out);
+            }
+            clock::wait_ticks(self.half_period);
+            self.gpio.write_pin(SCK, !idle);
+            if self.mode.samples_late() {
+                self.gpio.write_pin(MOSI, out);
+            } else {
+                received = (received << 1) | self.gpio.read_pin(MISO) as u8;
+            }
+            clock::wait_ticks(self.half_period);
+            self.gpio.write_pin(SCK, idle);
+            if self.mode.samples_late() {
+                received = (received << 1) | self.gpio.read_pin(MISO) as u8;
+            }
+        }
+        received
+    }
+    /// Sends each byte of `buf`, replacing it with what came back.
+    pub fn transfer(&mut self, buf: &mut [u8]) {
+        for byte in buf {
+            *byte = self.transfer_byte(*byte);
+        }
+    }
+    pub fn write(&mut self, data: &[u8]) {
+        for byte in data {
+            self.transfer_byte(*byte);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! The free-running clock from `speglar_core::link::clock` on the real timers,
+//! plus deadlines for blocking operations.
+use speglar_core::link::clock;
+pub use speglar_core::link::clock::{millis_to_ticks, TICKS_PER_SECOND};
+
+use super::Hardware;
+
+pub type Clock = clock::Clock<Hardware>;
+
+/// Starts the clock if it isn't already running.
+pub fn start() {
+    Clock::new().start()
+}
+
+/// The current time in ticks.
+pub fn now() -> u32 {
+    Clock::new().now()
+}
+
+/// Busy-waits for at least `ticks` ticks; the clock needs to be running.
+pub fn wait_ticks(ticks: u32) {
+    Clock::new().wait_ticks(ticks)
+}
+
+/// A point in time for a blocking operation to give up at.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Deadline {
+    end: u32,
+}
+
+impl Deadline {
+    pub fn after_millis(millis: u32) -> Self {
+        Self::after_ticks(millis_to_ticks(millis))
+    }
+    pub fn after_ticks(ticks: u32) -> Self {
+        start();
+        Self {
+            end: now().wrapping_add(ticks),
+        }
+    }
+    pub fn expired(&self) -> bool {
+        now().wrapping_sub(self.end) as i32 >= 0
+    }
+}
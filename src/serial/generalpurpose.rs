@@ -1,7 +1,12 @@
 use core::marker::PhantomData;
 
+use agb::external::portable_atomic::{AtomicU16, Ordering};
+use agb::interrupt::{add_interrupt_handler, Interrupt, InterruptHandler};
+use speglar_core::link::spsc::SpscQueue;
+
 use crate::utils::{read_bit_u8, write_bit_u8};
 
+use super::clock::{self, Deadline};
 use super::*;
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
@@ -31,8 +36,18 @@ impl GpioDirection {
     }
 }
 
+/// A falling edge on SI, seen by the serial interrupt.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct SiEdge {
+    /// When it happened, in `clock` ticks.
+    pub timestamp: u32,
+    /// All four pins as the interrupt found them.
+    pub pins: PinState,
+}
+
 pub struct GeneralPurpose<'a> {
     _handle: PhantomData<&'a mut Serial>,
+    edge_interrupt: Option<InterruptHandler>,
 }
 
 impl<'a> GeneralPurpose<'a> {
@@ -40,11 +55,13 @@ impl<'a> GeneralPurpose<'a> {
         RcntWrapper::get().set_mode(SerialMode::Gpio);
         Self {
             _handle: PhantomData,
+            edge_interrupt: None,
         }
     }
     pub fn from_handle<'b: 'a>(_handle: &'a mut PhantomData<&'b mut Serial>) -> Self {
         Self {
             _handle: PhantomData,
+            edge_interrupt: None,
         }
     }
     pub fn gpio_config(&self) -> GpioConfig {
@@ -60,9 +77,54 @@ impl<'a> GeneralPurpose<'a> {
     pub fn interupt_enabled(&self) -> bool {
         RcntWrapper::get().si_interrupt_enabled()
     }
+    /// Only sets the RCNT bit; use `enable_edge_events` to actually handle
+    /// the interrupts.
     pub fn set_interrupt(&self, interupt: bool) {
         RcntWrapper::get().enable_si_interrupt(interupt)
     }
+
+    /// Starts queueing up an `SiEdge` every time SI goes from high to low,
+    /// dropping anything already queued.
+    pub fn enable_edge_events(&mut self) {
+        clock::start();
+        EDGE_QUEUE.clear();
+        DROPPED_EDGES.store(0, Ordering::Release);
+        self.edge_interrupt =
+            Some(unsafe { add_interrupt_handler(Interrupt::Serial, |_| edge_interrupt()) });
+        self.set_interrupt(true);
+    }
+    pub fn disable_edge_events(&mut self) {
+        self.set_interrupt(false);
+        self.edge_interrupt = None;
+    }
+    pub fn edge_events_enabled(&self) -> bool {
+        self.edge_interrupt.is_some()
+    }
+    /// Pops the oldest edge that's been queued up.
+    pub fn next_edge(&mut self) -> Option<SiEdge> {
+        let (timestamp, state) = EDGE_QUEUE.pop()?;
+        Some(SiEdge {
+            timestamp,
+            pins: PinState { state },
+        })
+    }
+    /// Waits for the next edge, for up to `timeout_millis`.
+    pub fn wait_edge(&mut self, timeout_millis: u32) -> Option<SiEdge> {
+        let deadline = Deadline::after_millis(timeout_millis);
+        loop {
+            if let Some(edge) = self.next_edge() {
+                return Some(edge);
+            }
+            if deadline.expired() {
+                return None;
+            }
+        }
+    }
+    /// How many edges were dropped because the queue was full since the last
+    /// call.
+    pub fn take_dropped_edges(&mut self) -> u16 {
+        DROPPED_EDGES.swap(0, Ordering::AcqRel)
+    }
     pub fn pins(&self) -> PinState {
         PinState::from_rcnt(RcntWrapper::get().read())
     }
@@ -77,6 +139,9 @@ impl<'a> GeneralPurpose<'a> {
     pub fn read_pin(&self, pin: Pin) -> bool {
         RcntWrapper::get().read_bit(pin as u8)
     }
+    pub fn set_direction(&self, pin: Pin, direction: GpioDirection) {
+        RcntWrapper::get().write_bit(pin as u8 + 4, direction.is_output())
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
@@ -175,3 +240,26 @@ impl GpioState {
         self.pins.into_rcnt() | self.config.into_rcnt()
     }
 }
+
+impl Drop for GeneralPurpose<'_> {
+    fn drop(&mut self) {
+        if self.edge_events_enabled() {
+            self.set_interrupt(false);
+        }
+    }
+}
+
+const EDGE_QUEUE_LEN: usize = 32;
+/// Every SI edge, as its timestamp and pins, written by the interrupt and read
+/// by `GeneralPurpose::next_edge`.
+static EDGE_QUEUE: SpscQueue<(u32, u8), EDGE_QUEUE_LEN> = SpscQueue::new();
+static DROPPED_EDGES: AtomicU16 = AtomicU16::new(0);
+
+fn edge_interrupt() {
+    let timestamp = clock::now();
+    let pins = PinState::from_rcnt(RcntWrapper::get().read());
+    if !EDGE_QUEUE.push((timestamp, pins.state)) {
+        let dropped = DROPPED_EDGES.load(Ordering::Relaxed);
+        DROPPED_EDGES.store(dropped.saturating_add(1), Ordering::Release);
+    }
+}
@@ -0,0 +1,54 @@
+//! The `RegisterBackend` for the GBA's actual registers.
+//!
+//! `speglar-core` only ever names registers by address, so this maps each
+//! address it can ask for onto a `VolAddress` and refuses anything else.
+use speglar_core::link::{
+    clock::{TM2CNT_H, TM2CNT_L, TM3CNT_H, TM3CNT_L},
+    RegisterBackend, RCNT, SIOCNT, SIOMLT_SEND, SIOMULTI0,
+};
+use voladdress::{Safe, VolAddress, VolBlock};
+
+use super::joybus::{JOYCNT, JOYSTAT};
+
+type Reg = VolAddress<u16, Safe, Safe>;
+
+const SIOMULTI: VolBlock<u16, Safe, Safe, 4> = unsafe { VolBlock::new(SIOMULTI0) };
+const SIOMULTI3: usize = SIOMULTI0 + 2 * 3;
+const JOYCNT_ADDR: usize = JOYCNT.as_usize();
+const JOYSTAT_ADDR: usize = JOYSTAT.as_usize();
+
+/// The GBA's registers, for the parts of `speglar-core` that drive the link
+/// port and timers.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Hardware;
+
+impl Hardware {
+    /// The register at `addr`.
+    ///
+    /// # Panics
+    /// If `addr` isn't one of the registers `speglar-core` uses.
+    fn register(addr: usize) -> Reg {
+        match addr {
+            SIOMULTI0..=SIOMULTI3 if addr % 2 == 0 => SIOMULTI.index((addr - SIOMULTI0) / 2),
+            SIOCNT => unsafe { Reg::new(SIOCNT) },
+            SIOMLT_SEND => unsafe { Reg::new(SIOMLT_SEND) },
+            RCNT => unsafe { Reg::new(RCNT) },
+            JOYCNT_ADDR => JOYCNT,
+            JOYSTAT_ADDR => JOYSTAT,
+            TM2CNT_L => unsafe { Reg::new(TM2CNT_L) },
+            TM2CNT_H => unsafe { Reg::new(TM2CNT_H) },
+            TM3CNT_L => unsafe { Reg::new(TM3CNT_L) },
+            TM3CNT_H => unsafe { Reg::new(TM3CNT_H) },
+            _ => panic!("No register at {:#x}", addr),
+        }
+    }
+}
+
+impl RegisterBackend for Hardware {
+    fn read(&self, addr: usize) -> u16 {
+        Self::register(addr).read()
+    }
+    fn write(&self, addr: usize, value: u16) {
+        Self::register(addr).write(value)
+    }
+}
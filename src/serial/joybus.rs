@@ -0,0 +1,194 @@
+use core::marker::PhantomData;
+
+use agb::external::portable_atomic::{AtomicBool, AtomicU16, Ordering};
+use agb::interrupt::{add_interrupt_handler, Interrupt, InterruptHandler};
+use speglar_core::link::joybus::{self, ALL_FLAGS, RECEIVE_FLAG, RESET_FLAG, SEND_FLAG};
+use speglar_core::link::spsc::SpscQueue;
+
+use super::clock::Deadline;
+use super::multiplayer::DEFAULT_TIMEOUT_MILLIS;
+use super::*;
+
+pub type JoycntWrapper = joybus::JoycntWrapper<Hardware>;
+pub type JoystatWrapper = joybus::JoystatWrapper<Hardware>;
+
+pub(super) const JOYCNT: VolAddress<u16, Safe, Safe> = unsafe { VolAddress::new(joybus::JOYCNT) };
+const JOY_RECV: VolAddress<u32, Safe, Safe> = unsafe { VolAddress::new(0x4000150) };
+const JOY_TRANS: VolAddress<u32, Safe, Safe> = unsafe { VolAddress::new(0x4000154) };
+/// Only the low half of JOYSTAT has anything in it.
+pub(super) const JOYSTAT: VolAddress<u16, Safe, Safe> = unsafe { VolAddress::new(joybus::JOYSTAT) };
+
+/// Something the host (e.g. a GameCube) did over the link.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum JoybusEvent {
+    /// The host sent a Device Reset (`0xFF`) command.
+    Reset,
+    /// The host wrote to us (command `0x15`).
+    Received(u32),
+    /// The host read what we'd put in `JOY_TRANS` (command `0x14`).
+    Sent,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum JoybusError {
+    /// The host hasn't read the last value we sent yet.
+    SendPending,
+    /// The host wrote faster than we read and some values were dropped.
+    Overflow,
+    /// A blocking operation didn't finish within the timeout.
+    Timeout,
+}
+
+/// Acts as a JOY BUS device, with the host driving every transfer.
+///
+/// The hardware answers the host's Reset and Status commands by itself; all
+/// that's left to us is the data it reads and writes, and noticing when that
+/// happens.
+pub struct JoybusSerial<'a> {
+    _handle: PhantomData<&'a mut Serial>,
+    interrupt: Option<InterruptHandler>,
+    timeout_millis: u32,
+}
+
+impl<'a> JoybusSerial<'a> {
+    pub fn new(_handle: &'a mut Serial) -> Self {
+        // FROM https://rust-console.github.io/gbatek-gbaonly/#siojoybusmode:
+        RcntWrapper::get().set_mode(SerialMode::Joybus);
+        let joycnt = JoycntWrapper::get();
+        joycnt.enable_irq(false);
+        joycnt.acknowledge(ALL_FLAGS);
+        JoystatWrapper::get().set_general_flags(0);
+        PENDING_FLAGS.store(0, Ordering::Release);
+        RECEIVED.clear();
+        RECEIVE_OVERFLOW.store(false, Ordering::Release);
+        Self {
+            _handle: PhantomData,
+            interrupt: None,
+            timeout_millis: DEFAULT_TIMEOUT_MILLIS,
+        }
+    }
+
+    /// Sets how long blocking operations wait before failing with
+    /// `JoybusError::Timeout`.
+    pub fn set_timeout_millis(&mut self, millis: u32) {
+        self.timeout_millis = millis;
+    }
+
+    /// Latches each command from the serial interrupt, so `poll` doesn't have
+    /// to be called before the host's next one.
+    pub fn enable_interrupt(&mut self) {
+        JoycntWrapper::get().enable_irq(true);
+        self.interrupt =
+            Some(unsafe { add_interrupt_handler(Interrupt::Serial, |_| joybus_interrupt()) });
+    }
+    pub fn interrupt_enabled(&self) -> bool {
+        self.interrupt.is_some()
+    }
+
+    /// Pops the next thing the host did, if anything, or reports that some
+    /// of what it wrote was dropped since the last check.
+    ///
+    /// A reset comes out ahead of anything else; everything the host wrote
+    /// comes out in order, ahead of the host reading what we sent.
+    pub fn poll(&mut self) -> Result<Option<JoybusEvent>, JoybusError> {
+        if self.interrupt.is_none() {
+            latch_flags();
+        }
+        if RECEIVE_OVERFLOW.swap(false, Ordering::AcqRel) {
+            return Err(JoybusError::Overflow);
+        }
+        let pending = PENDING_FLAGS.load(Ordering::Acquire);
+        if pending & RESET_FLAG != 0 {
+            PENDING_FLAGS.fetch_and(!RESET_FLAG, Ordering::AcqRel);
+            return Ok(Some(JoybusEvent::Reset));
+        }
+        if let Some(data) = RECEIVED.pop() {
+            return Ok(Some(JoybusEvent::Received(data)));
+        }
+        if pending & SEND_FLAG != 0 {
+            PENDING_FLAGS.fetch_and(!SEND_FLAG, Ordering::AcqRel);
+            return Ok(Some(JoybusEvent::Sent));
+        }
+        Ok(None)
+    }
+
+    /// Waits for the host to do something, for up to the timeout.
+    pub fn wait_event(&mut self) -> Result<JoybusEvent, JoybusError> {
+        let deadline = Deadline::after_millis(self.timeout_millis);
+        loop {
+            if let Some(event) = self.poll()? {
+                return Ok(event);
+            }
+            if deadline.expired() {
+                return Err(JoybusError::Timeout);
+            }
+        }
+    }
+
+    /// Whether the host has read everything we've sent.
+    pub fn can_send(&self) -> bool {
+        !JoystatWrapper::get().send_pending()
+    }
+    /// Puts `data` up for the host's next read command.
+    pub fn send(&mut self, data: u32) -> Result<(), JoybusError> {
+        if !self.can_send() {
+            return Err(JoybusError::SendPending);
+        }
+        JOY_TRANS.write(data);
+        Ok(())
+    }
+    /// Like `send`, but waits for the host to read the last value first.
+    pub fn send_blocking(&mut self, data: u32) -> Result<(), JoybusError> {
+        let deadline = Deadline::after_millis(self.timeout_millis);
+        while !self.can_send() {
+            if deadline.expired() {
+                return Err(JoybusError::Timeout);
+            }
+        }
+        self.send(data)
+    }
+
+    /// The two flags the host sees in our status that are ours to use.
+    pub fn general_flags(&self) -> u8 {
+        JoystatWrapper::get().general_flags()
+    }
+    pub fn set_general_flags(&mut self, flags: u8) {
+        JoystatWrapper::get().set_general_flags(flags)
+    }
+}
+
+impl Drop for JoybusSerial<'_> {
+    fn drop(&mut self) {
+        JoycntWrapper::get().enable_irq(false);
+    }
+}
+
+const RECEIVE_QUEUE_LEN: usize = 16;
+
+/// The resets and reads seen since the last `poll`, as `JOYCNT` flags.
+static PENDING_FLAGS: AtomicU16 = AtomicU16::new(0);
+/// Every value the host wrote that `poll` hasn't handed out yet.
+static RECEIVED: SpscQueue<u32, RECEIVE_QUEUE_LEN> = SpscQueue::new();
+static RECEIVE_OVERFLOW: AtomicBool = AtomicBool::new(false);
+
+/// Moves any new command flags from `JOYCNT` into `PENDING_FLAGS`, and any
+/// new value from the host into `RECEIVED`.
+fn latch_flags() {
+    let joycnt = JoycntWrapper::get();
+    let flags = joycnt.read() & ALL_FLAGS;
+    if flags == 0 {
+        return;
+    }
+    if flags & RECEIVE_FLAG != 0 {
+        // Reading this also clears the receive flag in JOYSTAT.
+        if !RECEIVED.push(JOY_RECV.read()) {
+            RECEIVE_OVERFLOW.store(true, Ordering::Release);
+        }
+    }
+    joycnt.acknowledge(flags);
+    PENDING_FLAGS.fetch_or(flags & !RECEIVE_FLAG, Ordering::AcqRel);
+}
+
+fn joybus_interrupt() {
+    latch_flags();
+}
@@ -0,0 +1,60 @@
+//! Sends a program to linked GBAs with no cartridge in; the handshake lives in
+//! `speglar_core::link::multiboot`, and this supplies the BIOS call it ends
+//! with.
+use core::arch::asm;
+
+pub use speglar_core::link::multiboot::MultibootError;
+use speglar_core::link::multiboot::{self, MultiBootParam, MultibootBios};
+
+use super::clock;
+use super::multiplayer::MultiplayerSerial;
+
+/// The real BIOS, with waits on the real clock.
+struct Bios;
+
+impl MultibootBios for Bios {
+    fn multiboot(&mut self, param: &MultiBootParam) -> u32 {
+        bios_multiboot(param)
+    }
+    fn wait_ticks(&mut self, ticks: u32) {
+        clock::wait_ticks(ticks)
+    }
+}
+
+/// Sends `image`, a multiboot ROM (linked to run from work RAM), to every
+/// child that answers. Returns which players got it.
+///
+/// `serial` needs to be at 115200 baud, and shouldn't have run
+/// `initialize_id`, since the children's BIOS won't answer that.
+pub fn send_multiboot(
+    serial: &mut MultiplayerSerial<'_>,
+    image: &'static [u8],
+) -> Result<[bool; 4], MultibootError> {
+    multiboot::send_multiboot(&mut **serial, &mut Bios, image)
+}
+
+/// Calls BIOS function 25h in multiplayer mode, returning 0 on success.
+fn bios_multiboot(param: &MultiBootParam) -> u32 {
+    let result: u32;
+    unsafe {
+        #[cfg(target_feature = "thumb-mode")]
+        asm!(
+            "swi 0x25",
+            inlateout("r0") param as *const MultiBootParam as u32 => result,
+            inlateout("r1") 1u32 => _,
+            lateout("r2") _,
+            lateout("r3") _,
+            lateout("r12") _,
+        );
+        #[cfg(not(target_feature = "thumb-mode"))]
+        asm!(
+            "swi 0x250000",
+            inlateout("r0") param as *const MultiBootParam as u32 => result,
+            inlateout("r1") 1u32 => _,
+            lateout("r2") _,
+            lateout("r3") _,
+            lateout("r12") _,
+        );
+    }
+    result
+}
@@ -0,0 +1,199 @@
+use core::marker::PhantomData;
+
+use agb::external::portable_atomic::{AtomicBool, AtomicU32, Ordering};
+use agb::interrupt::{add_interrupt_handler, Interrupt, InterruptHandler};
+use speglar_core::link::normal;
+
+use super::clock::Deadline;
+use super::multiplayer::DEFAULT_TIMEOUT_MILLIS;
+use super::*;
+
+pub use speglar_core::link::normal::{ClockSource, ClockSpeed, TransferLength};
+pub type NormalSiocnt = normal::NormalSiocnt<Hardware>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct NormalConfig {
+    pub clock: ClockSource,
+    /// Ignored with an external clock.
+    pub speed: ClockSpeed,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum NormalError {
+    /// There was a transfer already in progress when the new one was requested.
+    AlreadyInProgress,
+    /// A blocking operation didn't finish within the timeout.
+    Timeout,
+}
+
+/// A two-player link that shifts 8 or 32 bits each way per transfer, with one
+/// GBA driving the clock.
+///
+/// The side with the external clock holds SO low while it has a transfer set
+/// up, and the side with the internal clock waits to see that on SI before
+/// starting, so neither side misses a transfer.
+pub struct NormalSerial<'a> {
+    _handle: PhantomData<&'a mut Serial>,
+    interrupt: Option<InterruptHandler>,
+    config: NormalConfig,
+    length: TransferLength,
+    timeout_millis: u32,
+}
+
+impl<'a> NormalSerial<'a> {
+    pub fn new(_handle: &'a mut Serial, config: NormalConfig) -> Self {
+        let mut retvl = Self {
+            _handle: PhantomData,
+            interrupt: None,
+            config,
+            length: TransferLength::Bits8,
+            timeout_millis: DEFAULT_TIMEOUT_MILLIS,
+        };
+        retvl.initialize();
+        retvl
+    }
+
+    fn initialize(&mut self) {
+        // FROM https://rust-console.github.io/gbatek-gbaonly/#sionormalmode:
+        RcntWrapper::get().set_mode(SerialMode::Normal);
+        let siocnt = NormalSiocnt::get();
+        siocnt.write(0);
+        siocnt.set_mode(SerialMode::Normal);
+        siocnt.set_clock(self.config.clock);
+        siocnt.set_speed(self.config.speed);
+        siocnt.set_length(self.length);
+        // High means "not ready" to the other side.
+        siocnt.set_so_inactive(true);
+    }
+
+    pub fn config(&self) -> NormalConfig {
+        self.config
+    }
+    /// Sets how long blocking operations wait before failing with
+    /// `NormalError::Timeout`.
+    pub fn set_timeout_millis(&mut self, millis: u32) {
+        self.timeout_millis = millis;
+    }
+
+    /// Records each transfer's result from the serial interrupt, for
+    /// `take_completed`.
+    pub fn enable_interrupt(&mut self) {
+        NormalSiocnt::get().enable_irq(true);
+        self.interrupt =
+            Some(unsafe { add_interrupt_handler(Interrupt::Serial, |_| completion_interrupt()) });
+    }
+    pub fn interrupt_enabled(&self) -> bool {
+        self.interrupt.is_some()
+    }
+
+    pub fn busy(&self) -> bool {
+        NormalSiocnt::get().busy()
+    }
+
+    /// Sets up a transfer sending `data`, which is truncated to 8 bits for
+    /// 8-bit transfers.
+    ///
+    /// With an internal clock this starts it straight away, so the other side
+    /// should already be ready (see `other_ready`); with an external clock it
+    /// waits for the other side to run the clock.
+    pub fn start_transfer(&mut self, length: TransferLength, data: u32) -> Result<(), NormalError> {
+        let siocnt = NormalSiocnt::get();
+        if siocnt.busy() {
+            return Err(NormalError::AlreadyInProgress);
+        }
+        if length != self.length {
+            self.length = length;
+            siocnt.set_length(length);
+        }
+        match length {
+            TransferLength::Bits8 => SIODATA8.write(data as u8 as u16),
+            TransferLength::Bits32 => SIODATA32.write(data),
+        }
+        TRANSFER_DONE.store(false, Ordering::Release);
+        siocnt.start_transfer();
+        if self.config.clock == ClockSource::External {
+            siocnt.set_so_inactive(false);
+        }
+        Ok(())
+    }
+
+    /// With an internal clock, whether the other side has a transfer set up
+    /// and is waiting on us.
+    pub fn other_ready(&self) -> bool {
+        !NormalSiocnt::get().si_state()
+    }
+
+    /// The data received by the last transfer, once it's done.
+    pub fn finish_transfer(&mut self) -> Option<u32> {
+        let siocnt = NormalSiocnt::get();
+        if siocnt.busy() {
+            return None;
+        }
+        if self.config.clock == ClockSource::External {
+            siocnt.set_so_inactive(true);
+        }
+        Some(read_received(self.length))
+    }
+
+    /// Pops the result of a transfer recorded by the interrupt.
+    pub fn take_completed(&mut self) -> Option<u32> {
+        if !TRANSFER_DONE.swap(false, Ordering::AcqRel) {
+            return None;
+        }
+        if self.config.clock == ClockSource::External {
+            NormalSiocnt::get().set_so_inactive(true);
+        }
+        Some(TRANSFER_DATA.load(Ordering::Acquire))
+    }
+
+    /// Sends `data` and waits for what comes back, for up to the timeout.
+    pub fn transfer(&mut self, length: TransferLength, data: u32) -> Result<u32, NormalError> {
+        let deadline = Deadline::after_millis(self.timeout_millis);
+        if self.config.clock == ClockSource::Internal {
+            while !self.other_ready() {
+                if deadline.expired() {
+                    return Err(NormalError::Timeout);
+                }
+            }
+        }
+        self.start_transfer(length, data)?;
+        loop {
+            if let Some(received) = self.finish_transfer() {
+                TRANSFER_DONE.store(false, Ordering::Release);
+                return Ok(received);
+            }
+            if deadline.expired() {
+                return Err(NormalError::Timeout);
+            }
+        }
+    }
+    pub fn transfer8(&mut self, data: u8) -> Result<u8, NormalError> {
+        self.transfer(TransferLength::Bits8, data as u32)
+            .map(|received| received as u8)
+    }
+    pub fn transfer32(&mut self, data: u32) -> Result<u32, NormalError> {
+        self.transfer(TransferLength::Bits32, data)
+    }
+}
+
+impl Drop for NormalSerial<'_> {
+    fn drop(&mut self) {
+        NormalSiocnt::get().enable_irq(false);
+    }
+}
+
+fn read_received(length: TransferLength) -> u32 {
+    match length {
+        TransferLength::Bits8 => SIODATA8.read() as u8 as u32,
+        TransferLength::Bits32 => SIODATA32.read(),
+    }
+}
+
+static TRANSFER_DONE: AtomicBool = AtomicBool::new(false);
+static TRANSFER_DATA: AtomicU32 = AtomicU32::new(0);
+
+fn completion_interrupt() {
+    let length = NormalSiocnt::get().length();
+    TRANSFER_DATA.store(read_received(length), Ordering::Release);
+    TRANSFER_DONE.store(true, Ordering::Release);
+}
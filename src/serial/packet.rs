@@ -0,0 +1,8 @@
+//! Reliable byte packets over the multiplayer link; the transport itself is
+//! `speglar_core::packet::PacketLink`.
+use speglar_core::packet;
+
+use super::Hardware;
+
+#[allow(dead_code)]
+pub type PacketLink<'a> = packet::PacketLink<'a, 'static, Hardware>;
@@ -0,0 +1,223 @@
+use core::marker::PhantomData;
+
+use agb::external::critical_section;
+use agb::external::portable_atomic::{AtomicBool, Ordering};
+use agb::interrupt::{add_interrupt_handler, Interrupt, InterruptHandler};
+use speglar_core::link::spsc::SpscQueue;
+use speglar_core::link::uart;
+pub use speglar_core::link::uart::{DataLength, Parity};
+
+use super::clock::Deadline;
+use super::multiplayer::DEFAULT_TIMEOUT_MILLIS;
+use super::*;
+
+pub type UartSiocnt = uart::UartSiocnt<Hardware>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct UartConfig {
+    pub rate: BaudRate,
+    pub parity: Parity,
+    pub data_length: DataLength,
+    /// Only send while the other side holds SC low.
+    pub cts: bool,
+    /// Use the hardware's 4-byte FIFOs rather than a single byte each way.
+    pub fifo: bool,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum UartError {
+    /// The "error" flag was tripped in the SIOCNT register, from a bad stop
+    /// bit, a parity mismatch, or the hardware receive buffer overflowing.
+    FailedOkayCheck,
+    /// Bytes came in faster than they were read and some were dropped.
+    Overflow,
+    /// A blocking operation didn't finish within the timeout.
+    Timeout,
+}
+
+/// Talks to anything speaking plain serial, like a PC over a USB-serial
+/// adapter.
+///
+/// Bytes go through software queues on both sides, which get moved to and
+/// from the hardware by the serial interrupt (if enabled) or by `poll`.
+pub struct UartSerial<'a> {
+    _handle: PhantomData<&'a mut Serial>,
+    interrupt: Option<InterruptHandler>,
+    config: UartConfig,
+    timeout_millis: u32,
+}
+
+impl<'a> UartSerial<'a> {
+    pub fn new(_handle: &'a mut Serial, config: UartConfig) -> Result<Self, UartError> {
+        let mut retvl = Self {
+            _handle: PhantomData,
+            interrupt: None,
+            config,
+            timeout_millis: DEFAULT_TIMEOUT_MILLIS,
+        };
+        RECEIVE_QUEUE.clear();
+        SEND_QUEUE.clear();
+        UART_ERROR.store(false, Ordering::Release);
+        UART_OVERFLOW.store(false, Ordering::Release);
+        retvl.initialize()?;
+        Ok(retvl)
+    }
+
+    fn initialize(&mut self) -> Result<(), UartError> {
+        // FROM https://rust-console.github.io/gbatek-gbaonly/#siouartmode:
+        RcntWrapper::get().set_mode(SerialMode::Uart);
+        let siocnt = UartSiocnt::get();
+        // Everything off while we set it up, which also resets the FIFOs.
+        siocnt.write(0);
+        siocnt.set_mode(SerialMode::Uart);
+        siocnt.set_baud_rate(self.config.rate);
+        siocnt.set_cts(self.config.cts);
+        siocnt.set_parity(self.config.parity);
+        siocnt.set_data_length(self.config.data_length);
+        siocnt.enable_fifo(self.config.fifo);
+        siocnt.enable_send(true);
+        siocnt.enable_receive(true);
+        if siocnt.error_flag() {
+            return Err(UartError::FailedOkayCheck);
+        }
+        Ok(())
+    }
+
+    pub fn config(&self) -> UartConfig {
+        self.config
+    }
+    /// Sets how long blocking operations wait before failing with
+    /// `UartError::Timeout`.
+    pub fn set_timeout_millis(&mut self, millis: u32) {
+        self.timeout_millis = millis;
+    }
+
+    /// Moves bytes to and from the hardware from the serial interrupt, so
+    /// nothing gets missed between calls to `poll`.
+    pub fn enable_interrupt(&mut self) {
+        UartSiocnt::get().enable_irq(true);
+        self.interrupt =
+            Some(unsafe { add_interrupt_handler(Interrupt::Serial, |_| pump_hardware()) });
+    }
+    pub fn interrupt_enabled(&self) -> bool {
+        self.interrupt.is_some()
+    }
+
+    /// Moves bytes to and from the hardware, and reports any errors since the
+    /// last check.
+    pub fn poll(&mut self) -> Result<(), UartError> {
+        critical_section::with(|_| pump_hardware());
+        if UART_ERROR.swap(false, Ordering::AcqRel) {
+            return Err(UartError::FailedOkayCheck);
+        }
+        if UART_OVERFLOW.swap(false, Ordering::AcqRel) {
+            return Err(UartError::Overflow);
+        }
+        Ok(())
+    }
+
+    /// Queues `byte` to be sent, returning `false` if the queue is full.
+    pub fn try_write_byte(&mut self, byte: u8) -> bool {
+        let pushed = SEND_QUEUE.push(byte);
+        critical_section::with(|_| pump_hardware());
+        pushed
+    }
+    /// Queues all of `data` to be sent, waiting for room if needed.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), UartError> {
+        let deadline = Deadline::after_millis(self.timeout_millis);
+        for byte in data {
+            while !self.try_write_byte(*byte) {
+                self.poll()?;
+                if deadline.expired() {
+                    return Err(UartError::Timeout);
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Waits until everything queued has been handed to the hardware.
+    pub fn flush(&mut self) -> Result<(), UartError> {
+        let deadline = Deadline::after_millis(self.timeout_millis);
+        while !SEND_QUEUE.is_empty() {
+            self.poll()?;
+            if deadline.expired() {
+                return Err(UartError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pops the oldest received byte, if there is one.
+    pub fn read_byte(&mut self) -> Result<Option<u8>, UartError> {
+        self.poll()?;
+        Ok(RECEIVE_QUEUE.pop())
+    }
+    /// Reads as many bytes as are waiting, up to `buf.len()`, returning how
+    /// many were read.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, UartError> {
+        self.poll()?;
+        let mut count = 0;
+        for slot in buf.iter_mut() {
+            let Some(byte) = RECEIVE_QUEUE.pop() else {
+                break;
+            };
+            *slot = byte;
+            count += 1;
+        }
+        Ok(count)
+    }
+    /// Waits until `buf` has been filled.
+    pub fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), UartError> {
+        let deadline = Deadline::after_millis(self.timeout_millis);
+        let mut filled = 0;
+        while filled < buf.len() {
+            filled += self.read(&mut buf[filled..])?;
+            if filled < buf.len() && deadline.expired() {
+                return Err(UartError::Timeout);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UartSerial<'_> {
+    fn drop(&mut self) {
+        let siocnt = UartSiocnt::get();
+        siocnt.enable_irq(false);
+        siocnt.enable_send(false);
+        siocnt.enable_receive(false);
+    }
+}
+
+const UART_QUEUE_LEN: usize = 64;
+
+/// Bytes on their way between the interrupt and `UartSerial`.
+type ByteQueue = SpscQueue<u8, UART_QUEUE_LEN>;
+
+static RECEIVE_QUEUE: ByteQueue = ByteQueue::new();
+static SEND_QUEUE: ByteQueue = ByteQueue::new();
+static UART_ERROR: AtomicBool = AtomicBool::new(false);
+static UART_OVERFLOW: AtomicBool = AtomicBool::new(false);
+
+/// Drains the hardware's receive side into `RECEIVE_QUEUE` and fills its send
+/// side from `SEND_QUEUE`. Only ever runs with interrupts off, so the queues
+/// only ever have one reader and one writer at a time.
+fn pump_hardware() {
+    let siocnt = UartSiocnt::get();
+    // Reading SIOCNT clears the error flag, so grab it all at once.
+    let status = siocnt.read();
+    if read_bit(status, 6) {
+        UART_ERROR.store(true, Ordering::Release);
+    }
+    while !siocnt.receive_empty() {
+        if !RECEIVE_QUEUE.push(SIODATA8.read() as u8) {
+            UART_OVERFLOW.store(true, Ordering::Release);
+        }
+    }
+    while !siocnt.send_full() {
+        let Some(byte) = SEND_QUEUE.pop() else {
+            break;
+        };
+        SIODATA8.write(byte as u16);
+    }
+}